@@ -1,8 +1,12 @@
 use std::{
-    fs,
-    io::{self, Read, Seek}, time::Duration, thread,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt, fs,
+    io::{self, Read, Seek}, time::{Duration, Instant}, thread,
 };
 
+use flate2::read::ZlibDecoder;
+use rayon::prelude::*;
+
 #[derive(Clone, Copy, Debug)]
 pub enum MapLumpIndex {
     Things = 1,
@@ -17,17 +21,85 @@ pub enum MapLumpIndex {
     BlockMap = 10,
 }
 
+impl MapLumpIndex {
+    pub const ALL: [MapLumpIndex; 10] = [
+        MapLumpIndex::Things,
+        MapLumpIndex::LineDefs,
+        MapLumpIndex::SideDefs,
+        MapLumpIndex::Vertexes,
+        MapLumpIndex::Segs,
+        MapLumpIndex::SSectors,
+        MapLumpIndex::Nodes,
+        MapLumpIndex::Sectors,
+        MapLumpIndex::Reject,
+        MapLumpIndex::BlockMap,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MapLumpIndex::Things => "THINGS",
+            MapLumpIndex::LineDefs => "LINEDEFS",
+            MapLumpIndex::SideDefs => "SIDEDEFS",
+            MapLumpIndex::Vertexes => "VERTEXES",
+            MapLumpIndex::Segs => "SEGS",
+            MapLumpIndex::SSectors => "SSECTORS",
+            MapLumpIndex::Nodes => "NODES",
+            MapLumpIndex::Sectors => "SECTORS",
+            MapLumpIndex::Reject => "REJECT",
+            MapLumpIndex::BlockMap => "BLOCKMAP",
+        }
+    }
+
+    // Bytes per record in the vanilla format, a valid lump is a whole
+    // number of them. REJECT is a bit matrix and BLOCKMAP a list of words.
+    pub fn record_size(&self) -> usize {
+        match self {
+            MapLumpIndex::Things => Thing::SIZE,
+            MapLumpIndex::LineDefs => LineDef::SIZE,
+            MapLumpIndex::SideDefs => SideDef::SIZE,
+            MapLumpIndex::Vertexes => Vertex::SIZE,
+            MapLumpIndex::Segs => Seg::SIZE,
+            MapLumpIndex::SSectors => SubSector::SIZE,
+            MapLumpIndex::Nodes => Node::SIZE,
+            MapLumpIndex::Sectors => Sector::SIZE,
+            MapLumpIndex::Reject => u8::SIZE,
+            MapLumpIndex::BlockMap => 2,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum LineDefFlags {
-    Blocking = 0,
-    BlockMonsters = 1,
-    TwoSided = 2,
-    DontPegTop = 4,
-    DontPegBottom = 8,
-    Secret = 16,
-    SoundBlock = 32,
-    DontDraw = 64,
-    Draw = 128,
+    Blocking = 1,
+    BlockMonsters = 2,
+    TwoSided = 4,
+    DontPegTop = 8,
+    DontPegBottom = 16,
+    Secret = 32,
+    SoundBlock = 64,
+    DontDraw = 128,
+    Draw = 256,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ThingFlags {
+    // Skills 1 and 2.
+    Easy = 1,
+    Medium = 2,
+    // Skills 4 and 5.
+    Hard = 4,
+    // Ambush, the monster waits until it sees the player.
+    Deaf = 8,
+    MultiplayerOnly = 16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Skill {
+    Baby = 1,
+    Easy = 2,
+    Medium = 3,
+    Hard = 4,
+    Nightmare = 5,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -35,11 +107,336 @@ pub enum LineDefFlags {
 pub struct Thing {
     pub x: i16,
     pub y: i16,
+    // Degrees, counter-clockwise from east.
     pub angle: i16,
     pub t_type: i16,
     pub flags: i16,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThingCategory {
+    PlayerStart,
+    Monster,
+    Item,
+    Other,
+}
+
+impl Thing {
+    pub fn category(&self) -> ThingCategory {
+        match self.t_type {
+            // Player 1-4 and deathmatch starts.
+            1..=4 | 11 => ThingCategory::PlayerStart,
+
+            7 | 9 | 16 | 58 | 64..=69 | 71 | 72 | 84 | 3001..=3006 => ThingCategory::Monster,
+
+            // Weapons, ammo, powerups and keys.
+            5 | 6 | 8 | 13 | 17 | 38..=40 | 82 | 83 | 2001..=2008 | 2010..=2015
+            | 2018 | 2019 | 2022..=2026 | 2045..=2049 => ThingCategory::Item,
+
+            _ => ThingCategory::Other,
+        }
+    }
+
+    pub fn has_flag(&self, flag: ThingFlags) -> bool {
+        self.flags & flag as i16 != 0
+    }
+
+    // Whether the thing is spawned when starting a game on `skill`.
+    pub fn appears_on(&self, skill: Skill, multiplayer: bool) -> bool {
+        if self.has_flag(ThingFlags::MultiplayerOnly) && !multiplayer {
+            return false;
+        }
+
+        self.has_flag(match skill {
+            Skill::Baby | Skill::Easy => ThingFlags::Easy,
+            Skill::Medium => ThingFlags::Medium,
+            Skill::Hard | Skill::Nightmare => ThingFlags::Hard,
+        })
+    }
+}
+
+// Sprite prefix and spawn frame of DOOM's actors, `None` for things that
+// aren't drawn like deathmatch starts and teleport destinations.
+pub fn thing_sprite(t_type: i16) -> Option<(&'static str, char)> {
+    let sprite = match t_type {
+        1..=4 => ("PLAY", 'A'),
+        10 | 12 => ("PLAY", 'W'),
+        15 => ("PLAY", 'N'),
+
+        // Monsters.
+        3004 => ("POSS", 'A'),
+        9 => ("SPOS", 'A'),
+        65 => ("CPOS", 'A'),
+        3001 => ("TROO", 'A'),
+        3002 | 58 => ("SARG", 'A'),
+        3006 => ("SKUL", 'A'),
+        3005 => ("HEAD", 'A'),
+        69 => ("BOS2", 'A'),
+        3003 => ("BOSS", 'A'),
+        68 => ("BSPI", 'A'),
+        71 => ("PAIN", 'A'),
+        66 => ("SKEL", 'A'),
+        67 => ("FATT", 'A'),
+        64 => ("VILE", 'A'),
+        16 => ("CYBR", 'A'),
+        7 => ("SPID", 'A'),
+        84 => ("SSWV", 'A'),
+        72 => ("KEEN", 'A'),
+        88 => ("BBRN", 'A'),
+
+        // Corpses.
+        18 => ("POSS", 'L'),
+        19 => ("SPOS", 'L'),
+        20 => ("TROO", 'M'),
+        21 => ("SARG", 'N'),
+        22 => ("HEAD", 'L'),
+        23 => ("SKUL", 'K'),
+
+        // Weapons.
+        2001 => ("SHOT", 'A'),
+        82 => ("SGN2", 'A'),
+        2002 => ("MGUN", 'A'),
+        2003 => ("LAUN", 'A'),
+        2004 => ("PLAS", 'A'),
+        2005 => ("CSAW", 'A'),
+        2006 => ("BFUG", 'A'),
+
+        // Ammo.
+        2007 => ("CLIP", 'A'),
+        2048 => ("AMMO", 'A'),
+        2008 => ("SHEL", 'A'),
+        2049 => ("SBOX", 'A'),
+        2010 => ("ROCK", 'A'),
+        2046 => ("BROK", 'A'),
+        2047 => ("CELL", 'A'),
+        17 => ("CELP", 'A'),
+        8 => ("BPAK", 'A'),
+
+        // Health, armor and powerups.
+        2011 => ("STIM", 'A'),
+        2012 => ("MEDI", 'A'),
+        2014 => ("BON1", 'A'),
+        2015 => ("BON2", 'A'),
+        2018 => ("ARM1", 'A'),
+        2019 => ("ARM2", 'A'),
+        83 => ("MEGA", 'A'),
+        2013 => ("SOUL", 'A'),
+        2022 => ("PINV", 'A'),
+        2023 => ("PSTR", 'A'),
+        2024 => ("PINS", 'A'),
+        2025 => ("SUIT", 'A'),
+        2026 => ("PMAP", 'A'),
+        2045 => ("PVIS", 'A'),
+
+        // Keys.
+        5 => ("BKEY", 'A'),
+        6 => ("YKEY", 'A'),
+        13 => ("RKEY", 'A'),
+        40 => ("BSKU", 'A'),
+        39 => ("YSKU", 'A'),
+        38 => ("RSKU", 'A'),
+
+        // Obstacles and decorations.
+        2035 => ("BAR1", 'A'),
+        48 => ("ELEC", 'A'),
+        2028 => ("COLU", 'A'),
+        30 => ("COL1", 'A'),
+        31 => ("COL2", 'A'),
+        32 => ("COL3", 'A'),
+        33 => ("COL4", 'A'),
+        36 => ("COL5", 'A'),
+        37 => ("COL6", 'A'),
+        41 => ("CEYE", 'A'),
+        42 => ("FSKU", 'A'),
+        43 => ("TRE1", 'A'),
+        44 => ("TBLU", 'A'),
+        45 => ("TGRN", 'A'),
+        46 => ("TRED", 'A'),
+        54 => ("TRE2", 'A'),
+        55 => ("SMBT", 'A'),
+        56 => ("SMGT", 'A'),
+        57 => ("SMRT", 'A'),
+        47 => ("SMIT", 'A'),
+        70 => ("FCAN", 'A'),
+        34 => ("CAND", 'A'),
+        35 => ("CBRA", 'A'),
+        85 => ("TLMP", 'A'),
+        86 => ("TLP2", 'A'),
+        24 => ("POL5", 'A'),
+        25 => ("POL1", 'A'),
+        26 => ("POL6", 'A'),
+        27 => ("POL4", 'A'),
+        28 => ("POL2", 'A'),
+        29 => ("POL3", 'A'),
+        49 | 63 => ("GOR1", 'A'),
+        50 | 59 => ("GOR2", 'A'),
+        51 | 61 => ("GOR3", 'A'),
+        52 | 60 => ("GOR4", 'A'),
+        53 | 62 => ("GOR5", 'A'),
+        73 => ("HDB1", 'A'),
+        74 => ("HDB2", 'A'),
+        75 => ("HDB3", 'A'),
+        76 => ("HDB4", 'A'),
+        77 => ("HDB5", 'A'),
+        78 => ("HDB6", 'A'),
+        79 => ("POB1", 'A'),
+        80 => ("POB2", 'A'),
+        81 => ("BRS1", 'A'),
+
+        _ => return None,
+    };
+
+    Some(sprite)
+}
+
+// Display names of DOOM's doomednums, e.g. for reports and exports.
+pub fn thing_name(t_type: i16) -> Option<&'static str> {
+    let name = match t_type {
+        // Player starts and markers.
+        1 => "Player 1 start",
+        2 => "Player 2 start",
+        3 => "Player 3 start",
+        4 => "Player 4 start",
+        11 => "Deathmatch start",
+        14 => "Teleport destination",
+        87 => "Spawn spot",
+        89 => "Spawn shooter",
+
+        // Monsters.
+        3004 => "Zombieman",
+        9 => "Shotgun guy",
+        65 => "Heavy weapon dude",
+        3001 => "Imp",
+        3002 => "Demon",
+        58 => "Spectre",
+        3006 => "Lost soul",
+        3005 => "Cacodemon",
+        69 => "Hell knight",
+        3003 => "Baron of Hell",
+        68 => "Arachnotron",
+        71 => "Pain elemental",
+        66 => "Revenant",
+        67 => "Mancubus",
+        64 => "Arch-vile",
+        16 => "Cyberdemon",
+        7 => "Spider Mastermind",
+        84 => "Wolfenstein SS",
+        72 => "Commander Keen",
+        88 => "Boss brain",
+
+        // Corpses.
+        10 => "Bloody mess",
+        12 => "Bloody mess 2",
+        15 => "Dead player",
+        18 => "Dead zombieman",
+        19 => "Dead shotgun guy",
+        20 => "Dead imp",
+        21 => "Dead demon",
+        22 => "Dead cacodemon",
+        23 => "Dead lost soul",
+
+        // Weapons.
+        2001 => "Shotgun",
+        82 => "Super shotgun",
+        2002 => "Chaingun",
+        2003 => "Rocket launcher",
+        2004 => "Plasma gun",
+        2005 => "Chainsaw",
+        2006 => "BFG 9000",
+
+        // Ammo.
+        2007 => "Clip",
+        2048 => "Box of bullets",
+        2008 => "Shells",
+        2049 => "Box of shells",
+        2010 => "Rocket",
+        2046 => "Box of rockets",
+        2047 => "Cell charge",
+        17 => "Cell charge pack",
+        8 => "Backpack",
+
+        // Health, armor and powerups.
+        2011 => "Stimpack",
+        2012 => "Medikit",
+        2014 => "Health bonus",
+        2015 => "Armor bonus",
+        2018 => "Armor",
+        2019 => "Megaarmor",
+        83 => "Megasphere",
+        2013 => "Soulsphere",
+        2022 => "Invulnerability",
+        2023 => "Berserk",
+        2024 => "Partial invisibility",
+        2025 => "Radiation suit",
+        2026 => "Computer area map",
+        2045 => "Light amplification visor",
+
+        // Keys.
+        5 => "Blue keycard",
+        6 => "Yellow keycard",
+        13 => "Red keycard",
+        40 => "Blue skull key",
+        39 => "Yellow skull key",
+        38 => "Red skull key",
+
+        // Obstacles and decorations.
+        2035 => "Exploding barrel",
+        48 => "Tall techno column",
+        2028 => "Floor lamp",
+        30 => "Tall green pillar",
+        31 => "Short green pillar",
+        32 => "Tall red pillar",
+        33 => "Short red pillar",
+        36 => "Short green pillar with heart",
+        37 => "Short red pillar with skull",
+        41 => "Evil eye",
+        42 => "Floating skull",
+        43 => "Burnt tree",
+        44 => "Tall blue firestick",
+        45 => "Tall green firestick",
+        46 => "Tall red firestick",
+        54 => "Brown tree",
+        55 => "Short blue firestick",
+        56 => "Short green firestick",
+        57 => "Short red firestick",
+        47 => "Stalagmite",
+        70 => "Burning barrel",
+        34 => "Candle",
+        35 => "Candelabra",
+        85 => "Tall techno floor lamp",
+        86 => "Short techno floor lamp",
+        24 => "Pool of blood and flesh",
+        25 => "Impaled human",
+        26 => "Twitching impaled human",
+        27 => "Skull on a pole",
+        28 => "Five skulls shish kebab",
+        29 => "Pile of skulls and candles",
+        49 => "Hanging victim - twitching",
+        63 => "Hanging victim - twitching - not blocking",
+        50 => "Hanging victim - arms out",
+        59 => "Hanging victim - arms out - not blocking",
+        51 => "Hanging victim - one-legged",
+        61 => "Hanging victim - one-legged - not blocking",
+        52 => "Hanging pair of legs",
+        60 => "Hanging pair of legs - not blocking",
+        53 => "Hanging leg",
+        62 => "Hanging leg - not blocking",
+        73 => "Hanging victim - guts removed",
+        74 => "Hanging victim - guts and brain removed",
+        75 => "Hanging torso - looking down",
+        76 => "Hanging torso - open skull",
+        77 => "Hanging torso - looking up",
+        78 => "Hanging torso - brain removed",
+        79 => "Pool of blood",
+        80 => "Pool of blood 2",
+        81 => "Pool of brains",
+
+        _ => return None,
+    };
+
+    Some(name)
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct LineDef {
@@ -52,6 +449,127 @@ pub struct LineDef {
     pub left_sidedef: i16,
 }
 
+impl LineDef {
+    pub fn is_one_sided(&self) -> bool {
+        self.left_sidedef == -1
+    }
+
+    pub fn has_flag(&self, flag: LineDefFlags) -> bool {
+        self.flags & flag as i16 != 0
+    }
+
+    pub fn is_blocking(&self) -> bool {
+        self.is_one_sided() || self.has_flag(LineDefFlags::Blocking)
+    }
+}
+
+// Boom's generalized specials, one range of `special_type` per kind with
+// the parameters packed into the low bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeneralizedKind {
+    Crusher,
+    Stairs,
+    Lift,
+    LockedDoor,
+    Door,
+    Ceiling,
+    Floor,
+}
+
+// W is walking over the line, S using it, G shooting it and D using a door
+// directly; 1 only triggers once, R repeatedly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeneralizedTrigger {
+    W1,
+    WR,
+    S1,
+    SR,
+    G1,
+    GR,
+    D1,
+    DR,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeneralizedSpeed {
+    Slow,
+    Normal,
+    Fast,
+    Turbo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GeneralizedSpecial {
+    pub kind: GeneralizedKind,
+    pub trigger: GeneralizedTrigger,
+    pub speed: GeneralizedSpeed,
+    // Seconds doors and lifts wait before going back.
+    pub delay: Option<u32>,
+    // Whether monsters can trigger it too.
+    pub monsters: bool,
+    // The kind's own bits, above the trigger and speed, shifted down to 0.
+    pub flags: u16,
+}
+
+impl LineDef {
+    // Start of each generalized range, the last goes up to 0x7FFF.
+    const GENERALIZED_RANGES: [(u16, GeneralizedKind); 7] = [
+        (0x2F80, GeneralizedKind::Crusher),
+        (0x3000, GeneralizedKind::Stairs),
+        (0x3400, GeneralizedKind::Lift),
+        (0x3800, GeneralizedKind::LockedDoor),
+        (0x3C00, GeneralizedKind::Door),
+        (0x4000, GeneralizedKind::Ceiling),
+        (0x6000, GeneralizedKind::Floor),
+    ];
+
+    // None for vanilla specials.
+    pub fn generalized(&self) -> Option<GeneralizedSpecial> {
+        let special = self.special_type as u16;
+
+        let &(base, kind) = Self::GENERALIZED_RANGES
+            .iter()
+            .rev()
+            .find(|(base, _)| special >= *base && special <= 0x7FFF)?;
+
+        let trigger = [
+            GeneralizedTrigger::W1,
+            GeneralizedTrigger::WR,
+            GeneralizedTrigger::S1,
+            GeneralizedTrigger::SR,
+            GeneralizedTrigger::G1,
+            GeneralizedTrigger::GR,
+            GeneralizedTrigger::D1,
+            GeneralizedTrigger::DR,
+        ][(special & 7) as usize];
+
+        let speed = [
+            GeneralizedSpeed::Slow,
+            GeneralizedSpeed::Normal,
+            GeneralizedSpeed::Fast,
+            GeneralizedSpeed::Turbo,
+        ][(special >> 3 & 3) as usize];
+
+        let flags = (special - base) >> 5;
+
+        let delay = match kind {
+            GeneralizedKind::Door => Some([1, 4, 9, 30][(flags >> 3 & 3) as usize]),
+            GeneralizedKind::Lift => Some([1, 3, 5, 10][(flags >> 1 & 3) as usize]),
+            _ => None,
+        };
+
+        let monsters = match kind {
+            GeneralizedKind::Door => flags >> 2 & 1 != 0,
+            GeneralizedKind::LockedDoor => false,
+            // Only for floors and ceilings that don't change textures.
+            GeneralizedKind::Floor | GeneralizedKind::Ceiling => flags & 0x61 == 1,
+            _ => flags & 1 != 0,
+        };
+
+        Some(GeneralizedSpecial { kind, trigger, speed, delay, monsters, flags })
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct SideDef {
@@ -65,15 +583,44 @@ pub struct SideDef {
 
 impl SideDef {
     pub fn upper_texture(&self) -> String {
-        WAD::slice_to_string(&self.upper_texture)
+        WAD::lump_name(&self.upper_texture)
     }
 
     pub fn lower_texture(&self) -> String {
-        WAD::slice_to_string(&self.lower_texture)
+        WAD::lump_name(&self.lower_texture)
     }
 
     pub fn middle_texture(&self) -> String {
-        WAD::slice_to_string(&self.middle_texture)
+        WAD::lump_name(&self.middle_texture)
+    }
+
+    // "-" means there's no texture, some editors leave the name empty instead.
+    fn is_texture(name: &[u8; 8]) -> bool {
+        name[0] != 0 && WAD::lump_name(name) != "-"
+    }
+
+    pub fn has_upper(&self) -> bool {
+        Self::is_texture(&self.upper_texture)
+    }
+
+    pub fn has_lower(&self) -> bool {
+        Self::is_texture(&self.lower_texture)
+    }
+
+    pub fn has_middle(&self) -> bool {
+        Self::is_texture(&self.middle_texture)
+    }
+
+    // The names of the textures that are there, upper, lower then middle.
+    pub fn textures(&self) -> Vec<String> {
+        [
+            (self.has_upper(), self.upper_texture()),
+            (self.has_lower(), self.lower_texture()),
+            (self.has_middle(), self.middle_texture()),
+        ]
+        .into_iter()
+        .filter_map(|(present, name)| present.then_some(name))
+        .collect()
     }
 }
 
@@ -87,19 +634,54 @@ pub struct Vertex {
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct Seg {
-    pub start_vertex: i16,
-    pub end_vertex: i16,
+    // The indexes are 32-bit like in extended nodes, vanilla lumps store
+    // them in 16 bits.
+    pub start_vertex: u32,
+    pub end_vertex: u32,
+    // Binary angle, see `bam_to_degrees`.
     pub angle: i16,
-    pub linedef: i16,
+    // -1 for minisegs.
+    pub linedef: i32,
     pub direction: i16,
+    // Distance from the start of the linedef's side to the seg's start.
     pub offset: i16,
 }
 
+impl Seg {
+    // Node builders add segs along partition lines that aren't part of any
+    // linedef, they only close off subsectors.
+    pub fn is_miniseg(&self) -> bool {
+        self.linedef < 0
+    }
+
+    // Texture column at the seg's start: segs of a split linedef continue
+    // its texture where the previous one left off.
+    pub fn texture_offset(&self, sidedef: &SideDef) -> i16 {
+        sidedef.x_offset.wrapping_add(self.offset)
+    }
+
+    // The column of a `texture_width` wide texture drawn `distance` units
+    // along the seg, wrapping around like DOOM's power of two widths.
+    pub fn texture_column(&self, sidedef: &SideDef, distance: f32, texture_width: u16) -> u16 {
+        let column = self.texture_offset(sidedef) as f32 + distance;
+
+        (column.floor() as i32).rem_euclid(texture_width.max(1) as i32) as u16
+    }
+
+    // 16-bit linedef index, 0xFFFF for minisegs.
+    fn read_linedef(reader: &mut LumpReader) -> io::Result<i32> {
+        match reader.read_u16()? {
+            0xFFFF => Ok(-1),
+            line => Ok(line as i32),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct SubSector {
-    pub num_segs: i16,
-    pub first_seg: i16,
+    pub num_segs: u32,
+    pub first_seg: u32,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -111,8 +693,49 @@ pub struct Node {
     pub dy_partition: i16,
     pub front_bbox: [i16; 4],
     pub back_bbox: [i16; 4],
-    pub front_child: i16, // front
-    pub back_child: i16, // back
+    // Subsectors have `Map::SUB_SECTOR_FLAG` set.
+    pub front_child: u32, // front
+    pub back_child: u32, // back
+}
+
+// A node's bounding box, in map units with Y pointing up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub top: i16,
+    pub bottom: i16,
+    pub left: i16,
+    pub right: i16,
+}
+
+impl Rect {
+    // Bounding boxes are stored as top, bottom, left, right.
+    pub fn from_bbox(bbox: [i16; 4]) -> Self {
+        Self { top: bbox[0], bottom: bbox[1], left: bbox[2], right: bbox[3] }
+    }
+
+    // Edges are inside.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        (self.left as f32..=self.right as f32).contains(&x)
+            && (self.bottom as f32..=self.top as f32).contains(&y)
+    }
+
+    pub fn width(&self) -> i32 {
+        self.right as i32 - self.left as i32
+    }
+
+    pub fn height(&self) -> i32 {
+        self.top as i32 - self.bottom as i32
+    }
+}
+
+impl Node {
+    pub fn front_rect(&self) -> Rect {
+        Rect::from_bbox(self.front_bbox)
+    }
+
+    pub fn back_rect(&self) -> Rect {
+        Rect::from_bbox(self.back_bbox)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -128,12 +751,87 @@ pub struct Sector {
 }
 
 impl Sector {
+    pub const SKY_TEXTURE: &'static str = "F_SKY1";
+
     pub fn floor_texture(&self) -> String {
-        WAD::slice_to_string(&self.floor_texture)
+        WAD::lump_name(&self.floor_texture)
     }
 
     pub fn ceiling_texture(&self) -> String {
-        WAD::slice_to_string(&self.ceiling_texture)
+        WAD::lump_name(&self.ceiling_texture)
+    }
+
+    // Sky ceilings show the sky texture instead of a flat.
+    pub fn is_sky(&self) -> bool {
+        self.ceiling_texture() == Self::SKY_TEXTURE
+    }
+
+    pub fn special(&self) -> SectorSpecial {
+        match self.special_type {
+            0 => SectorSpecial::Normal,
+            1 => SectorSpecial::LightBlinkRandom,
+            2 => SectorSpecial::LightBlinkHalfSecond,
+            3 => SectorSpecial::LightBlinkSecond,
+            4 => SectorSpecial::DamageStrobe,
+            5 => SectorSpecial::DamageHellslime,
+            7 => SectorSpecial::DamageNukage,
+            8 => SectorSpecial::LightGlow,
+            9 => SectorSpecial::Secret,
+            10 => SectorSpecial::DoorCloseIn30,
+            11 => SectorSpecial::DamageEndLevel,
+            12 => SectorSpecial::LightSyncSecond,
+            13 => SectorSpecial::LightSyncHalfSecond,
+            14 => SectorSpecial::DoorRaiseIn300,
+            16 => SectorSpecial::DamageSuperHellslime,
+            17 => SectorSpecial::LightFlicker,
+            other => SectorSpecial::Unknown(other),
+        }
+    }
+
+    pub fn is_damaging(&self) -> bool {
+        self.special().damage() > 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectorSpecial {
+    Normal,
+    LightBlinkRandom,
+    LightBlinkHalfSecond,
+    LightBlinkSecond,
+    // 20% damage and blinking every half second.
+    DamageStrobe,
+    // 10% damage.
+    DamageHellslime,
+    // 5% damage.
+    DamageNukage,
+    LightGlow,
+    Secret,
+    // The sector's door closes 30 seconds after the level starts.
+    DoorCloseIn30,
+    // 20% damage, the level ends when the player's health drops below 11%.
+    DamageEndLevel,
+    LightSyncSecond,
+    LightSyncHalfSecond,
+    // The sector's door opens 5 minutes after the level starts.
+    DoorRaiseIn300,
+    // 20% damage.
+    DamageSuperHellslime,
+    LightFlicker,
+    Unknown(i16),
+}
+
+impl SectorSpecial {
+    // Health lost every 32 tics without a radiation suit.
+    pub fn damage(&self) -> i16 {
+        match self {
+            SectorSpecial::DamageNukage => 5,
+            SectorSpecial::DamageHellslime => 10,
+            SectorSpecial::DamageStrobe
+            | SectorSpecial::DamageEndLevel
+            | SectorSpecial::DamageSuperHellslime => 20,
+            _ => 0,
+        }
     }
 }
 
@@ -164,7 +862,7 @@ pub struct Directory {
 
 impl Directory {
     pub fn name(&self) -> String {
-        WAD::slice_to_string(&self.name)
+        WAD::lump_name(&self.name)
     }
 }
 
@@ -193,7 +891,51 @@ impl Header {
 }
 
 #[derive(Debug)]
-pub struct WAD {
+pub enum WadError {
+    Io(io::Error),
+    LumpNotFound(String),
+    InvalidLump(String),
+    NoMapLoaded,
+    // Data that runs past the end of the file, the directory included:
+    // `expected` bytes were needed, only `got` were left.
+    TruncatedLump { name: String, expected: usize, got: usize },
+    MapNotFound(String),
+    // The WAD has no lumps at all, or its directory was never read.
+    DirectoryNotLoaded,
+    // Reading needs the WAD's file, but it was read from memory.
+    NoPath,
+}
+
+impl fmt::Display for WadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WadError::Io(err) => write!(f, "{}", err),
+            WadError::LumpNotFound(name) => write!(f, "Lump {} not found.", name),
+            WadError::InvalidLump(name) => write!(f, "Lump {} is malformed.", name),
+            WadError::NoMapLoaded => write!(f, "No map has been loaded."),
+            WadError::TruncatedLump { name, expected, got } => write!(
+                f,
+                "Lump {} exceeds the end of the file, {} of {} bytes read.",
+                name, got, expected,
+            ),
+            WadError::MapNotFound(name) => write!(f, "Map {} not found.", name),
+            WadError::DirectoryNotLoaded => write!(f, "The WAD's directory is empty."),
+            WadError::NoPath => write!(f, "The WAD wasn't opened from a file."),
+        }
+    }
+}
+
+impl std::error::Error for WadError {}
+
+impl From<io::Error> for WadError {
+    fn from(err: io::Error) -> Self {
+        WadError::Io(err)
+    }
+}
+
+// A map's parsed lumps, owned independently of the WAD they were read from.
+#[derive(Clone, Debug, Default)]
+pub struct Map {
     pub things: Vec<Thing>,
     pub line_defs: Vec<LineDef>,
     pub side_defs: Vec<SideDef>,
@@ -202,12 +944,51 @@ pub struct WAD {
     pub ssectors: Vec<SubSector>,
     pub nodes: Vec<Node>,
     pub sectors: Vec<Sector>,
+    // Raw REJECT bit matrix, see `Map::is_rejected`.
+    pub reject: Vec<u8>,
+}
+
+// The current map is reachable through `Deref`, so `wad.things` and the
+// `Map` queries work on the WAD directly.
+#[derive(Debug)]
+pub struct WAD {
+    pub map: Map,
 
     pub directory: Vec<Directory>,
     pub header: Header,
 
     map_index: Option<usize>,
-    file: fs::File,
+    file: Box<dyn WadSource>,
+    // Where `file` was opened from, `None` for other sources.
+    path: Option<String>,
+
+    #[cfg(feature = "mmap")]
+    mmap: Option<memmap2::Mmap>,
+}
+
+// Anything lumps can be read from: a file, an in-memory buffer, ...
+pub trait WadSource: Read + Seek {}
+
+impl <T: Read + Seek> WadSource for T {}
+
+impl std::ops::Deref for WAD {
+    type Target = Map;
+
+    fn deref(&self) -> &Map {
+        &self.map
+    }
+}
+
+impl std::ops::DerefMut for WAD {
+    fn deref_mut(&mut self) -> &mut Map {
+        &mut self.map
+    }
+}
+
+impl fmt::Debug for dyn WadSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WadSource")
+    }
 }
 
 impl WAD {
@@ -218,563 +999,8949 @@ impl WAD {
             .map(|&c| c as char)
             .collect::<String>()
     }
+
+    // Lump, texture and flat names: up to the first NUL, in upper case like
+    // DOOM compares them.
+    pub fn lump_name(slice: &[u8]) -> String {
+        slice
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c.to_ascii_uppercase() as char)
+            .collect()
+    }
 }
 
 impl WAD {
     const DIRECTORY_SIZE: usize = 16;
     const HEADER_SIZE: usize = 12;
 
-    fn read_directory(&mut self) -> io::Result<()> {
+    fn read_directory(&mut self) -> Result<(), WadError> {
         let offset = self.header.offset as u64;
 
-        self.directory.clear();
-        self.file.seek(io::SeekFrom::Start(offset))?;
+        // A corrupt count would otherwise mean billions of failing reads.
+        let len = self.file.seek(io::SeekFrom::End(0))?;
+        let size = self.header.count as u64 * Self::DIRECTORY_SIZE as u64;
 
-        for _ in 0..(self.header.count as usize) {
-            let mut bytes = [0; Self::DIRECTORY_SIZE];
+        if offset + size > len {
+            let got = len.saturating_sub(offset) as usize;
 
-            self.file.read_exact(&mut bytes)?;
-            self.directory
-                .push(unsafe { std::mem::transmute::<_, Directory>(bytes) });
+            return Err(Self::truncated("directory", size as usize, got));
         }
 
+        let mut bytes = vec![0; size as usize];
+
+        self.file.seek(io::SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut bytes)?;
+
+        self.directory = parse_records(&bytes)?;
+
         Ok(())
     }
 
-    fn read_header(&mut self) -> io::Result<()> {
+    fn read_header(&mut self) -> Result<(), WadError> {
         let mut bytes = [0; Self::HEADER_SIZE];
 
         self.file.seek(io::SeekFrom::Start(0))?;
         self.file.read_exact(&mut bytes)?;
 
-        self.header = unsafe { std::mem::transmute::<_, Header>(bytes) };
+        self.header = Header::read(&mut LumpReader::new(&bytes))?;
 
         Ok(())
     }
 }
 
 impl WAD {
-    // `offset` - Map index + MapLumpIndex.
-    fn read_map_lump(&mut self, offset: usize) -> io::Result<Vec<u8>> {
+    fn truncated(name: &str, expected: usize, got: usize) -> WadError {
+        WadError::TruncatedLump { name: name.to_string(), expected, got }
+    }
+
+    // `offset` - Map index + MapLumpIndex. Lumps running past the end of
+    // the file fail with a `TruncatedLump` naming them.
+    fn read_map_lump(&mut self, offset: usize) -> Result<Vec<u8>, WadError> {
         let lump = self.directory[offset];
+        let size = lump.size as usize;
 
-        let mut bytes = vec![0; lump.size as usize];
+        #[cfg(feature = "mmap")]
+        if let Some(mapping) = &self.mmap {
+            let start = (lump.offset as usize).min(mapping.len());
+            let bytes = &mapping[start..mapping.len().min(start.saturating_add(size))];
+
+            return match bytes.len() == size {
+                true => Ok(bytes.to_vec()),
+                false => Err(Self::truncated(&lump.name(), size, bytes.len())),
+            };
+        }
+
+        let mut bytes = Vec::new();
 
         self.file.seek(io::SeekFrom::Start(lump.offset as u64))?;
-        self.file.read_exact(&mut bytes)?;
+        (&mut self.file).take(size as u64).read_to_end(&mut bytes)?;
 
-        Ok(bytes)
+        match bytes.len() == size {
+            true => Ok(bytes),
+            false => Err(Self::truncated(&lump.name(), size, bytes.len())),
+        }
     }
 
-    fn read_map_lump_as<T>(&mut self, index: MapLumpIndex) -> io::Result<Vec<T>> {
-        match self.map_index {
-            Some(map_index) => {
-                let bytes = self.read_map_lump(map_index + index as usize)?;
+    // Directory index of one of a map's lumps. The lumps following the
+    // marker are matched by name up to the next map, found by its ExMy or
+    // MAPxx marker or by a map lump showing up a second time. Fixed offsets
+    // would misread maps without nodes or a blockmap, which leave theirs
+    // out, and maps with other lumps like SCRIPTS after their marker.
+    fn find_map_lump(&self, map_index: usize, index: MapLumpIndex) -> Option<usize> {
+        self.find_map_lump_named(map_index, index.name())
+    }
 
-                let len = bytes.len() / std::mem::size_of::<T>();
-                let ptr = bytes.as_ptr();
+    // Same as `find_map_lump`, for lumps like TEXTMAP that aren't in
+    // `MapLumpIndex`. UDMF maps end at their ENDMAP.
+    fn find_map_lump_named(&self, map_index: usize, lump_name: &str) -> Option<usize> {
+        let mut seen = BTreeSet::new();
 
-                let data = unsafe { Vec::from_raw_parts(ptr as *mut T, len, len) };
-                std::mem::forget(bytes);
+        for (i, dir) in self.directory.iter().enumerate().skip(map_index + 1) {
+            let name = dir.name();
 
-                Ok(data)
+            if name == lump_name {
+                return Some(i);
             }
-            None => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "No map has been loaded.",
-            )),
-        }
-    }
-}
 
-impl WAD {
-    pub fn change_map(&mut self, name: &str) -> Result<bool, io::Error> {
-        for (i, dir) in self.directory.iter().enumerate() {
-            if dir.name() == name {
-                self.map_index = Some(i);
+            let is_map_lump = MapLumpIndex::ALL.iter().any(|lump| name == lump.name());
 
-                self.things = self.read_map_lump_as(MapLumpIndex::Things)?;
-                self.line_defs = self.read_map_lump_as(MapLumpIndex::LineDefs)?;
-                self.side_defs = self.read_map_lump_as(MapLumpIndex::SideDefs)?;
-                self.vertexes = self.read_map_lump_as(MapLumpIndex::Vertexes)?;
-                self.segs = self.read_map_lump_as(MapLumpIndex::Segs)?;
-                self.ssectors = self.read_map_lump_as(MapLumpIndex::SSectors)?;
-                self.nodes = self.read_map_lump_as(MapLumpIndex::Nodes)?;
-                self.sectors = self.read_map_lump_as(MapLumpIndex::Sectors)?;
+            let is_marker = Self::map_order(&name).0 < 2 || name == "ENDMAP";
 
-                return Ok(true);
+            if is_marker || (is_map_lump && !seen.insert(name)) {
+                break;
             }
         }
 
-        Ok(false)
-    }
-}
-
-impl WAD {
-    pub fn setup(&mut self) -> io::Result<()> {
-        self.read_header()?;
-        self.read_directory()?;
-
-        Ok(())
+        None
     }
 
-    pub fn open(&mut self, path: &str) -> io::Result<()> {
-        self.file = fs::File::open(path)?;
-        self.setup()?;
-
-        Ok(())
+    // Missing lumps read as empty.
+    fn read_optional_map_lump(
+        &mut self,
+        map_index: usize,
+        index: MapLumpIndex,
+    ) -> Result<Vec<u8>, WadError> {
+        match self.find_map_lump(map_index, index) {
+            Some(lump) => self.read_map_lump(lump),
+            None => Ok(Vec::new()),
+        }
     }
-}
 
-impl WAD {
-    pub fn new(path: &str) -> Result<Self, io::Error> {
-        let mut ctx = Self {
-            things: Vec::new(),
-            line_defs: Vec::new(),
-            side_defs: Vec::new(),
-            vertexes: Vec::new(),
-            segs: Vec::new(),
-            ssectors: Vec::new(),
-            nodes: Vec::new(),
-            sectors: Vec::new(),
+    // `map_index` - Directory index of the map marker. A missing lump
+    // leaves the map unusable, so it's an error.
+    fn read_map_lump_as<T: Record>(
+        &mut self,
+        map_index: usize,
+        index: MapLumpIndex,
+    ) -> Result<Vec<T>, WadError> {
+        let Some(lump) = self.find_map_lump(map_index, index) else {
+            return Err(WadError::InvalidLump(index.name().to_string()));
+        };
 
-            directory: Vec::new(),
-            header: Header::default(),
+        let bytes = self.read_map_lump(lump)?;
 
-            map_index: None,
-            file: fs::File::open(path)?,
-        };
+        Self::parse_lump_records(index.name(), &bytes)
+    }
 
-        ctx.setup()?;
+    // Like `read_map_lump_as`, for the lumps node-less maps leave out.
+    fn read_optional_map_lump_as<T: Record>(
+        &mut self,
+        map_index: usize,
+        index: MapLumpIndex,
+    ) -> Result<Vec<T>, WadError> {
+        match self.find_map_lump(map_index, index) {
+            Some(_) => self.read_map_lump_as(map_index, index),
+            None => Ok(Vec::new()),
+        }
+    }
 
-        Ok(ctx)
+    // A partial record at the end means the lump was cut short.
+    fn parse_lump_records<T: Record>(name: &str, bytes: &[u8]) -> Result<Vec<T>, WadError> {
+        match bytes.len() % T::SIZE {
+            0 => Ok(parse_records(bytes)?),
+            _ => Err(Self::truncated(name, bytes.len().next_multiple_of(T::SIZE), bytes.len())),
+        }
     }
-}
 
-// - - -
-#[derive(Debug)]
-pub struct Player {
-    pub thing: Thing,
-    pub position: (f32, f32),
-    pub angle: f32,
-}
+    // Raw bytes of one of the current map's lumps, including the ones that
+    // aren't parsed like REJECT and BLOCKMAP.
+    pub fn map_lump(&mut self, index: MapLumpIndex) -> Result<Vec<u8>, WadError> {
+        let map_index = self.map_index.ok_or(WadError::NoMapLoaded)?;
 
-impl Player {
-    pub fn new(thing: Thing) -> Self {
-        Self {
-            thing,
-            position: (thing.x as f32, thing.y as f32),
-            angle: thing.angle as f32,
+        match self.find_map_lump(map_index, index) {
+            Some(lump) => Ok(self.read_map_lump(lump)?),
+            None => Err(WadError::LumpNotFound(index.name().to_string())),
         }
     }
 }
 
 // - - -
-pub struct BSP <'a> {
-    pub map_data: &'a WAD, 
-    pub root_node_id: usize,
+// Little-endian reader over an in-memory lump.
+struct LumpReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
 }
 
-impl <'a> BSP <'a> {
-    pub fn is_on_back_side(&self, renderer: &mut MapViewer, node: &Node) -> bool {
-        let dx = renderer.player.position.0 - node.x_partition as f32;
-        let dy = renderer.player.position.1 - node.y_partition as f32;
-
-        dx * node.dy_partition as f32 - dy * node.dx_partition as f32 <= 0.0
+impl <'a> LumpReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
     }
 
-    pub fn render_sub_sector(&self, renderer: &mut MapViewer, sub_sector_id: u16) {
-        let sub_sector = self.map_data.ssectors[sub_sector_id as usize];
-
-        for i in 0..sub_sector.num_segs {
-            let seg = &self.map_data.segs[(sub_sector.first_seg + i) as usize];
+    fn read_bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self.position.saturating_add(len);
 
-            //thread::sleep(Duration::from_millis(1));
+        match self.bytes.get(self.position..end) {
+            Some(slice) => {
+                self.position = end;
 
-            renderer.draw_segment(*seg);
-            //renderer.window.display();
+                Ok(slice)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Lump data ended unexpectedly.",
+            )),
         }
     }
 
-    pub fn render_bsp_node(&self, renderer: &mut MapViewer, node_id: u16) {
-        let sub_sector_identifier = 0x8000;
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
 
-        #[allow(unused_assignments)]
-        let mut sub_sector_id = 0x8000;
- 
-        if node_id >= sub_sector_identifier {
-            sub_sector_id = node_id - sub_sector_identifier;
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let bytes = self.read_bytes(2)?;
 
-            self.render_sub_sector(renderer, sub_sector_id);            
-            return
-        }
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
 
-        let node = &self.map_data.nodes[node_id as usize];
+    fn read_i16(&mut self) -> io::Result<i16> {
+        Ok(self.read_u16()? as i16)
+    }
 
-        if self.is_on_back_side(renderer, node) {
-            self.render_bsp_node(renderer, node.back_child as u16);
-            self.render_bsp_node(renderer, node.front_child as u16);
-        } else {
-            self.render_bsp_node(renderer, node.front_child as u16);
-            self.render_bsp_node(renderer, node.back_child as u16);
-        }
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let bytes = self.read_bytes(4)?;
 
-            
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
 
-    pub fn update(&self, renderer: &mut MapViewer) {
-        self.render_bsp_node(renderer, self.root_node_id as u16); 
+    fn read_i32(&mut self) -> io::Result<i32> {
+        Ok(self.read_u32()? as i32)
     }
-}
 
-impl <'a> BSP <'a> {
-    pub fn new(map_data: &'a WAD) -> Self {
-        let root_node_id = map_data.nodes.len() - 1;
+    fn read_name(&mut self) -> io::Result<[u8; 8]> {
+        let mut name = [0; 8];
+        name.copy_from_slice(self.read_bytes(8)?);
 
-        Self {
-            map_data,
-            root_node_id,
-        }
+        Ok(name)
     }
 }
 
 // - - -
+// WAD and map lump records, read field by field so parsing doesn't depend
+// on the host's byte order or on how the structs are laid out.
+trait Record: Sized {
+    // Bytes per record in the lump.
+    const SIZE: usize;
 
-use rand::Rng;
-use sfml::{
-    graphics::{
-        CircleShape, Color, RectangleShape, RenderTarget, RenderWindow, Shape, Transformable,
-    },
-    system::Vector2f,
-    window::{ContextSettings, Event, Key, Style},
-};
+    fn read(reader: &mut LumpReader) -> io::Result<Self>;
 
-pub struct MapViewer <'a> {
-    window: RenderWindow,
+    // Appends the record in its lump layout, `SIZE` bytes.
+    fn write(&self, out: &mut Vec<u8>);
 
-    w_height: f32,
-    w_width: f32,
+    // Snapshots keep the lump layout, except for records whose parsed
+    // indexes are wider than the vanilla lump's.
+    const SNAPSHOT_SIZE: usize = Self::SIZE;
 
-    max_map_height: f32,
-    min_map_height: f32,
-    max_map_width: f32,
-    min_map_width: f32,
+    fn read_snapshot(reader: &mut LumpReader) -> io::Result<Self> {
+        Self::read(reader)
+    }
 
-    map_vertexes: Vec<Vector2f>,
-    map_data:&'a  WAD,
+    fn write_snapshot(&self, out: &mut Vec<u8>) {
+        self.write(out)
+    }
+}
 
-    player: Player,
-    //bsp: BSP<'a>,
+// A trailing partial record is ignored.
+fn parse_records<T: Record>(bytes: &[u8]) -> io::Result<Vec<T>> {
+    let mut reader = LumpReader::new(bytes);
 
-    //engine: Engine<'a>,
+    (0..bytes.len() / T::SIZE).map(|_| T::read(&mut reader)).collect()
 }
 
-impl <'a> MapViewer <'_> {
-    pub fn draw_line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, color: Color) {
-        let mut line = RectangleShape::new();
-        line.set_fill_color(color);
-        line.set_size(Vector2f::new(1.0, 1.0));
-        line.set_position(Vector2f::new(x1, y1));
-        line.set_rotation((y2 - y1).atan2(x2 - x1).to_degrees());
-
-        let length = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
-        line.set_size(Vector2f::new(length, 1.0));
+// The inverse of `parse_records`.
+fn records_to_bytes<T: Record>(items: &[T]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(items.len() * T::SIZE);
 
-        self.window.draw(&line);
+    for item in items {
+        item.write(&mut bytes);
     }
 
-    pub fn draw_circle(&mut self, x: f32, y: f32, radius: f32, count: usize, color: Color) {
-        let mut circle = CircleShape::new(radius, count);
-        circle.set_fill_color(color);
-        circle.set_position(Vector2f::new(x - 2.0, y - 2.0));
+    bytes
+}
+
+impl Record for Header {
+    const SIZE: usize = WAD::HEADER_SIZE;
+
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        let mut identifier = [0; 4];
+        identifier.copy_from_slice(reader.read_bytes(4)?);
+
+        Ok(Header { identifier, count: reader.read_u32()?, offset: reader.read_u32()? })
+    }
 
-        self.window.draw(&circle);
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.identifier);
+        out.extend_from_slice(&self.count.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
     }
 }
 
-impl <'a> MapViewer <'_> {
-    pub fn calc_map_bounds(&mut self) {
-        let vertexes = self.map_data.vertexes.to_vec();
+impl Record for Directory {
+    const SIZE: usize = WAD::DIRECTORY_SIZE;
 
-        let mut vertexes_x = vertexes.iter().map(|v| v.x).collect::<Vec<_>>();
-        let mut vertexes_y = vertexes.iter().map(|v| v.y).collect::<Vec<_>>();
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(Directory {
+            offset: reader.read_u32()?,
+            size: reader.read_u32()?,
+            name: reader.read_name()?,
+        })
+    }
 
-        vertexes_x.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        vertexes_y.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.name);
+    }
+}
 
-        let x_size = vertexes_x.len();
-        let y_size = vertexes_y.len();
+// Byte matrices like REJECT.
+impl Record for u8 {
+    const SIZE: usize = 1;
 
-        self.min_map_height = vertexes_y[0] as f32;
-        self.max_map_height = vertexes_y[y_size - 1] as f32;
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        reader.read_u8()
+    }
 
-        self.min_map_width = vertexes_x[0] as f32;
-        self.max_map_width = vertexes_x[x_size - 1] as f32;
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(*self);
     }
 }
 
-impl <'a> MapViewer <'a> {
-    pub fn traslate_vertex_x(&self, x: f32) -> f32 {
-        (x.min(self.max_map_width).max(self.min_map_width) - self.min_map_width)
-            * ((self.w_width - 30.0) - 30.0)
-            / (self.max_map_width - self.min_map_width)
-            + 30.0
+impl Record for Thing {
+    const SIZE: usize = 10;
+
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(Thing {
+            x: reader.read_i16()?,
+            y: reader.read_i16()?,
+            angle: reader.read_i16()?,
+            t_type: reader.read_i16()?,
+            flags: reader.read_i16()?,
+        })
     }
 
-    pub fn traslate_vertex_y(&self, y: f32) -> f32 {
-        self.w_height
-            - (y.min(self.max_map_height).max(self.min_map_height) - self.min_map_height)
-                * ((self.w_height - 30.0) - 30.0)
-                / (self.max_map_height - self.min_map_height)
-            - 30.0
+    fn write(&self, out: &mut Vec<u8>) {
+        for field in [self.x, self.y, self.angle, self.t_type, self.flags] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
     }
 }
 
-impl <'a> MapViewer <'_> {
-    pub fn draw_bbox(&mut self, bbox: [i16; 4], color: Color) {
-        // 0 -> top
-        // 1 -> bottom
-        // 2 -> left
-        // 3 -> right
+impl Record for LineDef {
+    const SIZE: usize = 14;
+
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(LineDef {
+            start_vertex: reader.read_i16()?,
+            end_vertex: reader.read_i16()?,
+            flags: reader.read_i16()?,
+            special_type: reader.read_i16()?,
+            sector_tag: reader.read_i16()?,
+            right_sidedef: reader.read_i16()?,
+            left_sidedef: reader.read_i16()?,
+        })
+    }
 
-        let x = self.traslate_vertex_x(bbox[2] as f32); 
-        let y = self.traslate_vertex_y(bbox[0] as f32);
+    fn write(&self, out: &mut Vec<u8>) {
+        let fields = [
+            self.start_vertex,
+            self.end_vertex,
+            self.flags,
+            self.special_type,
+            self.sector_tag,
+            self.right_sidedef,
+            self.left_sidedef,
+        ];
+
+        for field in fields {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+}
 
-        let w = self.traslate_vertex_x(bbox[3] as f32) - x;
-        let h = self.traslate_vertex_y(bbox[1] as f32) - y;
+impl Record for SideDef {
+    const SIZE: usize = 30;
+
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(SideDef {
+            x_offset: reader.read_i16()?,
+            y_offset: reader.read_i16()?,
+            upper_texture: reader.read_name()?,
+            lower_texture: reader.read_name()?,
+            middle_texture: reader.read_name()?,
+            sector: reader.read_i16()?,
+        })
+    }
 
-        let mut rect = RectangleShape::new();
-        rect.set_fill_color(Color::TRANSPARENT);
-        rect.set_outline_color(color);
-        rect.set_outline_thickness(1.0);
-        rect.set_size(Vector2f::new(w, h));
-        rect.set_position(Vector2f::new(x, y));
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.x_offset.to_le_bytes());
+        out.extend_from_slice(&self.y_offset.to_le_bytes());
+        out.extend_from_slice(&self.upper_texture);
+        out.extend_from_slice(&self.lower_texture);
+        out.extend_from_slice(&self.middle_texture);
+        out.extend_from_slice(&self.sector.to_le_bytes());
+    }
+}
+
+impl Record for Vertex {
+    const SIZE: usize = 4;
 
-        self.window.draw(&rect);
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(Vertex { x: reader.read_i16()?, y: reader.read_i16()? })
     }
 
-    pub fn draw_node(&mut self, node_id: usize) {
-        let node = &self.map_data.nodes[node_id];
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.x.to_le_bytes());
+        out.extend_from_slice(&self.y.to_le_bytes());
+    }
+}
+
+impl Record for Seg {
+    const SIZE: usize = 12;
+
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(Seg {
+            start_vertex: reader.read_u16()? as u32,
+            end_vertex: reader.read_u16()? as u32,
+            angle: reader.read_i16()?,
+            linedef: Self::read_linedef(reader)?,
+            direction: reader.read_i16()?,
+            offset: reader.read_i16()?,
+        })
+    }
 
-        let front_bbox = node.front_bbox;
-        let back_bbox = node.back_bbox;
+    fn write(&self, out: &mut Vec<u8>) {
+        let fields = [
+            self.start_vertex as u16,
+            self.end_vertex as u16,
+            self.angle as u16,
+            self.linedef as u16,
+            self.direction as u16,
+            self.offset as u16,
+        ];
+
+        for field in fields {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+    }
 
-        self.draw_bbox(front_bbox, Color::GREEN);
-        self.draw_bbox(back_bbox, Color::RED);
+    const SNAPSHOT_SIZE: usize = 18;
+
+    fn read_snapshot(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(Seg {
+            start_vertex: reader.read_u32()?,
+            end_vertex: reader.read_u32()?,
+            angle: reader.read_i16()?,
+            linedef: reader.read_i32()?,
+            direction: reader.read_i16()?,
+            offset: reader.read_i16()?,
+        })
+    }
 
-        let x1 = self.traslate_vertex_x(node.x_partition as f32);
-        let y1 = self.traslate_vertex_y(node.y_partition as f32);
+    fn write_snapshot(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.start_vertex.to_le_bytes());
+        out.extend_from_slice(&self.end_vertex.to_le_bytes());
+        out.extend_from_slice(&self.angle.to_le_bytes());
+        out.extend_from_slice(&self.linedef.to_le_bytes());
+        out.extend_from_slice(&self.direction.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
+    }
+}
 
-        let x2 = self.traslate_vertex_x(node.x_partition as f32 + node.dx_partition as f32);
-        let y2 = self.traslate_vertex_y(node.y_partition as f32 + node.dy_partition as f32);
+impl Record for SubSector {
+    const SIZE: usize = 4;
 
-        self.draw_line(x1, y1, x2, y2, Color::BLUE); 
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(SubSector {
+            num_segs: reader.read_u16()? as u32,
+            first_seg: reader.read_u16()? as u32,
+        })
     }
 
-    pub fn draw_linedefs(&mut self) {
-        let linedefs = &self.map_data.line_defs;
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.num_segs as u16).to_le_bytes());
+        out.extend_from_slice(&(self.first_seg as u16).to_le_bytes());
+    }
 
-        for line in linedefs.iter() {
-            let vertex1 = self.map_data.vertexes[line.start_vertex as usize];
-            let vertex2 = self.map_data.vertexes[line.end_vertex as usize];
+    const SNAPSHOT_SIZE: usize = 8;
 
-            self.draw_line(
-                self.traslate_vertex_x(vertex1.x as f32),
-                self.traslate_vertex_y(vertex1.y as f32),
-                self.traslate_vertex_x(vertex2.x as f32),
-                self.traslate_vertex_y(vertex2.y as f32),
-                Color::rgb(70, 70, 70),
-            );
-        } 
+    fn read_snapshot(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(SubSector { num_segs: reader.read_u32()?, first_seg: reader.read_u32()? })
     }
 
-    pub fn draw_vertexes(&mut self) {
-        let vertexes = &self.map_data.vertexes;
+    fn write_snapshot(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.num_segs.to_le_bytes());
+        out.extend_from_slice(&self.first_seg.to_le_bytes());
+    }
+}
 
-        for vertex in vertexes.iter() {
-            self.draw_circle(
-                self.traslate_vertex_x(vertex.x as f32),
-                self.traslate_vertex_y(vertex.y as f32),
-                2.0,
-                30,
-                Color::WHITE,
-            );
+impl Node {
+    // Everything but the children, which are 16 or 32-bit.
+    fn read_partition(reader: &mut LumpReader) -> io::Result<Self> {
+        fn bbox(reader: &mut LumpReader) -> io::Result<[i16; 4]> {
+            Ok([reader.read_i16()?, reader.read_i16()?, reader.read_i16()?, reader.read_i16()?])
         }
+
+        Ok(Node {
+            x_partition: reader.read_i16()?,
+            y_partition: reader.read_i16()?,
+            dx_partition: reader.read_i16()?,
+            dy_partition: reader.read_i16()?,
+            front_bbox: bbox(reader)?,
+            back_bbox: bbox(reader)?,
+            front_child: 0,
+            back_child: 0,
+        })
     }
 
-    pub fn rand_color(&mut self) -> Color {
-        let mut rng = rand::thread_rng();
+    fn write_partition(&self, out: &mut Vec<u8>) {
+        let partition = [self.x_partition, self.y_partition, self.dx_partition, self.dy_partition];
 
-        Color::rgb(rng.gen_range(0..255), rng.gen_range(0..255), rng.gen_range(0..255))
+        for field in partition.iter().chain(&self.front_bbox).chain(&self.back_bbox) {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
     }
 
-    pub fn draw_segment(&mut self, seg: Seg) {
-        let vertex1 = self.map_data.vertexes[seg.start_vertex as usize];
-        let vertex2 = self.map_data.vertexes[seg.end_vertex as usize];
+    // Vanilla children flag subsectors with 0x8000.
+    fn read_vanilla_child(reader: &mut LumpReader) -> io::Result<u32> {
+        let child = reader.read_u16()? as u32;
+
+        match child & 0x8000 != 0 {
+            true => Ok((child & 0x7FFF) | Map::SUB_SECTOR_FLAG),
+            false => Ok(child),
+        }
+    }
+
+    fn vanilla_child(child: u32) -> u16 {
+        match child & Map::SUB_SECTOR_FLAG != 0 {
+            true => (child as u16 & 0x7FFF) | 0x8000,
+            false => child as u16,
+        }
+    }
+}
 
-        let color = Color::rgb(110, 110, 110);
+impl Record for Node {
+    const SIZE: usize = 28;
 
-        self.draw_line(
-            self.traslate_vertex_x(vertex1.x as f32),
-            self.traslate_vertex_y(vertex1.y as f32),
-            self.traslate_vertex_x(vertex2.x as f32),
-            self.traslate_vertex_y(vertex2.y as f32),
-            color,
-        );
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        let node = Self::read_partition(reader)?;
+
+        Ok(Node {
+            front_child: Self::read_vanilla_child(reader)?,
+            back_child: Self::read_vanilla_child(reader)?,
+            ..node
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        self.write_partition(out);
+
+        out.extend_from_slice(&Self::vanilla_child(self.front_child).to_le_bytes());
+        out.extend_from_slice(&Self::vanilla_child(self.back_child).to_le_bytes());
+    }
+
+    const SNAPSHOT_SIZE: usize = 32;
+
+    fn read_snapshot(reader: &mut LumpReader) -> io::Result<Self> {
+        let node = Self::read_partition(reader)?;
+
+        Ok(Node { front_child: reader.read_u32()?, back_child: reader.read_u32()?, ..node })
+    }
+
+    fn write_snapshot(&self, out: &mut Vec<u8>) {
+        self.write_partition(out);
+
+        out.extend_from_slice(&self.front_child.to_le_bytes());
+        out.extend_from_slice(&self.back_child.to_le_bytes());
     }
 }
 
-impl <'a> MapViewer <'a> {
-    pub fn run(&mut self, bsp: &BSP) {
-        //let mut window = &self.window;
+impl Record for Sector {
+    const SIZE: usize = 26;
+
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(Sector {
+            floor_height: reader.read_i16()?,
+            ceiling_height: reader.read_i16()?,
+            floor_texture: reader.read_name()?,
+            ceiling_texture: reader.read_name()?,
+            light_level: reader.read_i16()?,
+            special_type: reader.read_i16()?,
+            tag: reader.read_i16()?,
+        })
+    }
 
-        //let vertexes = &self.map_vertexes;
-        //let linedefs = &self.map_data.line_defs;
-        //
-        
-        loop {
-            while let Some(event) = self.window.poll_event() {
-                match event {
-                    Event::Closed => return,
-                    Event::KeyPressed { code, .. } => match code {
-                        Key::Escape => return,
-                        _ => {}
-                    },
-                    _ => {}
-                }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.floor_height.to_le_bytes());
+        out.extend_from_slice(&self.ceiling_height.to_le_bytes());
+        out.extend_from_slice(&self.floor_texture);
+        out.extend_from_slice(&self.ceiling_texture);
+
+        for field in [self.light_level, self.special_type, self.tag] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+}
+
+// - - -
+// Extended node formats written by ZDBSP and friends. The NODES lump starts
+// with a 4-byte signature and carries the vertexes, subsectors, segs and nodes
+// itself (the SEGS and SSECTORS lumps are left empty). `ZNOD` is the same data
+// compressed with zlib.
+impl WAD {
+    const XNOD_SIGNATURE: &'static [u8] = b"XNOD";
+    const ZNOD_SIGNATURE: &'static [u8] = b"ZNOD";
+
+    // Takes the map's other lumps as read so far, extended nodes replace its
+    // segs and subsectors and append to its vertexes.
+    fn read_nodes(
+        &mut self,
+        map_index: usize,
+        line_defs: &[LineDef],
+        vertexes: &mut Vec<Vertex>,
+        segs: &mut Vec<Seg>,
+        ssectors: &mut Vec<SubSector>,
+    ) -> Result<Vec<Node>, WadError> {
+        let bytes = self.read_optional_map_lump(map_index, MapLumpIndex::Nodes)?;
+
+        match bytes.get(..4) {
+            Some(Self::XNOD_SIGNATURE) => {
+                Ok(Self::read_extended_nodes(&bytes[4..], line_defs, vertexes, segs, ssectors)?)
             }
+            Some(Self::ZNOD_SIGNATURE) => {
+                let mut data = Vec::new();
+                ZlibDecoder::new(&bytes[4..]).read_to_end(&mut data)?;
 
-            self.window.clear(Color::BLACK);
+                Ok(Self::read_extended_nodes(&data, line_defs, vertexes, segs, ssectors)?)
+            }
+            _ => Self::parse_lump_records(MapLumpIndex::Nodes.name(), &bytes),
+        }
+    }
 
-            // Draw vertexes
-            /*let mut circle = CircleShape::new(2.0, 12);
-            circle.set_fill_color(Color::WHITE);
+    fn read_extended_nodes(
+        bytes: &[u8],
+        line_defs: &[LineDef],
+        vertexes: &mut Vec<Vertex>,
+        segs: &mut Vec<Seg>,
+        ssectors: &mut Vec<SubSector>,
+    ) -> io::Result<Vec<Node>> {
+        let mut reader = LumpReader::new(bytes);
+
+        // Vertexes: the map's own ones plus the ones created by the node builder.
+        let original_vertexes = reader.read_u32()? as usize;
+        let new_vertexes = reader.read_u32()?;
+
+        if original_vertexes > vertexes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Extended nodes reference more vertexes than the map has.",
+            ));
+        }
 
-            for vertex in vertexes.iter() {
-                let vertex = Vector2f::new(vertex.x - 2.0, vertex.y - 2.0);
+        vertexes.truncate(original_vertexes);
 
-                circle.set_position(vertex);
-                self.window.draw(&circle);
-            }*/
+        for _ in 0..new_vertexes {
+            // 16.16 fixed point.
+            let x = reader.read_i32()? >> 16;
+            let y = reader.read_i32()? >> 16;
 
-            // Draw linedefs
-            /*for linedef in linedefs.iter() {
-                let mut line = RectangleShape::new();
-                line.set_size(Vector2f::new(1.0, 1.0));
-                line.set_fill_color(Color::WHITE);
+            vertexes.push(Vertex { x: x as i16, y: y as i16 });
+        }
 
-                let p_1 = vertexes[linedef.start_vertex as usize];
-                let p_2 = vertexes[linedef.end_vertex as usize];
+        // Subsectors only store their seg count, segs are consecutive.
+        let mut first_seg: u32 = 0;
 
-                let a = (p_2.y - p_1.y).atan2(p_2.x - p_1.x);
+        ssectors.clear();
 
-                let length = ((p_2.y - p_1.y).powi(2) + (p_2.x - p_1.x).powi(2)).sqrt();
+        for _ in 0..reader.read_u32()? {
+            let num_segs = reader.read_u32()?;
 
-                line.set_size(Vector2f::new(length, 1.0));
-                line.set_rotation(a.to_degrees());
-                line.set_position(p_1);
+            ssectors.push(SubSector { num_segs, first_seg });
 
-                self.window.draw(&line);
-            }*/
+            first_seg = first_seg.saturating_add(num_segs);
+        }
 
-            self.draw_linedefs();
-            //self.draw_vertexes();
- 
-            self.draw_node(
-                self.map_data.nodes.len() - 1
-            );
+        segs.clear();
+
+        for _ in 0..reader.read_u32()? {
+            let start_vertex = reader.read_u32()?;
+            let end_vertex = reader.read_u32()?;
+            let linedef = Seg::read_linedef(&mut reader)?;
+            let direction = reader.read_u8()? as i16;
+
+            let mut seg = Seg {
+                start_vertex,
+                end_vertex,
+                angle: 0,
+                linedef,
+                direction,
+                offset: 0,
+            };
+
+            Self::extended_seg_geometry(vertexes, line_defs, &mut seg);
+            segs.push(seg);
+        }
 
-            bsp.update(self);             
+        let mut nodes = Vec::new();
 
-            // Draw Player
-            /*let mut player = CircleShape::new(2.0, 12);
+        for _ in 0..reader.read_u32()? {
+            let x_partition = reader.read_i16()?;
+            let y_partition = reader.read_i16()?;
+            let dx_partition = reader.read_i16()?;
+            let dy_partition = reader.read_i16()?;
 
-            player.set_fill_color(Color::BLUE);
+            let mut front_bbox = [0; 4];
+            let mut back_bbox = [0; 4];
 
-            let player_x = self.traslate_vertex_x(self.player.position.0);
-            let player_y = self.traslate_vertex_y(self.player.position.1);
+            for side in front_bbox.iter_mut() {
+                *side = reader.read_i16()?;
+            }
 
-            player.set_position(Vector2f::new(player_x, player_y));
-            player.set_rotation(self.player.angle.to_degrees());
+            for side in back_bbox.iter_mut() {
+                *side = reader.read_i16()?;
+            }
 
-            self.window.draw(&player);
-            self.window.display();*/
- 
-            self.window.display();
+            // Subsectors are flagged with the high bit, like `Map::SUB_SECTOR_FLAG`.
+            let front_child = reader.read_u32()?;
+            let back_child = reader.read_u32()?;
+
+            nodes.push(Node {
+                x_partition,
+                y_partition,
+                dx_partition,
+                dy_partition,
+                front_bbox,
+                back_bbox,
+                front_child,
+                back_child,
+            });
         }
+
+        Ok(nodes)
     }
-}
 
-impl <'a> MapViewer <'a> {
-    pub fn new(width: f32, height: f32, map_data: &'a WAD) -> Self {
-        let m_vertexes = map_data.vertexes.clone();
+    // Extended segs don't store `angle` nor `offset`, derive them from the
+    // vertexes like the vanilla node builders do.
+    fn extended_seg_geometry(vertexes: &[Vertex], line_defs: &[LineDef], seg: &mut Seg) {
+        let (start, end) = match (
+            vertexes.get(seg.start_vertex as usize),
+            vertexes.get(seg.end_vertex as usize),
+        ) {
+            (Some(&start), Some(&end)) => (start, end),
+            _ => return,
+        };
 
-        let player_thing = map_data.things[0];
-        let player = Player::new(player_thing.clone());
-        //let bsp = BSP::new(&map_data);
+        let dx = end.x as f32 - start.x as f32;
+        let dy = end.y as f32 - start.y as f32;
 
-        //let engine = Engine::new(&mut window, &map_data);
-        
-        let w_title = "Where's All the Data? - Map Viewer";
+        seg.angle = degrees_to_bam(dy.atan2(dx).to_degrees()) as i16;
 
-        //let w_height = self.w_height as u32;
-        //let w_width = self.w_width as u32;
+        if seg.linedef < 0 {
+            return;
+        }
 
-        //let root_node_id = map_data.nodes.len() - 1;
-        let context = ContextSettings {
-            antialiasing_level: 0,
-            ..Default::default()
+        if let Some(line) = line_defs.get(seg.linedef as usize) {
+            let origin = match seg.direction {
+                0 => line.start_vertex,
+                _ => line.end_vertex,
+            };
+
+            if let Some(origin) = vertexes.get(origin as usize) {
+                let ox = start.x as f32 - origin.x as f32;
+                let oy = start.y as f32 - origin.y as f32;
+
+                seg.offset = (ox * ox + oy * oy).sqrt() as i16;
+            }
+        }
+    }
+}
+
+impl WAD {
+    // Later lumps override earlier ones, as with PWADs loaded over an IWAD.
+    pub fn find_lump(&self, name: &str) -> Option<usize> {
+        self.directory.iter().rposition(|dir| dir.name() == name)
+    }
+
+    pub fn read_lump_bytes(&mut self, name: &str) -> Result<Vec<u8>, WadError> {
+        match self.find_lump(name) {
+            Some(index) => Ok(self.read_map_lump(index)?),
+            None => Err(WadError::LumpNotFound(name.to_string())),
+        }
+    }
+
+    // Streams a lump instead of reading it whole, reads stop at its end.
+    // It borrows the WAD's file, so it has to be dropped before other reads.
+    pub fn lump_reader(&mut self, name: &str) -> Result<impl Read + '_, WadError> {
+        let lump = match self.find_lump(name) {
+            Some(index) => self.directory[index],
+            None => return Err(WadError::LumpNotFound(name.to_string())),
         };
 
-        //let mut window = RenderWindow::new((w_width, w_height), w_title, Style::CLOSE, &context);
-        //window.set_vertical_sync_enabled(true);
+        self.file.seek(io::SeekFrom::Start(lump.offset as u64))?;
 
-        let mut viewer = Self {
-            window: RenderWindow::new((width as u32, height as u32), w_title, Style::CLOSE, &context),
-            //window: & mut window,
+        Ok((&mut self.file).take(lump.size as u64))
+    }
+}
 
-            w_height: height,
-            w_width: width,
+// - - -
+// A directory entry along with where it is in the directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LumpInfo {
+    pub index: usize,
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
 
-            max_map_height: 0.0,
-            min_map_height: 0.0,
-            max_map_width: 0.0,
-            min_map_width: 0.0,
+impl WAD {
+    // Every lump in each `start_marker`..`end_marker` range, without the
+    // markers themselves or nested ones like F1_START inside F_START.
+    pub fn lumps_between(&self, start_marker: &str, end_marker: &str) -> Vec<LumpInfo> {
+        let mut inside = false;
+        let mut lumps = Vec::new();
+
+        for (index, dir) in self.directory.iter().enumerate() {
+            let name = dir.name();
+
+            if name == start_marker {
+                inside = true;
+            } else if name == end_marker {
+                inside = false;
+            } else if inside && !name.ends_with("_START") && !name.ends_with("_END") {
+                lumps.push(LumpInfo { index, name, offset: dir.offset, size: dir.size });
+            }
+        }
 
-            map_vertexes: Vec::new(),
-            map_data,
+        lumps
+    }
+}
 
-            player,
-            //bsp: BSP { map_data, root_node_id  }
+impl WAD {
+    // The sprite lump a thing is drawn with when facing the viewer, e.g.
+    // POSSA1, or POSSA0 for sprites without rotations. Lumps shared by two
+    // frames like TROOA2A8 match too.
+    pub fn thing_sprite_lump(&self, t_type: i16) -> Option<LumpInfo> {
+        let (sprite, frame) = thing_sprite(t_type)?;
+        let prefix = format!("{}{}", sprite, frame);
+
+        self.lumps_between("S_START", "S_END")
+            .into_iter()
+            .filter(|lump| lump.name.starts_with(&prefix))
+            .find(|lump| matches!(lump.name.as_bytes().get(5), Some(b'0' | b'1')))
+    }
+}
+
+impl WAD {
+    // Lumps between the F_START/F_END (or a PWAD's FF_START/FF_END) markers,
+    // without the F1_START... sub-markers.
+    pub fn flat_names(&self) -> Vec<String> {
+        let mut in_flats = false;
+        let mut names = Vec::new();
+
+        for dir in self.directory.iter() {
+            let name = dir.name();
+
+            match name.as_str() {
+                "F_START" | "FF_START" => in_flats = true,
+                "F_END" | "FF_END" => in_flats = false,
+                _ if in_flats && dir.size > 0 => names.push(name),
+                _ => {}
+            }
+        }
+
+        names
+    }
+
+    // Names of the composite textures in TEXTURE1 and, if present, TEXTURE2.
+    pub fn texture_names(&mut self) -> Result<Vec<String>, WadError> {
+        Ok(self.texture_defs()?.into_iter().map(|def| def.name).collect())
+    }
+
+    // The composite textures in TEXTURE1 and, if present, TEXTURE2.
+    pub fn texture_defs(&mut self) -> Result<Vec<TextureDef>, WadError> {
+        let mut defs = Self::read_texture_defs(&self.read_lump_bytes("TEXTURE1")?)
+            .map_err(|_| WadError::InvalidLump("TEXTURE1".to_string()))?;
+
+        if self.find_lump("TEXTURE2").is_some() {
+            defs.extend(
+                Self::read_texture_defs(&self.read_lump_bytes("TEXTURE2")?)
+                    .map_err(|_| WadError::InvalidLump("TEXTURE2".to_string()))?,
+            );
+        }
+
+        Ok(defs)
+    }
+
+    fn read_texture_defs(bytes: &[u8]) -> io::Result<Vec<TextureDef>> {
+        let mut reader = LumpReader::new(bytes);
+        let count = reader.read_i32()?.max(0) as usize;
+
+        let mut defs = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let offset = reader.read_i32()?.max(0) as usize;
+            let mut entry = LumpReader::new(bytes.get(offset..).unwrap_or(&[]));
+
+            let name = Self::lump_name(entry.read_bytes(8)?);
+
+            // The masked flag and the column directory are unused.
+            entry.read_i32()?;
+            let width = entry.read_u16()?;
+            let height = entry.read_u16()?;
+            entry.read_i32()?;
+
+            let patch_count = entry.read_i16()?.max(0) as usize;
+            let mut patches = Vec::with_capacity(patch_count);
+
+            for _ in 0..patch_count {
+                let origin_x = entry.read_i16()?;
+                let origin_y = entry.read_i16()?;
+                let patch = entry.read_u16()?;
+
+                // Step dir and colormap, unused too.
+                entry.read_i32()?;
+
+                patches.push(TexturePatch { origin_x, origin_y, patch });
+            }
+
+            defs.push(TextureDef { name, width, height, patches });
+        }
+
+        Ok(defs)
+    }
+
+    // PNAMES, the patch lumps `TexturePatch::patch` indexes into.
+    pub fn patch_names(&mut self) -> Result<Vec<String>, WadError> {
+        let bytes = self.read_lump_bytes("PNAMES")?;
+        let mut reader = LumpReader::new(&bytes);
 
+        let read = |reader: &mut LumpReader| -> io::Result<Vec<String>> {
+            let count = reader.read_i32()?.max(0) as usize;
 
-            //engine: engine,
+            (0..count).map(|_| Ok(Self::lump_name(reader.read_bytes(8)?))).collect()
         };
 
-        let mut vertexes = Vec::new();
-        viewer.calc_map_bounds();
+        read(&mut reader).map_err(|_| WadError::InvalidLump("PNAMES".to_string()))
+    }
+
+    // Draws the texture's patches over each other in order, the cells none
+    // of them cover stay transparent.
+    pub fn composite_texture(
+        &mut self,
+        def: &TextureDef,
+        patch_names: &[String],
+    ) -> Result<Picture, WadError> {
+        let (width, height) = (def.width as usize, def.height as usize);
+        let mut pixels = vec![None; width * height];
+
+        for texture_patch in &def.patches {
+            let name = patch_names
+                .get(texture_patch.patch as usize)
+                .ok_or_else(|| WadError::InvalidLump(def.name.clone()))?;
+            let patch = self.picture(name)?;
+
+            for y in 0..patch.height as usize {
+                for x in 0..patch.width as usize {
+                    let tx = texture_patch.origin_x as isize + x as isize;
+                    let ty = texture_patch.origin_y as isize + y as isize;
+
+                    if tx < 0 || ty < 0 || tx as usize >= width || ty as usize >= height {
+                        continue;
+                    }
+
+                    if let Some(index) = patch.pixels[y * patch.width as usize + x] {
+                        pixels[ty as usize * width + tx as usize] = Some(index);
+                    }
+                }
+            }
+        }
+
+        Ok(Picture { width: def.width, height: def.height, left_offset: 0, top_offset: 0, pixels })
+    }
+}
+
+// A patch of a composite texture, placed with its top left at the origin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TexturePatch {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    // Index into PNAMES.
+    pub patch: u16,
+}
+
+// A TEXTURE1/TEXTURE2 entry, the wall textures sidedefs name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextureDef {
+    pub name: String,
+    pub width: u16,
+    pub height: u16,
+    pub patches: Vec<TexturePatch>,
+}
+
+// One of PLAYPAL's palettes, flats and pictures index into it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [(u8, u8, u8); 256],
+}
+
+impl Palette {
+    pub const SIZE: usize = 256 * 3;
 
-        for vertex in m_vertexes.iter() {
-            let x = viewer.traslate_vertex_x(vertex.x as f32);
-            let y = viewer.traslate_vertex_y(vertex.y as f32);
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let bytes = LumpReader::new(bytes).read_bytes(Self::SIZE)?;
+        let mut colors = [(0, 0, 0); 256];
 
-            vertexes.push(Vector2f::new(x, y));
+        for (color, rgb) in colors.iter_mut().zip(bytes.chunks_exact(3)) {
+            *color = (rgb[0], rgb[1], rgb[2]);
         }
 
-        viewer.map_vertexes = vertexes.clone();
-        viewer
+        Ok(Self { colors })
     }
 }
 
-#[test]
-fn test_map_viewer() {
-    let mut map_data = WAD::new("/home/flames/Downloads/DOOM.wad").unwrap();
-    let _ = map_data.change_map("E1M8");
+// A patch, sprite or other picture lump: columns of posts, the gaps between
+// them are transparent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Picture {
+    pub width: u16,
+    pub height: u16,
+    pub left_offset: i16,
+    pub top_offset: i16,
+    // Row-major palette indexes, `None` where transparent.
+    pub pixels: Vec<Option<u8>>,
+}
 
-    let root_node_id = map_data.nodes.len() - 1;
+impl Picture {
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = LumpReader::new(bytes);
 
-    let mut map_viewer = MapViewer::new(320.0 * 4.0, 200.0 * 4.0, &map_data);
-    map_viewer.run(
-        &BSP { map_data: &map_data, root_node_id }
-    );
+        let width = reader.read_u16()?;
+        let height = reader.read_u16()?;
+        let left_offset = reader.read_i16()?;
+        let top_offset = reader.read_i16()?;
+
+        let mut pixels = vec![None; width as usize * height as usize];
+
+        for x in 0..width as usize {
+            let offset = reader.read_u32()? as usize;
+            let mut column = LumpReader::new(bytes.get(offset..).unwrap_or(&[]));
+
+            // Tall patches give a top at or above the last post's relative to it.
+            let mut last_top = None;
+
+            loop {
+                let top_delta = column.read_u8()?;
+
+                if top_delta == 0xFF {
+                    break;
+                }
+
+                let top = match last_top {
+                    Some(last) if top_delta as usize <= last => last + top_delta as usize,
+                    _ => top_delta as usize,
+                };
+                last_top = Some(top);
+
+                let length = column.read_u8()? as usize;
+
+                // Both ends of the post are padded with a byte.
+                column.read_u8()?;
+                let post = column.read_bytes(length)?;
+                column.read_u8()?;
+
+                for (y, &index) in (top..height as usize).zip(post) {
+                    pixels[y * width as usize + x] = Some(index);
+                }
+            }
+        }
+
+        Ok(Self { width, height, left_offset, top_offset, pixels })
+    }
+
+    // Texture space lookup: `u` wraps around the width like wall textures
+    // do, `v` is clamped to the height. `None` where transparent.
+    pub fn sample(&self, u: i32, v: i32) -> Option<u8> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        let x = u.rem_euclid(self.width as i32) as usize;
+        let y = v.clamp(0, self.height as i32 - 1) as usize;
+
+        self.pixels[y * self.width as usize + x]
+    }
+
+    // Row-major RGBA8, transparent pixels have a zero alpha.
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| match pixel {
+                Some(index) => {
+                    let (r, g, b) = palette.colors[*index as usize];
+                    [r, g, b, 255]
+                }
+                None => [0, 0, 0, 0],
+            })
+            .collect()
+    }
+}
+
+// Row-major RGBA8, flats are always opaque.
+pub fn flat_to_rgba(flat: &[u8; WAD::FLAT_SIZE], palette: &Palette) -> Vec<u8> {
+    flat.iter()
+        .flat_map(|&index| {
+            let (r, g, b) = palette.colors[index as usize];
+            [r, g, b, 255]
+        })
+        .collect()
+}
+
+impl WAD {
+    pub fn picture(&mut self, name: &str) -> Result<Picture, WadError> {
+        let bytes = self.read_lump_bytes(name)?;
+
+        Picture::parse(&bytes).map_err(|_| WadError::InvalidLump(name.to_string()))
+    }
+}
+
+impl WAD {
+    pub const FLAT_SIZE: usize = 64 * 64;
+
+    // PLAYPAL holds 14 palettes, 0 is the normal one and the rest are the
+    // damage, pickup and radiation suit tints.
+    pub fn palette(&mut self, index: usize) -> Result<Palette, WadError> {
+        let bytes = self.read_lump_bytes("PLAYPAL")?;
+
+        bytes
+            .get(index * Palette::SIZE..)
+            .and_then(|bytes| Palette::parse(bytes).ok())
+            .ok_or_else(|| WadError::InvalidLump("PLAYPAL".to_string()))
+    }
+
+    // A 64x64 row-major block of palette indexes.
+    pub fn flat(&mut self, name: &str) -> Result<[u8; Self::FLAT_SIZE], WadError> {
+        let bytes = self.read_lump_bytes(name)?;
+
+        bytes
+            .get(..Self::FLAT_SIZE)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| WadError::InvalidLump(name.to_string()))
+    }
+}
+
+impl Picture {
+    // Flats as 64x64 pictures without transparent pixels.
+    pub fn from_flat(flat: &[u8; WAD::FLAT_SIZE]) -> Self {
+        Self {
+            width: 64,
+            height: 64,
+            left_offset: 0,
+            top_offset: 0,
+            pixels: flat.iter().map(|&index| Some(index)).collect(),
+        }
+    }
+}
+
+// - - -
+#[derive(Clone, Debug)]
+pub struct CachedTexture {
+    pub picture: Picture,
+    // `picture` with the cache's palette applied, see `Picture::to_rgba`.
+    pub rgba: Vec<u8>,
+}
+
+// Wall textures, flats and pictures by name, each decoded the first time
+// it's asked for.
+#[derive(Clone, Debug)]
+pub struct TextureCache {
+    palette: Palette,
+    flat_names: HashSet<String>,
+    texture_defs: HashMap<String, TextureDef>,
+    patch_names: Vec<String>,
+    textures: HashMap<String, CachedTexture>,
+    decodes: usize,
+}
+
+impl TextureCache {
+    // Without any flats or composite textures, every name is a picture lump.
+    pub fn new(palette: Palette) -> Self {
+        Self {
+            palette,
+            flat_names: HashSet::new(),
+            texture_defs: HashMap::new(),
+            patch_names: Vec::new(),
+            textures: HashMap::new(),
+            decodes: 0,
+        }
+    }
+
+    // With PLAYPAL's first palette, the WAD's flats and, if it has them,
+    // its TEXTURE1/PNAMES textures.
+    pub fn from_wad(wad: &mut WAD) -> Result<Self, WadError> {
+        let mut cache = Self::new(wad.palette(0)?);
+
+        cache.flat_names = wad.flat_names().into_iter().collect();
+
+        if wad.find_lump("TEXTURE1").is_some() {
+            cache.texture_defs =
+                wad.texture_defs()?.into_iter().map(|def| (def.name.clone(), def)).collect();
+            cache.patch_names = wad.patch_names()?;
+        }
+
+        Ok(cache)
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    // Lumps between F_START and F_END are read as flats, TEXTURE1/TEXTURE2
+    // names are built from their patches and the rest read as pictures.
+    pub fn get_or_decode(&mut self, wad: &mut WAD, name: &str) -> Result<&CachedTexture, WadError> {
+        if !self.textures.contains_key(name) {
+            let picture = match self.texture_defs.get(name) {
+                _ if self.flat_names.contains(name) => Picture::from_flat(&wad.flat(name)?),
+                Some(def) => wad.composite_texture(def, &self.patch_names)?,
+                None => wad.picture(name)?,
+            };
+
+            let rgba = picture.to_rgba(&self.palette);
+
+            self.textures.insert(name.to_string(), CachedTexture { picture, rgba });
+            self.decodes += 1;
+        }
+
+        Ok(&self.textures[name])
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CachedTexture> {
+        self.textures.get(name)
+    }
+
+    // How many lumps were decoded, each at most once.
+    pub fn decode_count(&self) -> usize {
+        self.decodes
+    }
+}
+
+// Start and end of the animation cycles hardcoded in DOOM, every flat or
+// texture between the two in WAD order is a frame.
+const ANIMATED_FLATS: [(&str, &str); 9] = [
+    ("NUKAGE1", "NUKAGE3"),
+    ("FWATER1", "FWATER4"),
+    ("SWATER1", "SWATER4"),
+    ("LAVA1", "LAVA4"),
+    ("BLOOD1", "BLOOD3"),
+    ("RROCK05", "RROCK08"),
+    ("SLIME01", "SLIME04"),
+    ("SLIME05", "SLIME08"),
+    ("SLIME09", "SLIME12"),
+];
+
+const ANIMATED_TEXTURES: [(&str, &str); 13] = [
+    ("BLODGR1", "BLODGR4"),
+    ("SLADRIP1", "SLADRIP3"),
+    ("BLODRIP1", "BLODRIP4"),
+    ("FIREWALA", "FIREWALL"),
+    ("GSTFONT1", "GSTFONT3"),
+    ("FIRELAVA", "FIRELAV3"),
+    ("FIREMAG1", "FIREMAG3"),
+    ("FIREBLU1", "FIREBLU2"),
+    ("ROCKRED1", "ROCKRED3"),
+    ("BFALL1", "BFALL4"),
+    ("SFALL1", "SFALL4"),
+    ("WFALL1", "WFALL4"),
+    ("DBRAIN1", "DBRAIN4"),
+];
+
+impl WAD {
+    // Like DOOM, cycles missing either end are skipped.
+    fn animation_groups(names: &[String], cycles: &[(String, String)]) -> Vec<Vec<String>> {
+        cycles
+            .iter()
+            .filter_map(|(start, end)| {
+                let first = names.iter().position(|name| name == start)?;
+                let last = names.iter().position(|name| name == end)?;
+
+                (first < last).then(|| names[first..=last].to_vec())
+            })
+            .collect()
+    }
+
+    // The cycles of one kind from ANIMATED, or the hardcoded ones without it.
+    fn animation_cycles(&mut self, textures: bool) -> Result<Vec<(String, String)>, WadError> {
+        if self.find_lump("ANIMATED").is_some() {
+            return Ok(self
+                .animated_boom()?
+                .into_iter()
+                .filter(|anim| anim.is_texture == textures)
+                .map(|anim| (anim.first, anim.last))
+                .collect());
+        }
+
+        let cycles: &[(&str, &str)] = match textures {
+            true => &ANIMATED_TEXTURES,
+            false => &ANIMATED_FLATS,
+        };
+
+        Ok(cycles.iter().map(|(start, end)| (start.to_string(), end.to_string())).collect())
+    }
+
+    pub fn animated_flats(&mut self) -> Result<Vec<Vec<String>>, WadError> {
+        let cycles = self.animation_cycles(false)?;
+
+        Ok(Self::animation_groups(&self.flat_names(), &cycles))
+    }
+
+    pub fn animated_textures(&mut self) -> Result<Vec<Vec<String>>, WadError> {
+        let cycles = self.animation_cycles(true)?;
+
+        Ok(Self::animation_groups(&self.texture_names()?, &cycles))
+    }
+}
+
+// A texture or flat cycle from Boom's ANIMATED lump.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnimDef {
+    pub is_texture: bool,
+    pub first: String,
+    pub last: String,
+    // Tics per frame.
+    pub speed: u32,
+}
+
+// A pair of switch textures from Boom's SWITCHES lump.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SwitchDef {
+    pub off: String,
+    pub on: String,
+    // 1 for the shareware, 2 for the registered and 3 for the commercial IWAD.
+    pub episode: u16,
+}
+
+impl WAD {
+    // Boom stores names as 9 bytes, zero terminated.
+    fn read_boom_name(reader: &mut LumpReader) -> io::Result<String> {
+        let bytes = reader.read_bytes(9)?;
+
+        Ok(Self::lump_name(&bytes[..8]))
+    }
+
+    // 23 byte records, ended by a type of 0xFF.
+    pub fn animated_boom(&mut self) -> Result<Vec<AnimDef>, WadError> {
+        let bytes = self.read_lump_bytes("ANIMATED")?;
+        let invalid = |_| WadError::InvalidLump("ANIMATED".to_string());
+
+        let mut reader = LumpReader::new(&bytes);
+        let mut anims = Vec::new();
+
+        loop {
+            let kind = reader.read_u8().map_err(invalid)?;
+
+            if kind == 0xFF {
+                break;
+            }
+
+            let last = Self::read_boom_name(&mut reader).map_err(invalid)?;
+            let first = Self::read_boom_name(&mut reader).map_err(invalid)?;
+            let speed = reader.read_u32().map_err(invalid)?;
+
+            // Bit 0 is set for textures, the rest are flags like Boom's
+            // "allow decals".
+            anims.push(AnimDef { is_texture: kind & 1 != 0, first, last, speed });
+        }
+
+        Ok(anims)
+    }
+
+    // 20 byte records, ended by an episode of 0.
+    pub fn switches_boom(&mut self) -> Result<Vec<SwitchDef>, WadError> {
+        let bytes = self.read_lump_bytes("SWITCHES")?;
+        let invalid = |_| WadError::InvalidLump("SWITCHES".to_string());
+
+        let mut reader = LumpReader::new(&bytes);
+        let mut switches = Vec::new();
+
+        loop {
+            let off = Self::read_boom_name(&mut reader).map_err(invalid)?;
+            let on = Self::read_boom_name(&mut reader).map_err(invalid)?;
+            let episode = reader.read_u16().map_err(invalid)?;
+
+            if episode == 0 {
+                break;
+            }
+
+            switches.push(SwitchDef { off, on, episode });
+        }
+
+        Ok(switches)
+    }
+}
+
+// - - -
+// One player's input for a single game tic (1/35th of a second).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TicCmd {
+    pub forward_move: i8,
+    pub side_move: i8,
+    pub angle_turn: i16,
+    pub buttons: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct Demo {
+    pub version: u8,
+    pub skill: u8,
+    pub episode: u8,
+    pub map: u8,
+    pub deathmatch: bool,
+    pub respawn: bool,
+    pub fast: bool,
+    pub no_monsters: bool,
+    pub console_player: u8,
+    pub players: [bool; 4],
+
+    // One command per player in game for every tic, interleaved in player order.
+    pub tics: Vec<TicCmd>,
+}
+
+impl Demo {
+    const DEMO_END: u8 = 0x80;
+
+    pub fn player_count(&self) -> usize {
+        self.players.iter().filter(|&&in_game| in_game).count()
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = LumpReader::new(bytes);
+
+        let first = reader.read_u8()?;
+
+        // Demos older than v1.4 have no version byte and start with the skill.
+        let (version, skill) = match first {
+            0..=4 => (0, first),
+            _ => (first, reader.read_u8()?),
+        };
+
+        let episode = reader.read_u8()?;
+        let map = reader.read_u8()?;
+
+        let mut demo = Self {
+            version,
+            skill,
+            episode,
+            map,
+            deathmatch: false,
+            respawn: false,
+            fast: false,
+            no_monsters: false,
+            console_player: 0,
+            players: [false; 4],
+            tics: Vec::new(),
+        };
+
+        if version != 0 {
+            demo.deathmatch = reader.read_u8()? != 0;
+            demo.respawn = reader.read_u8()? != 0;
+            demo.fast = reader.read_u8()? != 0;
+            demo.no_monsters = reader.read_u8()? != 0;
+            demo.console_player = reader.read_u8()?;
+        }
+
+        for in_game in demo.players.iter_mut() {
+            *in_game = reader.read_u8()? != 0;
+        }
+
+        let player_count = demo.player_count();
+
+        loop {
+            for _ in 0..player_count {
+                let forward_move = reader.read_u8()?;
+
+                if forward_move == Self::DEMO_END {
+                    return Ok(demo);
+                }
+
+                let side_move = reader.read_u8()?;
+                let angle_turn = reader.read_u8()?;
+                let buttons = reader.read_u8()?;
+
+                demo.tics.push(TicCmd {
+                    forward_move: forward_move as i8,
+                    side_move: side_move as i8,
+                    // Only the high byte of the turn is recorded.
+                    angle_turn: ((angle_turn as u16) << 8) as i16,
+                    buttons,
+                });
+            }
+
+            if player_count == 0 {
+                return Ok(demo);
+            }
+        }
+    }
+}
+
+impl WAD {
+    pub fn demo(&mut self, name: &str) -> Result<Demo, WadError> {
+        let bytes = self.read_lump_bytes(name)?;
+
+        Demo::parse(&bytes).map_err(|_| WadError::InvalidLump(name.to_string()))
+    }
+}
+
+// - - -
+// DeHackEd patch fields by section, keyed by thing/frame/weapon number and
+// then by field name as written in the patch (e.g. "Hit points").
+#[derive(Clone, Debug, Default)]
+pub struct Dehacked {
+    pub things: BTreeMap<u32, BTreeMap<String, String>>,
+    pub frames: BTreeMap<u32, BTreeMap<String, String>>,
+    pub weapons: BTreeMap<u32, BTreeMap<String, String>>,
+}
+
+impl Dehacked {
+    pub fn parse(text: &str) -> Self {
+        let mut dehacked = Self::default();
+
+        // Fields of sections other than things, frames and weapons are skipped.
+        let mut section: Option<&mut BTreeMap<String, String>> = None;
+
+        for line in text.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                if let Some(fields) = section.as_mut() {
+                    fields.insert(key.trim().to_string(), value.trim().to_string());
+                }
+
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+
+            let kind = words.next().unwrap_or_default().to_ascii_lowercase();
+            let number = words.next().and_then(|word| word.parse::<u32>().ok());
+
+            section = match (kind.as_str(), number) {
+                ("thing", Some(number)) => Some(dehacked.things.entry(number).or_default()),
+                ("frame", Some(number)) => Some(dehacked.frames.entry(number).or_default()),
+                ("weapon", Some(number)) => Some(dehacked.weapons.entry(number).or_default()),
+                _ => None,
+            };
+        }
+
+        dehacked
+    }
+}
+
+impl WAD {
+    pub fn dehacked(&mut self) -> Result<String, WadError> {
+        let bytes = self.read_lump_bytes("DEHACKED")?;
+
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+// - - -
+// MUS is DOOM's compact MIDI variant: 16 channels (15 is percussion), events
+// with a "last" bit followed by a variable-length delay in 140Hz ticks.
+const MUS_SIGNATURE: &[u8] = b"MUS\x1A";
+const MIDI_SIGNATURE: &[u8] = b"MThd";
+
+// MUS controllers 1-9 and system events 10-14, indexed by MUS number.
+const MUS_TO_MIDI_CONTROLLERS: [u8; 15] = [0, 0, 1, 7, 10, 11, 91, 93, 64, 67, 120, 123, 126, 127, 121];
+
+fn midi_write_delay(track: &mut Vec<u8>, delay: u32) {
+    let mut buffer = delay & 0x7F;
+    let mut delay = delay >> 7;
+
+    while delay > 0 {
+        buffer = (buffer << 8) | 0x80 | (delay & 0x7F);
+        delay >>= 7;
+    }
+
+    loop {
+        track.push(buffer as u8);
+
+        if buffer & 0x80 == 0 {
+            break;
+        }
+
+        buffer >>= 8;
+    }
+}
+
+// The percussion channel moves from 15 to 9, channels 9-14 shift up by one.
+fn mus_to_midi_channel(channel: u8) -> u8 {
+    match channel {
+        15 => 9,
+        9..=14 => channel + 1,
+        _ => channel,
+    }
+}
+
+// Converts a MUS lump into a single-track (type 0) MIDI file. Lumps that are
+// already MIDI are returned unchanged, anything else yields an empty buffer.
+pub fn mus_to_midi(mus: &[u8]) -> Vec<u8> {
+    if mus.starts_with(MIDI_SIGNATURE) {
+        return mus.to_vec();
+    }
+
+    if !mus.starts_with(MUS_SIGNATURE) || mus.len() < 16 {
+        return Vec::new();
+    }
+
+    let score_start = u16::from_le_bytes([mus[6], mus[7]]) as usize;
+    let mut reader = LumpReader::new(mus.get(score_start..).unwrap_or_default());
+
+    // 70 ticks per quarter note at 500000us per quarter is 140 ticks a second.
+    let mut track = vec![0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20];
+
+    let mut velocities = [127u8; 16];
+    let mut delay = 0;
+
+    while let Ok(descriptor) = reader.read_u8() {
+        let channel = mus_to_midi_channel(descriptor & 0x0F);
+        let event = (descriptor >> 4) & 0x07;
+
+        let mut message: Vec<u8> = match event {
+            // Release note.
+            0 => match reader.read_u8() {
+                Ok(note) => vec![0x80 | channel, note & 0x7F, 0x40],
+                Err(_) => break,
+            },
+            // Play note, the high bit means a new volume follows.
+            1 => match reader.read_u8() {
+                Ok(note) => {
+                    if note & 0x80 != 0 {
+                        match reader.read_u8() {
+                            Ok(volume) => velocities[channel as usize] = volume.min(127),
+                            Err(_) => break,
+                        }
+                    }
+
+                    vec![0x90 | channel, note & 0x7F, velocities[channel as usize]]
+                }
+                Err(_) => break,
+            },
+            // Pitch bend, 128 is centered.
+            2 => match reader.read_u8() {
+                Ok(bend) => {
+                    let bend = bend as u16 * 64;
+
+                    vec![0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8]
+                }
+                Err(_) => break,
+            },
+            // System event.
+            3 => match reader.read_u8() {
+                Ok(controller @ 10..=14) => {
+                    vec![0xB0 | channel, MUS_TO_MIDI_CONTROLLERS[controller as usize], 0]
+                }
+                Ok(_) => Vec::new(),
+                Err(_) => break,
+            },
+            // Controller change, controller 0 is an instrument change.
+            4 => match (reader.read_u8(), reader.read_u8()) {
+                (Ok(0), Ok(value)) => vec![0xC0 | channel, value.min(127)],
+                (Ok(controller @ 1..=9), Ok(value)) => vec![
+                    0xB0 | channel,
+                    MUS_TO_MIDI_CONTROLLERS[controller as usize],
+                    value.min(127),
+                ],
+                (Ok(_), Ok(_)) => Vec::new(),
+                _ => break,
+            },
+            // End of measure.
+            5 => Vec::new(),
+            // Score end.
+            6 => break,
+            _ => match reader.read_u8() {
+                Ok(_) => Vec::new(),
+                Err(_) => break,
+            },
+        };
+
+        if !message.is_empty() {
+            midi_write_delay(&mut track, delay);
+            track.append(&mut message);
+
+            delay = 0;
+        }
+
+        if descriptor & 0x80 != 0 {
+            let mut time = 0u32;
+
+            while let Ok(byte) = reader.read_u8() {
+                time = time.saturating_mul(128).saturating_add((byte & 0x7F) as u32);
+
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+
+            delay = delay.saturating_add(time);
+        }
+    }
+
+    midi_write_delay(&mut track, delay);
+    track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+    let mut midi = MIDI_SIGNATURE.to_vec();
+    midi.extend_from_slice(&6u32.to_be_bytes());
+    midi.extend_from_slice(&0u16.to_be_bytes());
+    midi.extend_from_slice(&1u16.to_be_bytes());
+    midi.extend_from_slice(&70u16.to_be_bytes());
+
+    midi.extend_from_slice(b"MTrk");
+    midi.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    midi.append(&mut track);
+
+    midi
+}
+
+impl WAD {
+    pub fn music(&mut self, name: &str) -> Result<Vec<u8>, WadError> {
+        self.read_lump_bytes(name)
+    }
+}
+
+// - - -
+// Unsigned 8-bit mono PCM from a DMX sound lump.
+#[derive(Clone, Debug)]
+pub struct Sound {
+    pub sample_rate: u16,
+    pub samples: Vec<u8>,
+}
+
+impl Sound {
+    const DMX_FORMAT: u16 = 3;
+
+    // DMX pads the samples with 16 bytes on each side.
+    const PADDING: usize = 16;
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = LumpReader::new(bytes);
+
+        let format = reader.read_u16()?;
+        let sample_rate = reader.read_u16()?;
+        let len = reader.read_u32()? as usize;
+
+        if format != Self::DMX_FORMAT || len < Self::PADDING * 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a DMX sound.",
+            ));
+        }
+
+        let samples = reader.read_bytes(len)?;
+
+        Ok(Self {
+            sample_rate,
+            samples: samples[Self::PADDING..len - Self::PADDING].to_vec(),
+        })
+    }
+}
+
+impl WAD {
+    pub fn sound(&mut self, name: &str) -> Result<Sound, WadError> {
+        let bytes = self.read_lump_bytes(name)?;
+
+        Sound::parse(&bytes).map_err(|_| WadError::InvalidLump(name.to_string()))
+    }
+}
+
+// - - -
+// Code page 437, what the 80x25 text mode draws each byte as.
+const CP437: [char; 256] = [
+    ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', ' ',
+];
+
+// The 16 CGA colors, in attribute order.
+const DOS_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (0, 0, 170),
+    (0, 170, 0),
+    (0, 170, 170),
+    (170, 0, 0),
+    (170, 0, 170),
+    (170, 85, 0),
+    (170, 170, 170),
+    (85, 85, 85),
+    (85, 85, 255),
+    (85, 255, 85),
+    (85, 255, 255),
+    (255, 85, 85),
+    (255, 85, 255),
+    (255, 255, 85),
+    (255, 255, 255),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextCell {
+    pub ch: char,
+    pub fg: (u8, u8, u8),
+    pub bg: (u8, u8, u8),
+    pub blink: bool,
+}
+
+impl TextCell {
+    // A character byte followed by its attribute byte: foreground in the low
+    // nibble, background in the next three bits and blink in the top one.
+    pub fn from_bytes(ch: u8, attribute: u8) -> Self {
+        Self {
+            ch: CP437[ch as usize],
+            fg: DOS_COLORS[(attribute & 0x0F) as usize],
+            bg: DOS_COLORS[((attribute >> 4) & 0x07) as usize],
+            blink: attribute & 0x80 != 0,
+        }
+    }
+}
+
+// A text mode screen dump, such as the ENDOOM lump, in row-major order.
+#[derive(Clone, Debug)]
+pub struct TextScreen {
+    pub cells: Vec<TextCell>,
+}
+
+impl TextScreen {
+    pub const WIDTH: usize = 80;
+    pub const HEIGHT: usize = 25;
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let bytes = LumpReader::new(bytes).read_bytes(Self::WIDTH * Self::HEIGHT * 2)?;
+
+        Ok(Self {
+            cells: bytes
+                .chunks_exact(2)
+                .map(|pair| TextCell::from_bytes(pair[0], pair[1]))
+                .collect(),
+        })
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Option<&TextCell> {
+        match x < Self::WIDTH {
+            true => self.cells.get(y * Self::WIDTH + x),
+            false => None,
+        }
+    }
+
+    // 24-bit color escape codes, one line per row.
+    pub fn to_ansi(&self) -> String {
+        let mut ansi = String::new();
+
+        for row in self.cells.chunks(Self::WIDTH) {
+            let mut last = None;
+
+            for cell in row {
+                let attributes = (cell.fg, cell.bg, cell.blink);
+
+                if last != Some(attributes) {
+                    let (fg, bg) = (cell.fg, cell.bg);
+
+                    ansi += &format!(
+                        "\x1b[0;{}38;2;{};{};{};48;2;{};{};{}m",
+                        if cell.blink { "5;" } else { "" },
+                        fg.0, fg.1, fg.2,
+                        bg.0, bg.1, bg.2,
+                    );
+
+                    last = Some(attributes);
+                }
+
+                ansi.push(cell.ch);
+            }
+
+            ansi += "\x1b[0m\n";
+        }
+
+        ansi
+    }
+}
+
+impl WAD {
+    pub fn endoom(&mut self) -> Result<TextScreen, WadError> {
+        let bytes = self.read_lump_bytes("ENDOOM")?;
+
+        TextScreen::parse(&bytes).map_err(|_| WadError::InvalidLump("ENDOOM".to_string()))
+    }
+}
+
+// - - -
+// One of the two OPL2 operators of a voice, register values as DMX stores them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OplOperator {
+    // Tremolo, vibrato, sustain and KSR bits plus the frequency multiplier.
+    pub characteristic: u8,
+    pub attack: u8,
+    pub sustain: u8,
+    pub waveform: u8,
+    // Key scale level.
+    pub scale: u8,
+    pub level: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GenMidiVoice {
+    pub modulator: OplOperator,
+    // Feedback and connection bits.
+    pub feedback: u8,
+    pub carrier: OplOperator,
+    // Semitones added to the note played.
+    pub base_note_offset: i16,
+}
+
+// A GENMIDI instrument: the 128 General MIDI programs, then the 47 percussion keys.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GenMidiInstrument {
+    pub flags: u16,
+    pub fine_tuning: u8,
+    // The note played instead of the requested one, for fixed pitch instruments.
+    pub fixed_note: u8,
+    pub voices: [GenMidiVoice; 2],
+    // Empty when the lump leaves the name table out.
+    pub name: String,
+}
+
+impl GenMidiInstrument {
+    pub const HEADER: &'static [u8; 8] = b"#OPL_II#";
+    pub const COUNT: usize = 175;
+
+    pub fn is_fixed_pitch(&self) -> bool {
+        self.flags & 0x0001 != 0
+    }
+
+    // The second voice is only played when this is set.
+    pub fn is_double_voice(&self) -> bool {
+        self.flags & 0x0004 != 0
+    }
+
+    fn read_operator(reader: &mut LumpReader) -> io::Result<OplOperator> {
+        Ok(OplOperator {
+            characteristic: reader.read_u8()?,
+            attack: reader.read_u8()?,
+            sustain: reader.read_u8()?,
+            waveform: reader.read_u8()?,
+            scale: reader.read_u8()?,
+            level: reader.read_u8()?,
+        })
+    }
+
+    fn read_voice(reader: &mut LumpReader) -> io::Result<GenMidiVoice> {
+        let modulator = Self::read_operator(reader)?;
+        let feedback = reader.read_u8()?;
+        let carrier = Self::read_operator(reader)?;
+
+        // Padding byte.
+        reader.read_u8()?;
+
+        Ok(GenMidiVoice { modulator, feedback, carrier, base_note_offset: reader.read_i16()? })
+    }
+
+    fn write_operator(operator: &OplOperator, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[
+            operator.characteristic,
+            operator.attack,
+            operator.sustain,
+            operator.waveform,
+            operator.scale,
+            operator.level,
+        ]);
+    }
+
+    fn write_voice(voice: &GenMidiVoice, out: &mut Vec<u8>) {
+        Self::write_operator(&voice.modulator, out);
+        out.push(voice.feedback);
+        Self::write_operator(&voice.carrier, out);
+
+        // Padding byte.
+        out.push(0);
+        out.extend_from_slice(&voice.base_note_offset.to_le_bytes());
+    }
+}
+
+impl Record for GenMidiInstrument {
+    const SIZE: usize = 36;
+
+    fn read(reader: &mut LumpReader) -> io::Result<Self> {
+        Ok(GenMidiInstrument {
+            flags: reader.read_u16()?,
+            fine_tuning: reader.read_u8()?,
+            fixed_note: reader.read_u8()?,
+            voices: [Self::read_voice(reader)?, Self::read_voice(reader)?],
+            name: String::new(),
+        })
+    }
+
+    // The name isn't part of the record, it's in the table after them.
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        out.extend_from_slice(&[self.fine_tuning, self.fixed_note]);
+
+        for voice in &self.voices {
+            Self::write_voice(voice, out);
+        }
+    }
+}
+
+impl WAD {
+    // The header, 175 instruments and then their 32 byte names.
+    pub fn genmidi(&mut self) -> Result<Vec<GenMidiInstrument>, WadError> {
+        let bytes = self.read_lump_bytes("GENMIDI")?;
+        let invalid = |_| WadError::InvalidLump("GENMIDI".to_string());
+
+        let mut reader = LumpReader::new(&bytes);
+
+        if reader.read_bytes(8).map_err(invalid)? != GenMidiInstrument::HEADER {
+            return Err(WadError::InvalidLump("GENMIDI".to_string()));
+        }
+
+        let records = reader
+            .read_bytes(GenMidiInstrument::COUNT * GenMidiInstrument::SIZE)
+            .map_err(invalid)?;
+
+        let mut instruments = parse_records::<GenMidiInstrument>(records).map_err(invalid)?;
+
+        if let Ok(names) = reader.read_bytes(GenMidiInstrument::COUNT * 32) {
+            for (instrument, name) in instruments.iter_mut().zip(names.chunks_exact(32)) {
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+
+                instrument.name = String::from_utf8_lossy(&name[..end]).into_owned();
+            }
+        }
+
+        Ok(instruments)
+    }
+}
+
+// - - -
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapStats {
+    pub name: String,
+    pub things: usize,
+    pub line_defs: usize,
+    pub sectors: usize,
+    pub monsters: usize,
+    pub secrets: usize,
+}
+
+impl MapStats {
+    // Stats of the map currently loaded in `map_data`.
+    pub fn new(name: &str, map_data: &Map) -> Self {
+        Self {
+            name: name.to_string(),
+            things: map_data.things.len(),
+            line_defs: map_data.line_defs.len(),
+            sectors: map_data.sectors.len(),
+            monsters: map_data.monster_count(),
+            secrets: map_data.secret_sectors(),
+        }
+    }
+}
+
+impl Map {
+    const SECRET_SECTOR: i16 = 9;
+
+    pub fn secret_sectors(&self) -> usize {
+        self.sectors
+            .iter()
+            .filter(|sector| sector.special_type == Self::SECRET_SECTOR)
+            .count()
+    }
+
+    // Lines flagged to show as one-sided walls on the automap.
+    pub fn secret_linedefs(&self) -> usize {
+        self.line_defs
+            .iter()
+            .filter(|line| line.has_flag(LineDefFlags::Secret))
+            .count()
+    }
+
+    pub fn monster_count(&self) -> usize {
+        self.things
+            .iter()
+            .filter(|thing| thing.category() == ThingCategory::Monster)
+            .count()
+    }
+
+    // How many things of each doomednum the map places.
+    pub fn thing_histogram(&self) -> BTreeMap<i16, usize> {
+        let mut histogram = BTreeMap::new();
+
+        for thing in self.things.iter() {
+            *histogram.entry(thing.t_type).or_insert(0) += 1;
+        }
+
+        histogram
+    }
+
+    // Player `number`'s start, 1 to 4.
+    pub fn player_start(&self, number: u8) -> Option<Thing> {
+        self.things
+            .iter()
+            .find(|thing| (1..=4).contains(&number) && thing.t_type == number as i16)
+            .copied()
+    }
+
+    // Things inside the box from `min` to `max`, edges included.
+    pub fn things_in_rect(&self, min: (i16, i16), max: (i16, i16)) -> Vec<usize> {
+        let rect = Rect { top: max.1, bottom: min.1, left: min.0, right: max.0 };
+
+        self.things
+            .iter()
+            .enumerate()
+            .filter(|(_, thing)| rect.contains(thing.x as f32, thing.y as f32))
+            .map(|(thing_id, _)| thing_id)
+            .collect()
+    }
+
+    pub fn count_thing(&self, t_type: i16) -> usize {
+        self.things.iter().filter(|thing| thing.t_type == t_type).count()
+    }
+
+    // Wall textures the sidedefs name, absent ones left out.
+    pub fn used_textures(&self) -> BTreeSet<String> {
+        self.side_defs.iter().flat_map(|side| side.textures()).collect()
+    }
+
+    pub fn used_flats(&self) -> BTreeSet<String> {
+        self.sectors
+            .iter()
+            .flat_map(|sector| [sector.floor_texture(), sector.ceiling_texture()])
+            .collect()
+    }
+}
+
+impl WAD {
+    // Map markers are the lumps followed by a map's THINGS, or TEXTMAP for
+    // UDMF maps. ExMy and MAPxx markers may have other lumps before THINGS.
+    pub fn map_names(&self) -> Vec<String> {
+        self.map_markers().into_iter().map(|i| self.directory[i].name()).collect()
+    }
+
+    // Directory indexes of the markers `map_names` lists.
+    fn map_markers(&self) -> Vec<usize> {
+        let mut markers = Vec::new();
+        let mut i = 0;
+
+        // Lumps up to a map's THINGS are skipped, so they aren't taken for
+        // a marker themselves.
+        while i + 1 < self.directory.len() {
+            let name = self.directory[i].name();
+
+            let things = match self.directory[i + 1].name().as_str() {
+                "THINGS" | "TEXTMAP" => Some(i + 1),
+                _ if Self::map_order(&name).0 < 2 => self
+                    .find_map_lump(i, MapLumpIndex::Things)
+                    .or_else(|| self.find_map_lump_named(i, "TEXTMAP")),
+                _ => None,
+            };
+
+            match things {
+                Some(things) => {
+                    markers.push(i);
+                    i = things + 1;
+                }
+                None => i += 1,
+            }
+        }
+
+        markers
+    }
+
+    // Game order: ExMy by episode then map, then MAPxx by number, then any
+    // other names alphabetically.
+    pub fn map_names_sorted(&self) -> Vec<String> {
+        let mut names = self.map_names();
+        names.sort_by_key(|name| Self::map_order(name));
+        names
+    }
+
+    fn map_order(name: &str) -> (u8, u32, u32, String) {
+        if let [b'E', episode @ b'0'..=b'9', b'M', ..] = name.as_bytes() {
+            if let Ok(map) = name[3..].parse() {
+                return (0, (episode - b'0') as u32, map, String::new());
+            }
+        }
+
+        if let Some(Ok(map)) = name.strip_prefix("MAP").map(str::parse) {
+            return (1, 0, map, String::new());
+        }
+
+        (2, 0, 0, name.to_string())
+    }
+
+    // One result per map, in `map_names` order, so a broken map doesn't
+    // hide the others' stats. Each worker opens its own handle on the WAD's
+    // file, WADs read from memory have none to open.
+    pub fn map_stats_parallel(&self) -> Result<Vec<Result<MapStats, WadError>>, WadError> {
+        let path = self.path.as_deref().ok_or(WadError::NoPath)?;
+
+        Ok(self
+            .map_names()
+            .par_iter()
+            .map(|name| Ok(MapStats::new(name, &WAD::new(path)?.load_map(name)?)))
+            .collect())
+    }
+}
+
+// - - -
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapIssueKind {
+    // A linedef or seg pointing past the end of VERTEXES.
+    InvalidVertexRef,
+    // A linedef pointing past the end of SIDEDEFS.
+    InvalidSidedefRef,
+    // A sidedef pointing past the end of SECTORS.
+    InvalidSectorRef,
+    // A seg pointing past the end of LINEDEFS.
+    InvalidLineDefRef,
+    // A linedef whose right side is -1.
+    MissingFrontSidedef,
+    // A sector no sidedef belongs to.
+    UnreferencedSector,
+}
+
+// `index` is into the lump the kind is about: linedefs and segs for invalid
+// references from them, sidedefs for `InvalidSectorRef`, sectors for
+// `UnreferencedSector`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapIssue {
+    pub kind: MapIssueKind,
+    pub index: usize,
+}
+
+impl Map {
+    pub fn validate_current_map(&self) -> Vec<MapIssue> {
+        let mut issues = Vec::new();
+
+        let mut report = |kind, index| issues.push(MapIssue { kind, index });
+
+        let vertex_ok = |v: i64| v >= 0 && (v as usize) < self.vertexes.len();
+        let side_def_ok = |s: i16| s >= 0 && (s as usize) < self.side_defs.len();
+
+        for (i, line) in self.line_defs.iter().enumerate() {
+            if !vertex_ok(line.start_vertex as i64) || !vertex_ok(line.end_vertex as i64) {
+                report(MapIssueKind::InvalidVertexRef, i);
+            }
+
+            if line.right_sidedef == -1 {
+                report(MapIssueKind::MissingFrontSidedef, i);
+            } else if !side_def_ok(line.right_sidedef) {
+                report(MapIssueKind::InvalidSidedefRef, i);
+            }
+
+            if !line.is_one_sided() && !side_def_ok(line.left_sidedef) {
+                report(MapIssueKind::InvalidSidedefRef, i);
+            }
+        }
+
+        for (i, seg) in self.segs.iter().enumerate() {
+            if !vertex_ok(seg.start_vertex as i64) || !vertex_ok(seg.end_vertex as i64) {
+                report(MapIssueKind::InvalidVertexRef, i);
+            }
+
+            // Minisegs have no linedef.
+            if seg.linedef >= 0 && seg.linedef as usize >= self.line_defs.len() {
+                report(MapIssueKind::InvalidLineDefRef, i);
+            }
+        }
+
+        let mut referenced = vec![false; self.sectors.len()];
+
+        for (i, side) in self.side_defs.iter().enumerate() {
+            match referenced.get_mut(side.sector as usize) {
+                Some(used) if side.sector >= 0 => *used = true,
+                _ => report(MapIssueKind::InvalidSectorRef, i),
+            }
+        }
+
+        for (i, used) in referenced.iter().enumerate() {
+            if !used {
+                report(MapIssueKind::UnreferencedSector, i);
+            }
+        }
+
+        issues
+    }
+
+    // Sidedefs no linedef uses on either side, which editors and node
+    // builders would otherwise carry along.
+    pub fn orphan_sidedefs(&self) -> Vec<usize> {
+        let mut referenced = vec![false; self.side_defs.len()];
+
+        for line in self.line_defs.iter() {
+            for side in [line.right_sidedef, line.left_sidedef] {
+                if let Some(used) = referenced.get_mut(side as usize).filter(|_| side >= 0) {
+                    *used = true;
+                }
+            }
+        }
+
+        (0..referenced.len()).filter(|&i| !referenced[i]).collect()
+    }
+}
+
+impl WAD {
+    // Nothing is replaced until every lump has been read, so a map that fails
+    // to load leaves the previous one in place.
+    pub fn change_map(&mut self, name: &str) -> Result<(), WadError> {
+        let i = self.find_map(name)?;
+
+        self.map = self.read_map(i)?;
+        self.map_index = Some(i);
+
+        Ok(())
+    }
+
+    // Directory index of the map's marker. Lumps that aren't a marker, like
+    // PLAYPAL or a map's THINGS, aren't found.
+    fn find_map(&self, name: &str) -> Result<usize, WadError> {
+        if self.directory.is_empty() {
+            return Err(WadError::DirectoryNotLoaded);
+        }
+
+        self.map_markers()
+            .into_iter()
+            .find(|&i| self.directory[i].name() == name)
+            .ok_or_else(|| WadError::MapNotFound(name.to_string()))
+    }
+
+    // Re-reads the header and directory after the file changed on disk,
+    // then the current map by name. A failed read leaves everything as it
+    // was, a map that's gone is unloaded.
+    pub fn reload(&mut self) -> Result<(), WadError> {
+        let name = self.current_map_name();
+        let saved = (self.header, self.directory.clone(), self.map_index, self.map.clone());
+
+        let result = self.reload_map(name);
+
+        if result.is_err() {
+            (self.header, self.directory, self.map_index, self.map) = saved;
+        }
+
+        result
+    }
+
+    fn reload_map(&mut self, name: Option<String>) -> Result<(), WadError> {
+        self.setup()?;
+
+        #[cfg(feature = "mmap")]
+        if let (Some(_), Some(path)) = (&self.mmap, &self.path) {
+            self.mmap = Some(unsafe { memmap2::Mmap::map(&fs::File::open(path)?)? });
+        }
+
+        self.map_index = None;
+
+        if let Some(name) = name {
+            match self.change_map(&name) {
+                Err(WadError::MapNotFound(_)) => self.map = Map::default(),
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Directory index of the current map's marker lump.
+    pub fn current_map_index(&self) -> Option<usize> {
+        self.map_index
+    }
+
+    pub fn current_map_name(&self) -> Option<String> {
+        self.map_index.map(|i| self.directory[i].name())
+    }
+
+    // Reads a map without making it the current one, the result stays
+    // usable after the WAD is dropped.
+    // Missing maps are the same errors as for `change_map`.
+    pub fn load_map(&mut self, name: &str) -> Result<Map, WadError> {
+        let i = self.find_map(name)?;
+
+        self.read_map(i)
+    }
+
+    // Every map in the WAD, the current one is left as is.
+    pub fn load_all_maps(&mut self) -> Result<HashMap<String, Map>, WadError> {
+        let mut maps = HashMap::new();
+
+        for name in self.map_names() {
+            let map = self.load_map(&name)?;
+            maps.insert(name, map);
+        }
+
+        Ok(maps)
+    }
+
+    // `i` - Directory index of the map marker.
+    fn read_map(&mut self, i: usize) -> Result<Map, WadError> {
+        if let Some(textmap) = self.find_map_lump_named(i, "TEXTMAP") {
+            let text = self.read_map_lump(textmap)?;
+
+            return Map::from_udmf(&String::from_utf8_lossy(&text));
+        }
+
+        let things = self.read_map_lump_as(i, MapLumpIndex::Things)?;
+        let line_defs = self.read_map_lump_as(i, MapLumpIndex::LineDefs)?;
+        let side_defs = self.read_map_lump_as(i, MapLumpIndex::SideDefs)?;
+        let mut vertexes = self.read_map_lump_as(i, MapLumpIndex::Vertexes)?;
+        let mut segs = self.read_optional_map_lump_as(i, MapLumpIndex::Segs)?;
+        let mut ssectors = self.read_optional_map_lump_as(i, MapLumpIndex::SSectors)?;
+        let nodes = self.read_nodes(i, &line_defs, &mut vertexes, &mut segs, &mut ssectors)?;
+        let sectors = self.read_map_lump_as(i, MapLumpIndex::Sectors)?;
+        let reject = self.read_optional_map_lump(i, MapLumpIndex::Reject)?;
+
+        Ok(Map { things, line_defs, side_defs, vertexes, segs, ssectors, nodes, sectors, reject })
+    }
+}
+
+// - - -
+// UDMF maps keep their geometry in a TEXTMAP lump of text blocks instead of
+// the binary lumps, e.g. `vertex { x = 64.0; y = -32.0; }`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UdmfValue {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UdmfBlock {
+    pub kind: String,
+    pub fields: HashMap<String, UdmfValue>,
+}
+
+impl UdmfBlock {
+    // Floats are truncated, like the integer fields they replace.
+    pub fn int(&self, key: &str, default: i64) -> i64 {
+        match self.fields.get(key) {
+            Some(UdmfValue::Int(value)) => *value,
+            Some(UdmfValue::Float(value)) => *value as i64,
+            _ => default,
+        }
+    }
+
+    pub fn float(&self, key: &str, default: f64) -> f64 {
+        match self.fields.get(key) {
+            Some(UdmfValue::Int(value)) => *value as f64,
+            Some(UdmfValue::Float(value)) => *value,
+            _ => default,
+        }
+    }
+
+    pub fn string(&self, key: &str, default: &str) -> String {
+        match self.fields.get(key) {
+            Some(UdmfValue::Str(value)) => value.clone(),
+            _ => default.to_string(),
+        }
+    }
+
+    // Flags default to false.
+    pub fn flag(&self, key: &str) -> bool {
+        matches!(self.fields.get(key), Some(UdmfValue::Bool(true)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum UdmfToken {
+    Ident(String),
+    Value(UdmfValue),
+    Symbol(char),
+}
+
+// The namespace and the blocks in the order they appear.
+pub fn parse_udmf(text: &str) -> Result<(String, Vec<UdmfBlock>), WadError> {
+    let invalid = || WadError::InvalidLump("TEXTMAP".to_string());
+
+    let mut tokens = udmf_tokens(text)?.into_iter();
+    let mut namespace = None;
+    let mut blocks = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let UdmfToken::Ident(name) = token else {
+            return Err(invalid());
+        };
+
+        match tokens.next() {
+            // A global assignment, only the namespace matters.
+            Some(UdmfToken::Symbol('=')) => {
+                let value = tokens.next();
+
+                if tokens.next() != Some(UdmfToken::Symbol(';')) {
+                    return Err(invalid());
+                }
+
+                if name.eq_ignore_ascii_case("namespace") {
+                    match value {
+                        Some(UdmfToken::Value(UdmfValue::Str(value))) => namespace = Some(value),
+                        _ => return Err(invalid()),
+                    }
+                }
+            }
+            Some(UdmfToken::Symbol('{')) => {
+                let mut block = UdmfBlock { kind: name.to_lowercase(), ..Default::default() };
+
+                loop {
+                    match tokens.next() {
+                        Some(UdmfToken::Symbol('}')) => break,
+                        Some(UdmfToken::Ident(key)) => {
+                            if tokens.next() != Some(UdmfToken::Symbol('=')) {
+                                return Err(invalid());
+                            }
+
+                            let Some(UdmfToken::Value(value)) = tokens.next() else {
+                                return Err(invalid());
+                            };
+
+                            if tokens.next() != Some(UdmfToken::Symbol(';')) {
+                                return Err(invalid());
+                            }
+
+                            block.fields.insert(key.to_lowercase(), value);
+                        }
+                        _ => return Err(invalid()),
+                    }
+                }
+
+                blocks.push(block);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok((namespace.ok_or_else(invalid)?, blocks))
+}
+
+fn udmf_tokens(text: &str) -> Result<Vec<UdmfToken>, WadError> {
+    let invalid = || WadError::InvalidLump("TEXTMAP".to_string());
+
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '/' => {
+                chars.next();
+
+                match chars.next() {
+                    Some('/') => while chars.next_if(|&c| c != '\n').is_some() {},
+                    Some('*') => {
+                        let mut last = ' ';
+
+                        loop {
+                            match chars.next() {
+                                Some('/') if last == '*' => break,
+                                Some(c) => last = c,
+                                None => return Err(invalid()),
+                            }
+                        }
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            '=' | ';' | '{' | '}' => {
+                tokens.push(UdmfToken::Symbol(c));
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+
+                let mut value = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(c) => value.push(c),
+                        None => return Err(invalid()),
+                    }
+                }
+
+                tokens.push(UdmfToken::Value(UdmfValue::Str(value)));
+            }
+            _ if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let is_number = |c: &char| c.is_ascii_alphanumeric() || "+-.".contains(*c);
+                let mut number = String::new();
+
+                while let Some(c) = chars.next_if(is_number) {
+                    number.push(c);
+                }
+
+                let hex = number.strip_prefix("0x").or(number.strip_prefix("0X"));
+
+                let value = match (number.parse::<i64>(), hex) {
+                    (Ok(value), _) => UdmfValue::Int(value),
+                    (_, Some(hex)) => {
+                        UdmfValue::Int(i64::from_str_radix(hex, 16).map_err(|_| invalid())?)
+                    }
+                    _ => UdmfValue::Float(number.parse().map_err(|_| invalid())?),
+                };
+
+                tokens.push(UdmfToken::Value(value));
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    ident.push(c);
+                }
+
+                tokens.push(match ident.to_lowercase().as_str() {
+                    "true" => UdmfToken::Value(UdmfValue::Bool(true)),
+                    "false" => UdmfToken::Value(UdmfValue::Bool(false)),
+                    _ => UdmfToken::Ident(ident),
+                });
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Texture and flat names are stored like the binary lumps': 8 bytes,
+// upper case and zero padded.
+fn udmf_name(name: &str) -> [u8; 8] {
+    let mut bytes = [0; 8];
+
+    for (dst, src) in bytes.iter_mut().zip(name.to_ascii_uppercase().bytes()) {
+        *dst = src;
+    }
+
+    bytes
+}
+
+impl Map {
+    // Namespaces whose fields are read, the rest are rejected.
+    pub const UDMF_NAMESPACES: [&'static str; 2] = ["doom", "zdoom"];
+
+    // Segs, subsectors and nodes aren't part of TEXTMAP, they're left empty.
+    pub fn from_udmf(text: &str) -> Result<Map, WadError> {
+        let (namespace, blocks) = parse_udmf(text)?;
+
+        if !Self::UDMF_NAMESPACES.iter().any(|known| namespace.eq_ignore_ascii_case(known)) {
+            return Err(WadError::InvalidLump("TEXTMAP".to_string()));
+        }
+
+        let mut map = Map::default();
+
+        for block in blocks.iter() {
+            match block.kind.as_str() {
+                "vertex" => map.vertexes.push(Vertex {
+                    x: block.float("x", 0.0).round() as i16,
+                    y: block.float("y", 0.0).round() as i16,
+                }),
+                "linedef" => map.line_defs.push(Self::udmf_line_def(block)),
+                "sidedef" => map.side_defs.push(SideDef {
+                    x_offset: block.int("offsetx", 0) as i16,
+                    y_offset: block.int("offsety", 0) as i16,
+                    upper_texture: udmf_name(&block.string("texturetop", "-")),
+                    lower_texture: udmf_name(&block.string("texturebottom", "-")),
+                    middle_texture: udmf_name(&block.string("texturemiddle", "-")),
+                    sector: block.int("sector", -1) as i16,
+                }),
+                "sector" => map.sectors.push(Sector {
+                    floor_height: block.int("heightfloor", 0) as i16,
+                    ceiling_height: block.int("heightceiling", 0) as i16,
+                    floor_texture: udmf_name(&block.string("texturefloor", "")),
+                    ceiling_texture: udmf_name(&block.string("textureceiling", "")),
+                    light_level: block.int("lightlevel", 160) as i16,
+                    special_type: block.int("special", 0) as i16,
+                    tag: block.int("id", 0) as i16,
+                }),
+                "thing" => map.things.push(Self::udmf_thing(block)),
+                // Unknown blocks are allowed and skipped.
+                _ => {}
+            }
+        }
+
+        Ok(map)
+    }
+
+    fn udmf_line_def(block: &UdmfBlock) -> LineDef {
+        let flags = [
+            ("blocking", LineDefFlags::Blocking),
+            ("blockmonsters", LineDefFlags::BlockMonsters),
+            ("twosided", LineDefFlags::TwoSided),
+            ("dontpegtop", LineDefFlags::DontPegTop),
+            ("dontpegbottom", LineDefFlags::DontPegBottom),
+            ("secret", LineDefFlags::Secret),
+            ("blocksound", LineDefFlags::SoundBlock),
+            ("dontdraw", LineDefFlags::DontDraw),
+            ("mapped", LineDefFlags::Draw),
+        ];
+
+        // Doom's tags are `arg0`, ZDoom also has a line `id`.
+        let tag = match block.fields.contains_key("arg0") {
+            true => block.int("arg0", 0),
+            false => block.int("id", 0).max(0),
+        };
+
+        LineDef {
+            start_vertex: block.int("v1", 0) as i16,
+            end_vertex: block.int("v2", 0) as i16,
+            flags: flags
+                .iter()
+                .filter(|(key, _)| block.flag(key))
+                .fold(0, |flags, (_, flag)| flags | *flag as i16),
+            special_type: block.int("special", 0) as i16,
+            sector_tag: tag as i16,
+            right_sidedef: block.int("sidefront", -1) as i16,
+            left_sidedef: block.int("sideback", -1) as i16,
+        }
+    }
+
+    fn udmf_thing(block: &UdmfBlock) -> Thing {
+        let flags = [
+            ("skill1", ThingFlags::Easy),
+            ("skill2", ThingFlags::Easy),
+            ("skill3", ThingFlags::Medium),
+            ("skill4", ThingFlags::Hard),
+            ("skill5", ThingFlags::Hard),
+            ("ambush", ThingFlags::Deaf),
+        ];
+
+        let mut thing_flags = flags
+            .iter()
+            .filter(|(key, _)| block.flag(key))
+            .fold(0, |flags, (_, flag)| flags | *flag as i16);
+
+        if !block.flag("single") && (block.flag("coop") || block.flag("dm")) {
+            thing_flags |= ThingFlags::MultiplayerOnly as i16;
+        }
+
+        Thing {
+            x: block.float("x", 0.0).round() as i16,
+            y: block.float("y", 0.0).round() as i16,
+            angle: block.int("angle", 0) as i16,
+            t_type: block.int("type", 0) as i16,
+            flags: thing_flags,
+        }
+    }
+}
+
+// - - -
+// The sidedefs and sectors on both sides of a linedef.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LineDefContext {
+    pub front_side: Option<SideDef>,
+    pub back_side: Option<SideDef>,
+    pub front_sector: Option<Sector>,
+    pub back_sector: Option<Sector>,
+}
+
+// The space a two-sided line leaves open between its sectors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opening {
+    // Lowest ceiling.
+    pub top: i16,
+    // Highest floor.
+    pub bottom: i16,
+    // Height of the step between both floors, wider than the heights so
+    // extreme ones don't overflow.
+    pub floor_gap: i32,
+}
+
+impl Opening {
+    // Zero or less for closed doors and lifts.
+    pub fn height(&self) -> i32 {
+        self.top as i32 - self.bottom as i32
+    }
+}
+
+impl LineDefContext {
+    pub fn is_sky_wall(&self) -> bool {
+        [self.front_sector, self.back_sector]
+            .iter()
+            .flatten()
+            .any(|sector| sector.is_sky())
+    }
+
+    // Upper textures only show on two-sided lines, and not below a sky
+    // ceiling, which is left open so the sky is drawn behind it.
+    pub fn draws_upper(&self) -> bool {
+        match self.back_sector {
+            Some(back) => !back.is_sky(),
+            None => false,
+        }
+    }
+}
+
+// A linedef with its sidedefs borrowed from the map, see `Map::walls`.
+#[derive(Clone, Copy, Debug)]
+pub struct Wall<'m> {
+    pub linedef: &'m LineDef,
+    pub front: Option<&'m SideDef>,
+    pub back: Option<&'m SideDef>,
+}
+
+impl Map {
+    // One wall per linedef, in order. Sides that are -1 or point past the
+    // end of SIDEDEFS are None.
+    pub fn walls(&self) -> impl Iterator<Item = Wall<'_>> {
+        let side = |index: i16| match index < 0 {
+            true => None,
+            false => self.side_defs.get(index as usize),
+        };
+
+        self.line_defs.iter().map(move |linedef| Wall {
+            linedef,
+            front: side(linedef.right_sidedef),
+            back: side(linedef.left_sidedef),
+        })
+    }
+}
+
+impl Map {
+    // `index` - Sidedef index, `-1` means there is no sidedef.
+    fn side_def_at(&self, index: i16) -> Option<SideDef> {
+        if index < 0 {
+            return None;
+        }
+
+        self.side_defs.get(index as usize).copied()
+    }
+
+    fn sector_of(&self, side: Option<SideDef>) -> Option<Sector> {
+        let side = side?;
+
+        if side.sector < 0 {
+            return None;
+        }
+
+        self.sectors.get(side.sector as usize).copied()
+    }
+
+    // Index of the linedef closest to a map-space point and its distance.
+    pub fn nearest_linedef(&self, x: f32, y: f32) -> Option<(usize, f32)> {
+        let mut nearest: Option<(usize, f32)> = None;
+
+        for (i, line) in self.line_defs.iter().enumerate() {
+            let (v1, v2) = match (
+                self.vertexes.get(line.start_vertex as usize),
+                self.vertexes.get(line.end_vertex as usize),
+            ) {
+                (Some(v1), Some(v2)) => (v1, v2),
+                _ => continue,
+            };
+
+            let distance = point_segment_distance(
+                (x, y),
+                (v1.x as f32, v1.y as f32),
+                (v2.x as f32, v2.y as f32),
+            );
+
+            if nearest.is_none_or(|(_, best)| distance < best) {
+                nearest = Some((i, distance));
+            }
+        }
+
+        nearest
+    }
+
+    pub fn linedef_context(&self, ld: &LineDef) -> LineDefContext {
+        let front_side = self.side_def_at(ld.right_sidedef);
+        let back_side = self.side_def_at(ld.left_sidedef);
+
+        LineDefContext {
+            front_side,
+            back_side,
+            front_sector: self.sector_of(front_side),
+            back_sector: self.sector_of(back_side),
+        }
+    }
+
+    // The vertical gap between both sides, `None` for one-sided lines.
+    pub fn line_opening(&self, ld: &LineDef) -> Option<Opening> {
+        let context = self.linedef_context(ld);
+        let (front, back) = (context.front_sector?, context.back_sector?);
+
+        Some(Opening {
+            top: front.ceiling_height.min(back.ceiling_height),
+            bottom: front.floor_height.max(back.floor_height),
+            floor_gap: (front.floor_height as i32 - back.floor_height as i32).abs(),
+        })
+    }
+
+    // `None` for minisegs, which only split subsectors along a partition
+    // line and have no linedef (`-1`).
+    pub fn seg_linedef(&self, seg: &Seg) -> Option<&LineDef> {
+        if seg.is_miniseg() {
+            return None;
+        }
+
+        self.line_defs.get(seg.linedef as usize)
+    }
+
+    // Like `linedef_context`, but from the side the seg runs along. Empty for
+    // minisegs.
+    pub fn seg_context(&self, seg: &Seg) -> LineDefContext {
+        let Some(line) = self.seg_linedef(seg) else {
+            return LineDefContext::default();
+        };
+
+        let context = self.linedef_context(line);
+
+        match seg.direction {
+            0 => context,
+            _ => LineDefContext {
+                front_side: context.back_side,
+                back_side: context.front_side,
+                front_sector: context.back_sector,
+                back_sector: context.front_sector,
+            },
+        }
+    }
+}
+
+impl Map {
+    // Zero for lines pointing at missing vertexes.
+    pub fn linedef_length(&self, ld: &LineDef) -> f32 {
+        match (
+            self.vertexes.get(ld.start_vertex as usize),
+            self.vertexes.get(ld.end_vertex as usize),
+        ) {
+            (Some(v1), Some(v2)) => {
+                (v2.x as f32 - v1.x as f32).hypot(v2.y as f32 - v1.y as f32)
+            }
+            _ => 0.0,
+        }
+    }
+
+    // (min_x, min_y, max_x, max_y) over the vertexes, None without any.
+    pub fn map_bounds(&self) -> Option<(i16, i16, i16, i16)> {
+        let first = self.vertexes.first()?;
+
+        Some(self.vertexes.iter().fold(
+            (first.x, first.y, first.x, first.y),
+            |(min_x, min_y, max_x, max_y), v| {
+                (min_x.min(v.x), min_y.min(v.y), max_x.max(v.x), max_y.max(v.y))
+            },
+        ))
+    }
+
+    // Zero-length lines, whether both ends are the same vertex or two
+    // vertexes at the same spot. Lines pointing at missing vertexes are left
+    // to `validate_current_map`.
+    pub fn degenerate_linedefs(&self) -> Vec<usize> {
+        let has_vertexes = |line: &LineDef| {
+            [line.start_vertex, line.end_vertex]
+                .iter()
+                .all(|&vertex| vertex >= 0 && (vertex as usize) < self.vertexes.len())
+        };
+
+        self.line_defs
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| has_vertexes(line) && self.linedef_length(line) == 0.0)
+            .map(|(line_id, _)| line_id)
+            .collect()
+    }
+
+    // Lines with one side in the sector. Lines with the sector on both
+    // sides are inside it, not on its edge.
+    pub fn sector_perimeter(&self, sector_id: usize) -> f32 {
+        let in_sector = |side: i16| {
+            self.side_def_at(side)
+                .is_some_and(|side| side.sector >= 0 && side.sector as usize == sector_id)
+        };
+
+        self.line_defs
+            .iter()
+            .filter(|line| in_sector(line.right_sidedef) != in_sector(line.left_sidedef))
+            .map(|line| self.linedef_length(line))
+            .sum()
+    }
+
+    pub fn total_map_length(&self) -> f32 {
+        self.line_defs.iter().map(|line| self.linedef_length(line)).sum()
+    }
+}
+
+impl Map {
+    const SUB_SECTOR_FLAG: u32 = 0x8000_0000;
+
+    // Walks the BSP tree down to the subsector containing the point.
+    pub fn subsector_at(&self, x: f32, y: f32) -> usize {
+        if self.nodes.is_empty() {
+            return 0;
+        }
+
+        let mut node_id = (self.nodes.len() - 1) as u32;
+
+        while node_id & Self::SUB_SECTOR_FLAG == 0 {
+            let node = &self.nodes[node_id as usize];
+
+            let dx = x - node.x_partition as f32;
+            let dy = y - node.y_partition as f32;
+
+            node_id = match dx * node.dy_partition as f32 - dy * node.dx_partition as f32 <= 0.0 {
+                true => node.back_child,
+                false => node.front_child,
+            };
+        }
+
+        (node_id & !Self::SUB_SECTOR_FLAG) as usize
+    }
+
+    // Empty for unknown subsectors or ones whose segs run past SEGS.
+    pub fn subsector_segs(&self, sub_sector_id: usize) -> &[Seg] {
+        let Some(sub_sector) = self.ssectors.get(sub_sector_id) else {
+            return &[];
+        };
+
+        let first = sub_sector.first_seg as usize;
+        let last = first.saturating_add(sub_sector.num_segs as usize);
+
+        self.segs.get(first..last).unwrap_or(&[])
+    }
+
+    // The sector of a subsector, taken from the side of its first seg.
+    pub fn subsector_sector(&self, sub_sector_id: usize) -> Option<usize> {
+        let seg = self.subsector_segs(sub_sector_id).first()?;
+        let line = self.seg_linedef(seg)?;
+
+        let side = match seg.direction {
+            0 => self.side_def_at(line.right_sidedef),
+            _ => self.side_def_at(line.left_sidedef),
+        }?;
+
+        match side.sector < 0 || side.sector as usize >= self.sectors.len() {
+            true => None,
+            false => Some(side.sector as usize),
+        }
+    }
+
+    pub fn sector_at(&self, x: f32, y: f32) -> Option<&Sector> {
+        let sector_id = self.subsector_sector(self.subsector_at(x, y))?;
+
+        self.sectors.get(sector_id)
+    }
+
+    // REJECT has a bit per pair of sectors, set when nothing in `to` can be
+    // seen from `from`. Short or missing matrices reject nothing.
+    pub fn is_rejected(&self, from: usize, to: usize) -> bool {
+        let bit = from * self.sectors.len() + to;
+
+        self.reject
+            .get(bit / 8)
+            .is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+    }
+
+    // The start of every seg, plus the last seg's end when the subsector
+    // isn't closed, the edge back to the first point is implicit.
+    pub fn subsector_polygon(&self, sub_sector_id: usize) -> Vec<(f32, f32)> {
+        let segs = self.subsector_segs(sub_sector_id);
+
+        let point = |vertex: u32| {
+            self.vertexes
+                .get(vertex as usize)
+                .map(|vertex| (vertex.x as f32, vertex.y as f32))
+        };
+
+        let mut points = segs
+            .iter()
+            .filter_map(|seg| point(seg.start_vertex))
+            .collect::<Vec<_>>();
+
+        if let (Some(first), Some(last)) = (segs.first(), segs.last()) {
+            if first.start_vertex != last.end_vertex {
+                points.extend(point(last.end_vertex));
+            }
+        }
+
+        points
+    }
+
+    // Subsectors are convex, but only edges along linedefs have segs, so a
+    // subsector bounded by more than one partition line comes up short.
+    pub fn sector_area(&self, sector_id: usize) -> f32 {
+        (0..self.ssectors.len())
+            .filter(|&id| self.subsector_sector(id) == Some(sector_id))
+            .map(|id| polygon_area(&self.subsector_polygon(id)).abs())
+            .sum()
+    }
+
+    // A point inside the sector to label it at: the centroid of its
+    // subsectors, or of its largest one when that lands outside the sector,
+    // as it can for concave sectors and ones with holes. Subsectors missing
+    // too many segs to have an area fall back to the sector's line ends.
+    pub fn sector_centroid(&self, sector_id: usize) -> Option<(f32, f32)> {
+        let parts = (0..self.ssectors.len())
+            .filter(|&id| self.subsector_sector(id) == Some(sector_id))
+            .filter_map(|id| {
+                let polygon = self.subsector_polygon(id);
+
+                Some((polygon_centroid(&polygon)?, polygon_area(&polygon).abs()))
+            })
+            .collect::<Vec<_>>();
+
+        let total = parts.iter().map(|(_, area)| area).sum::<f32>();
+
+        if total <= 0.0 {
+            return self.sector_vertex_average(sector_id);
+        }
+
+        let (x, y) = parts
+            .iter()
+            .fold((0.0, 0.0), |(x, y), ((cx, cy), area)| (x + cx * area, y + cy * area));
+        let centroid = (x / total, y / total);
+
+        if self.subsector_sector(self.subsector_at(centroid.0, centroid.1)) == Some(sector_id) {
+            return Some(centroid);
+        }
+
+        parts
+            .iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(centroid, _)| *centroid)
+    }
+}
+
+impl Map {
+    fn sector_vertex_average(&self, sector_id: usize) -> Option<(f32, f32)> {
+        let in_sector = |side: i16| {
+            self.side_def_at(side)
+                .is_some_and(|side| side.sector >= 0 && side.sector as usize == sector_id)
+        };
+
+        let points = self
+            .line_defs
+            .iter()
+            .filter(|line| in_sector(line.right_sidedef) || in_sector(line.left_sidedef))
+            .flat_map(|line| [line.start_vertex, line.end_vertex])
+            .filter_map(|vertex| self.vertexes.get(vertex as usize))
+            .collect::<Vec<_>>();
+
+        if points.is_empty() {
+            return None;
+        }
+
+        let (x, y) = points
+            .iter()
+            .fold((0.0, 0.0), |(x, y), vertex| (x + vertex.x as f32, y + vertex.y as f32));
+
+        Some((x / points.len() as f32, y / points.len() as f32))
+    }
+}
+
+impl Map {
+    // Sectors grouped by their tag, untagged (0) sectors left out.
+    pub fn sectors_by_tag(&self) -> BTreeMap<i16, Vec<usize>> {
+        let mut groups = BTreeMap::<i16, Vec<usize>>::new();
+
+        for (sector_id, sector) in self.sectors.iter().enumerate() {
+            if sector.tag != 0 {
+                groups.entry(sector.tag).or_default().push(sector_id);
+            }
+        }
+
+        groups
+    }
+
+    // Sectors a linedef with `tag` acts on. Tag 0 is no tag, so it matches
+    // nothing, here and in `linedefs_with_tag`.
+    pub fn sectors_with_tag(&self, tag: i16) -> Vec<usize> {
+        self.sectors
+            .iter()
+            .enumerate()
+            .filter(|(_, sector)| tag != 0 && sector.tag == tag)
+            .map(|(sector_id, _)| sector_id)
+            .collect()
+    }
+
+    // Linedefs whose special acts on the sectors with `tag`.
+    pub fn linedefs_with_tag(&self, tag: i16) -> Vec<usize> {
+        self.line_defs
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| tag != 0 && line.sector_tag == tag)
+            .map(|(line_id, _)| line_id)
+            .collect()
+    }
+}
+
+impl Map {
+    // Sectors sharing a two-sided line with the sector.
+    pub fn neighbor_sectors(&self, sector_id: usize) -> Vec<usize> {
+        let sector_of = |side: i16| {
+            self.side_def_at(side)
+                .filter(|side| side.sector >= 0 && (side.sector as usize) < self.sectors.len())
+                .map(|side| side.sector as usize)
+        };
+
+        let mut neighbors = BTreeSet::new();
+
+        for line in self.line_defs.iter() {
+            match (sector_of(line.right_sidedef), sector_of(line.left_sidedef)) {
+                (Some(front), Some(back)) if front == sector_id && back != sector_id => {
+                    neighbors.insert(back);
+                }
+                (Some(front), Some(back)) if back == sector_id && front != sector_id => {
+                    neighbors.insert(front);
+                }
+                _ => {}
+            }
+        }
+
+        neighbors.into_iter().collect()
+    }
+
+    // DOOM's P_FindMinSurroundingLight: the darkest neighbor's light, or
+    // `max` when none is darker.
+    fn min_surrounding_light(&self, sector_id: usize, max: i16) -> i16 {
+        self.neighbor_sectors(sector_id)
+            .iter()
+            .map(|&id| self.sectors[id].light_level)
+            .fold(max, i16::min)
+    }
+
+    // The light level `time` into the level for the light specials, between
+    // the sector's own level and its darkest neighbor's. DOOM's random
+    // blinking and flickering are replaced by fixed patterns of the same
+    // lengths, so previews don't need its random number table.
+    pub fn effective_light(&self, sector_id: usize, time: Duration) -> u8 {
+        let Some(sector) = self.sectors.get(sector_id) else {
+            return 0;
+        };
+
+        let max = sector.light_level;
+        let tic = (time.as_secs_f64() * Engine::TIC_RATE as f64) as u64;
+
+        // Strobes go dark completely next to sectors as bright.
+        let dark = || match self.min_surrounding_light(sector_id, max) {
+            min if min == max => 0,
+            min => min,
+        };
+
+        // `bright` tics at the sector's level, then `unlit` tics at `min`.
+        let blink = |bright: u64, unlit: u64, min: i16| match tic % (bright + unlit) < bright {
+            true => max,
+            false => min,
+        };
+
+        let level = match sector.special() {
+            // P_SpawnLightFlash, unlike the strobes, stays at the neighbors' level.
+            SectorSpecial::LightBlinkRandom => {
+                blink(64, 7, self.min_surrounding_light(sector_id, max))
+            }
+            SectorSpecial::LightBlinkHalfSecond
+            | SectorSpecial::DamageStrobe
+            | SectorSpecial::LightSyncHalfSecond => blink(5, 15, dark()),
+            SectorSpecial::LightBlinkSecond | SectorSpecial::LightSyncSecond => {
+                blink(5, 35, dark())
+            }
+            // Down to the darkest neighbor and back up, 8 levels per tic.
+            SectorSpecial::LightGlow => {
+                let span = (max - self.min_surrounding_light(sector_id, max)) as u64;
+
+                match span {
+                    0 => max,
+                    _ => {
+                        let position = tic * 8 % (2 * span);
+
+                        max - position.min(2 * span - position) as i16
+                    }
+                }
+            }
+            // Dims by 0 to 48 every 4 tics, never below 16 over the
+            // darkest neighbor.
+            SectorSpecial::LightFlicker => {
+                let min = self.min_surrounding_light(sector_id, max) + 16;
+                let amount = [0, 32, 16, 48][(tic / 4 % 4) as usize];
+
+                match max - amount < min {
+                    true => min,
+                    false => max - amount,
+                }
+            }
+            _ => max,
+        };
+
+        level.clamp(0, 255) as u8
+    }
+}
+
+// A door line, with its opening shut and fully raised.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Door {
+    pub linedef: usize,
+    pub closed: Opening,
+    pub open: Opening,
+}
+
+impl LineDef {
+    // DR, D1 and their blazing versions, the doors opened from the line itself.
+    pub const DOOR_SPECIALS: [i16; 10] = [1, 26, 27, 28, 31, 32, 33, 34, 117, 118];
+
+    pub fn is_door(&self) -> bool {
+        Self::DOOR_SPECIALS.contains(&self.special_type)
+    }
+}
+
+impl Map {
+    // Linedefs with a door special, see `LineDef::DOOR_SPECIALS`.
+    pub fn doors(&self) -> Vec<usize> {
+        self.line_defs
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.is_door())
+            .map(|(line_id, _)| line_id)
+            .collect()
+    }
+
+    // The door sector is behind the line. Like DOOM, it closes down to its
+    // floor and opens up to 4 units below its lowest neighboring ceiling.
+    // None for one-sided or non-door lines.
+    pub fn door(&self, linedef_id: usize) -> Option<Door> {
+        let line = self.line_defs.get(linedef_id).filter(|line| line.is_door())?;
+        let opening = self.line_opening(line)?;
+
+        let sector_id = self.side_def_at(line.left_sidedef)?.sector as usize;
+        let sector = self.sectors.get(sector_id)?;
+        let front = self.linedef_context(line).front_sector?;
+
+        let open_ceiling = self.neighbor_sectors(sector_id)
+            .iter()
+            .map(|&id| self.sectors[id].ceiling_height)
+            .min()
+            .map_or(sector.ceiling_height, |ceiling| ceiling - 4);
+
+        Some(Door {
+            linedef: linedef_id,
+            closed: Opening { top: opening.bottom, ..opening },
+            open: Opening { top: open_ceiling.min(front.ceiling_height), ..opening },
+        })
+    }
+}
+
+impl Map {
+    const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+
+    // FNV-1a, so the value is stable across runs and Rust versions.
+    fn hash_bytes(hash: u64, bytes: &[u8]) -> u64 {
+        bytes.iter().fold(hash, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+        })
+    }
+
+    // Fingerprint of the current map's geometry and things, to tell whether
+    // two WADs ship the same map. The records are hashed in their lump
+    // layout, so the hash doesn't depend on the host, and each lump's length
+    // is hashed before its records so data can't shift between lumps unnoticed.
+    pub fn map_hash(&self) -> u64 {
+        let lumps = [
+            records_to_bytes(&self.things),
+            records_to_bytes(&self.line_defs),
+            records_to_bytes(&self.side_defs),
+            records_to_bytes(&self.vertexes),
+            records_to_bytes(&self.sectors),
+        ];
+
+        lumps.iter().fold(Self::FNV_OFFSET, |hash, bytes| {
+            let hash = Self::hash_bytes(hash, &(bytes.len() as u64).to_le_bytes());
+
+            Self::hash_bytes(hash, bytes)
+        })
+    }
+}
+
+impl Map {
+    // Binary dump of the parsed map for comparing across versions: THINGS,
+    // LINEDEFS, SIDEDEFS, VERTEXES, SEGS, SSECTORS, NODES, SECTORS and
+    // REJECT in that order, each as a u32 LE record count followed by the
+    // records in their lump layout. Segs, subsectors and nodes keep their
+    // indexes 32-bit, like extended nodes.
+    pub fn map_snapshot(&self) -> Vec<u8> {
+        fn records<T: Record>(items: &[T]) -> Vec<u8> {
+            let mut bytes = Vec::with_capacity(items.len() * T::SNAPSHOT_SIZE);
+
+            for item in items {
+                item.write_snapshot(&mut bytes);
+            }
+
+            bytes
+        }
+
+        let lumps = [
+            (self.things.len(), records(&self.things)),
+            (self.line_defs.len(), records(&self.line_defs)),
+            (self.side_defs.len(), records(&self.side_defs)),
+            (self.vertexes.len(), records(&self.vertexes)),
+            (self.segs.len(), records(&self.segs)),
+            (self.ssectors.len(), records(&self.ssectors)),
+            (self.nodes.len(), records(&self.nodes)),
+            (self.sectors.len(), records(&self.sectors)),
+            (self.reject.len(), records(&self.reject)),
+        ];
+
+        let mut snapshot = Vec::new();
+
+        for (count, bytes) in lumps {
+            snapshot.extend_from_slice(&(count as u32).to_le_bytes());
+            snapshot.extend_from_slice(&bytes);
+        }
+
+        snapshot
+    }
+
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<Map, WadError> {
+        let mut reader = LumpReader::new(snapshot);
+
+        fn records<T: Record>(reader: &mut LumpReader) -> Result<Vec<T>, WadError> {
+            let mut read = |len: usize| {
+                let got = reader.bytes.len() - reader.position;
+
+                reader.read_bytes(len).map_err(|_| WAD::truncated("snapshot", len, got))
+            };
+
+            let count = u32::from_le_bytes(read(4)?.try_into().unwrap()) as usize;
+            let mut records = LumpReader::new(read(count.saturating_mul(T::SNAPSHOT_SIZE))?);
+
+            Ok((0..count).map(|_| T::read_snapshot(&mut records)).collect::<io::Result<_>>()?)
+        }
+
+        let map = Map {
+            things: records(&mut reader)?,
+            line_defs: records(&mut reader)?,
+            side_defs: records(&mut reader)?,
+            vertexes: records(&mut reader)?,
+            segs: records(&mut reader)?,
+            ssectors: records(&mut reader)?,
+            nodes: records(&mut reader)?,
+            sectors: records(&mut reader)?,
+            reject: records(&mut reader)?,
+        };
+
+        match reader.position == snapshot.len() {
+            true => Ok(map),
+            false => Err(WadError::InvalidLump("snapshot".to_string())),
+        }
+    }
+}
+
+// - - -
+// Text exports of the current map for other tools.
+#[derive(Clone, Copy, Debug)]
+pub struct ObjOptions {
+    // Map units per exported unit, 32 is about a meter.
+    pub scale: f32,
+    // Y up like Blender's importer, instead of DOOM's Z up.
+    pub y_up: bool,
+    // Negates the map's Y, for tools that expect it to grow south.
+    pub flip_y: bool,
+}
+
+impl Default for ObjOptions {
+    fn default() -> Self {
+        Self { scale: 1.0, y_up: false, flip_y: false }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SvgOptions {
+    pub include_things: bool,
+    // SVG's Y grows down, so this keeps north up. Without it the map's Y is
+    // written as is.
+    pub flip_y: bool,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self { include_things: true, flip_y: true }
+    }
+}
+
+impl ObjOptions {
+    fn vertex(&self, x: f32, y: f32, z: f32) -> String {
+        let y = MapBounds::orient_y(y, self.flip_y);
+        let (x, y, z) = (x / self.scale, y / self.scale, z / self.scale);
+
+        // Y up keeps the handedness, so north ends up at -Z.
+        match self.y_up {
+            true => format!("v {} {} {}\n", x, z, -y),
+            false => format!("v {} {} {}\n", x, y, z),
+        }
+    }
+}
+
+impl Map {
+    // Length of the facing arrow drawn for each thing.
+    const THING_ARROW: f32 = 16.0;
+
+    fn thing_arrow(thing: &Thing) -> (f32, f32) {
+        let angle = (thing.angle as f32).to_radians();
+
+        (
+            thing.x as f32 + angle.cos() * Self::THING_ARROW,
+            thing.y as f32 + angle.sin() * Self::THING_ARROW,
+        )
+    }
+
+    // Map units, Y flipped so north is up unless `options` says otherwise.
+    // One-sided lines are drawn solid, two-sided ones gray, things as a
+    // circle with a line in their facing.
+    pub fn export_svg(&self, options: SvgOptions) -> String {
+        let flip_y = options.flip_y;
+        let y = |y: f32| MapBounds::orient_y(y, flip_y);
+        let bounds = MapBounds::from_vertexes(&self.vertexes).oriented(flip_y);
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            bounds.min_x,
+            bounds.min_y,
+            bounds.max_x - bounds.min_x,
+            bounds.max_y - bounds.min_y,
+        );
+
+        for line in self.line_defs.iter() {
+            let (Some(v1), Some(v2)) = (
+                self.vertexes.get(line.start_vertex as usize),
+                self.vertexes.get(line.end_vertex as usize),
+            ) else {
+                continue;
+            };
+
+            let stroke = match line.is_one_sided() {
+                true => "black",
+                false => "gray",
+            };
+
+            svg += &format!(
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"/>\n",
+                v1.x, y(v1.y as f32), v2.x, y(v2.y as f32), stroke,
+            );
+        }
+
+        if options.include_things {
+            for thing in self.things.iter() {
+                let (arrow_x, arrow_y) = Self::thing_arrow(thing);
+
+                svg += &format!(
+                    "<circle cx=\"{}\" cy=\"{}\" r=\"8\" fill=\"none\" stroke=\"red\"/>\n",
+                    thing.x, y(thing.y as f32),
+                );
+                svg += &format!(
+                    "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"red\"/>\n",
+                    thing.x, y(thing.y as f32), arrow_x, y(arrow_y),
+                );
+            }
+        }
+
+        svg += "</svg>\n";
+        svg
+    }
+
+    // Walls as quads, Z up unless `options` says otherwise: one-sided lines
+    // from floor to ceiling, two-sided ones where the floors or ceilings of
+    // both sides differ. Things are a line from their position at floor
+    // height in their facing.
+    pub fn export_obj(&self, include_things: bool, options: ObjOptions) -> String {
+        let mut obj = String::new();
+        let mut vertex_count = 0;
+
+        let mut quad = |obj: &mut String, v1: &Vertex, v2: &Vertex, bottom: i16, top: i16| {
+            if bottom >= top {
+                return;
+            }
+
+            for (v, z) in [(v1, bottom), (v2, bottom), (v2, top), (v1, top)] {
+                *obj += &options.vertex(v.x as f32, v.y as f32, z as f32);
+            }
+
+            *obj += &format!(
+                "f {} {} {} {}\n",
+                vertex_count + 1, vertex_count + 2, vertex_count + 3, vertex_count + 4,
+            );
+
+            vertex_count += 4;
+        };
+
+        for line in self.line_defs.iter() {
+            let (Some(v1), Some(v2)) = (
+                self.vertexes.get(line.start_vertex as usize),
+                self.vertexes.get(line.end_vertex as usize),
+            ) else {
+                continue;
+            };
+
+            let context = self.linedef_context(line);
+
+            match (context.front_sector, context.back_sector) {
+                (Some(front), None) => {
+                    quad(&mut obj, v1, v2, front.floor_height, front.ceiling_height);
+                }
+                (Some(front), Some(back)) => {
+                    let (floor, ceiling) = (front.floor_height, front.ceiling_height);
+                    let (back_floor, back_ceiling) = (back.floor_height, back.ceiling_height);
+
+                    quad(&mut obj, v1, v2, floor.min(back_floor), floor.max(back_floor));
+                    quad(&mut obj, v1, v2, ceiling.min(back_ceiling), ceiling.max(back_ceiling));
+                }
+                _ => {}
+            }
+        }
+
+        if include_things {
+            for thing in self.things.iter() {
+                let z = self
+                    .sector_at(thing.x as f32, thing.y as f32)
+                    .map_or(0, |sector| sector.floor_height);
+
+                let (x, y) = Self::thing_arrow(thing);
+
+                obj += &options.vertex(thing.x as f32, thing.y as f32, z as f32);
+                obj += &options.vertex(x, y, z as f32);
+                obj += &format!("l {} {}\n", vertex_count + 1, vertex_count + 2);
+
+                vertex_count += 2;
+            }
+        }
+
+        obj
+    }
+
+    // One row per thing under a header row. Names come from `thing_name`,
+    // unknown doomednums get an empty one.
+    pub fn things_csv(&self) -> String {
+        let mut csv = String::from("x,y,angle,type,type_name,category,flags\n");
+
+        for thing in self.things.iter() {
+            csv += &format!(
+                "{},{},{},{},{},{:?},{}\n",
+                thing.x,
+                thing.y,
+                thing.angle,
+                thing.t_type,
+                thing_name(thing.t_type).unwrap_or_default(),
+                thing.category(),
+                thing.flags,
+            );
+        }
+
+        csv
+    }
+
+    pub fn export_things_csv(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.things_csv())
+    }
+}
+
+impl WAD {
+    pub fn setup(&mut self) -> Result<(), WadError> {
+        self.read_header()?;
+        self.read_directory()?;
+
+        Ok(())
+    }
+
+    pub fn open(&mut self, path: &str) -> Result<(), WadError> {
+        self.file = Box::new(fs::File::open(path)?);
+        self.path = Some(path.to_string());
+        self.setup()?;
+
+        Ok(())
+    }
+}
+
+impl WAD {
+    pub fn new(path: &str) -> Result<Self, WadError> {
+        let mut ctx = Self::with_source(Box::new(fs::File::open(path)?))?;
+        ctx.path = Some(path.to_string());
+
+        Ok(ctx)
+    }
+
+    // Only the header and directory are read, map lumps are left alone
+    // until `change_map`, for tools that just inspect the lumps.
+    pub fn open_readonly(path: &str) -> Result<Self, WadError> {
+        Self::new(path)
+    }
+
+    pub fn from_reader<R: Read + Seek + 'static>(reader: R) -> Result<Self, WadError> {
+        Self::with_source(Box::new(reader))
+    }
+
+    // Reads the whole source into memory and drops it, so the file isn't
+    // held open (and locked, on Windows) while the WAD is in use.
+    pub fn into_memory(mut self) -> Result<WAD, WadError> {
+        let mut bytes = Vec::new();
+
+        self.file.seek(io::SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+
+        self.file = Box::new(io::Cursor::new(bytes));
+        self.path = None;
+
+        #[cfg(feature = "mmap")]
+        {
+            self.mmap = None;
+        }
+
+        Ok(self)
+    }
+
+    fn with_source(file: Box<dyn WadSource>) -> Result<Self, WadError> {
+        let mut ctx = Self {
+            map: Map::default(),
+
+            directory: Vec::new(),
+            header: Header::default(),
+
+            map_index: None,
+            file,
+            path: None,
+
+            #[cfg(feature = "mmap")]
+            mmap: None,
+        };
+
+        ctx.setup()?;
+
+        Ok(ctx)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl WAD {
+    // Lump reads copy out of the mapping instead of seeking and reading.
+    pub fn open_mmap(path: &str) -> Result<Self, WadError> {
+        let file = fs::File::open(path)?;
+
+        // The mapping is only read from, it's up to the caller not to
+        // truncate the file while it's open.
+        let mapping = unsafe { memmap2::Mmap::map(&file)? };
+
+        let mut ctx = Self::with_source(Box::new(file))?;
+        ctx.mmap = Some(mapping);
+        ctx.path = Some(path.to_string());
+
+        Ok(ctx)
+    }
+}
+
+// - - -
+// Binary angle measurement: 0x10000 is a full turn, counter-clockwise from
+// east. Returns degrees in [0, 360).
+pub fn bam_to_degrees(bam: u16) -> f32 {
+    bam as f32 * 360.0 / 65536.0
+}
+
+// Wraps `degrees` into a single turn first, 360 maps back to 0.
+pub fn degrees_to_bam(degrees: f32) -> u16 {
+    (degrees.rem_euclid(360.0) * 65536.0 / 360.0).round() as u32 as u16
+}
+
+// - - -
+#[derive(Debug)]
+pub struct Player {
+    pub thing: Thing,
+    pub position: (f32, f32),
+    // Degrees in [0, 360), counter-clockwise from east.
+    pub angle: f32,
+    // Eye height in map units, follows the floor below the player.
+    pub view_z: f32,
+    pub dims: PlayerDims,
+}
+
+// The player's size in map units, for mods with bigger or smaller players.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerDims {
+    // How close the player's center gets to walls.
+    pub radius: f32,
+    // Lower openings can't be walked through.
+    pub height: f32,
+    // Eye height above the floor.
+    pub view_height: f32,
+}
+
+impl Default for PlayerDims {
+    fn default() -> Self {
+        Self { radius: 16.0, height: 56.0, view_height: 41.0 }
+    }
+}
+
+impl Player {
+    pub fn new(thing: Thing) -> Self {
+        Self::with_dims(thing, PlayerDims::default())
+    }
+
+    pub fn with_dims(thing: Thing, dims: PlayerDims) -> Self {
+        Self {
+            thing,
+            position: (thing.x as f32, thing.y as f32),
+            angle: (thing.angle as f32).rem_euclid(360.0),
+            view_z: dims.view_height,
+            dims,
+        }
+    }
+
+    // Degrees in (-180, 180] from the view direction to the point,
+    // positive to the left. Within the FOV when its magnitude is at most half of it.
+    pub fn angle_to_point(&self, x: f32, y: f32) -> f32 {
+        let (px, py) = self.position;
+
+        normalize_degrees((y - py).atan2(x - px).to_degrees() - self.angle)
+    }
+}
+
+// - - -
+// Distance from a point to the closest point of a segment.
+fn point_segment_distance(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len_sq = dx * dx + dy * dy;
+
+    let t = if len_sq == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len_sq).clamp(0.0, 1.0)
+    };
+
+    let (cx, cy) = (a.0 + t * dx, a.1 + t * dy);
+
+    ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt()
+}
+
+// Shoelace formula, positive for counter-clockwise points.
+fn polygon_area(points: &[(f32, f32)]) -> f32 {
+    let edges = points.iter().zip(points.iter().cycle().skip(1));
+
+    edges.map(|(a, b)| a.0 * b.1 - b.0 * a.1).sum::<f32>() / 2.0
+}
+
+// `None` for polygons without an area.
+fn polygon_centroid(points: &[(f32, f32)]) -> Option<(f32, f32)> {
+    let area = polygon_area(points);
+
+    if area.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let edges = points.iter().zip(points.iter().cycle().skip(1));
+
+    let (x, y) = edges.fold((0.0, 0.0), |(x, y), (a, b)| {
+        let cross = a.0 * b.1 - b.0 * a.1;
+
+        (x + (a.0 + b.0) * cross, y + (a.1 + b.1) * cross)
+    });
+
+    Some((x / (6.0 * area), y / (6.0 * area)))
+}
+
+// - - -
+// Game logic, advanced one tic at a time independently of the frame rate.
+pub struct Engine <'a> {
+    pub map_data: &'a Map,
+    pub player: Player,
+}
+
+impl <'a> Engine <'a> {
+    pub const TIC_RATE: u32 = 35;
+
+    // DOOM thrusts 2048/65536 units per tic per move unit and applies a
+    // 0xE800/0x10000 friction, which settles at a third of the move value.
+    const MOVE_SCALE: f32 = 1.0 / 3.0;
+
+    // Taller steps block movement, like in DOOM.
+    const MAX_STEP_HEIGHT: f32 = 24.0;
+
+    // How fast the view catches up with a new floor height, per tic.
+    const VIEW_STEP_SPEED: f32 = 8.0;
+
+    pub fn tic_duration() -> Duration {
+        Duration::from_secs(1) / Self::TIC_RATE
+    }
+
+    fn floor_height_at(&self, x: f32, y: f32) -> f32 {
+        match self.map_data.sector_at(x, y) {
+            Some(sector) => sector.floor_height as f32,
+            None => 0.0,
+        }
+    }
+
+    pub fn can_move_to(&self, x: f32, y: f32) -> bool {
+        let vertexes = &self.map_data.vertexes;
+
+        let (px, py) = self.player.position;
+        let step = self.floor_height_at(x, y) - self.floor_height_at(px, py);
+
+        if step > Self::MAX_STEP_HEIGHT {
+            return false;
+        }
+
+        let dims = self.player.dims;
+
+        if let Some(sector) = self.map_data.sector_at(x, y) {
+            if ((sector.ceiling_height as i32 - sector.floor_height as i32) as f32) < dims.height {
+                return false;
+            }
+        }
+
+        self.map_data
+            .line_defs
+            .iter()
+            .filter(|line| line.is_blocking())
+            .all(|line| {
+                // Lines with a broken vertex reference don't block.
+                let (Some(v1), Some(v2)) = (
+                    vertexes.get(line.start_vertex as usize),
+                    vertexes.get(line.end_vertex as usize),
+                ) else {
+                    return true;
+                };
+
+                point_segment_distance(
+                    (x, y),
+                    (v1.x as f32, v1.y as f32),
+                    (v2.x as f32, v2.y as f32),
+                ) >= dims.radius
+            })
+    }
+
+    fn try_move(&mut self, dx: f32, dy: f32) {
+        let (x, y) = self.player.position;
+
+        // Slide along walls by retrying each axis on its own.
+        for (mx, my) in [(dx, dy), (dx, 0.0), (0.0, dy)] {
+            if self.can_move_to(x + mx, y + my) {
+                self.player.position = (x + mx, y + my);
+
+                return;
+            }
+        }
+    }
+
+    pub fn tic(&mut self, cmd: TicCmd) {
+        // `angle_turn` is the high half of a binary angle, negative turns
+        // right and wraps to the same angle.
+        let turn = bam_to_degrees(cmd.angle_turn as u16);
+        self.player.angle = (self.player.angle + turn).rem_euclid(360.0);
+
+        let angle = self.player.angle.to_radians();
+
+        let forward = cmd.forward_move as f32 * Self::MOVE_SCALE;
+        let side = cmd.side_move as f32 * Self::MOVE_SCALE;
+
+        // Strafing moves 90 degrees clockwise of the view direction.
+        let dx = forward * angle.cos() + side * angle.sin();
+        let dy = forward * angle.sin() - side * angle.cos();
+
+        if dx != 0.0 || dy != 0.0 {
+            self.try_move(dx, dy);
+        }
+
+        self.update_view_z();
+    }
+
+    fn update_view_z(&mut self) {
+        let (x, y) = self.player.position;
+        let target = self.floor_height_at(x, y) + self.player.dims.view_height;
+
+        let delta = (target - self.player.view_z)
+            .clamp(-Self::VIEW_STEP_SPEED, Self::VIEW_STEP_SPEED);
+
+        self.player.view_z += delta;
+    }
+}
+
+impl <'a> Engine <'a> {
+    pub fn new(map_data: &'a Map, player: Player) -> Self {
+        Self { map_data, player: Self::on_floor(map_data, player) }
+    }
+
+    // Moves the view, standing on whatever floor is below the new position.
+    pub fn set_player(&mut self, player: Player) {
+        self.player = Self::on_floor(self.map_data, player);
+    }
+
+    fn on_floor(map_data: &Map, mut player: Player) -> Player {
+        let (x, y) = player.position;
+
+        if let Some(sector) = map_data.sector_at(x, y) {
+            player.view_z = sector.floor_height as f32 + player.dims.view_height;
+        }
+
+        player
+    }
+
+    // Views from `thing`, e.g. a deathmatch start, keeping the player's size.
+    pub fn set_view_thing(&mut self, thing: Thing) {
+        self.set_player(Player::with_dims(thing, self.player.dims));
+    }
+}
+
+impl Engine <'_> {
+    // Closer points are clipped off, so columns stay finite.
+    const NEAR_PLANE: f32 = 1.0;
+
+    // The part of a view space segment, as (ahead, left) ends, in front of
+    // the near plane. Projecting an end behind the player would flip its
+    // column to the other side of the screen.
+    fn clip_to_near_plane(
+        a: (f32, f32),
+        b: (f32, f32),
+    ) -> Option<((f32, f32), (f32, f32))> {
+        let near = Self::NEAR_PLANE;
+
+        // Where the segment crosses the plane, from `front` towards `behind`.
+        let split = |front: (f32, f32), behind: (f32, f32)| {
+            let t = (front.0 - near) / (front.0 - behind.0);
+
+            (near, front.1 + t * (behind.1 - front.1))
+        };
+
+        match (a.0 >= near, b.0 >= near) {
+            (true, true) => Some((a, b)),
+            (true, false) => Some((a, split(a, b))),
+            (false, true) => Some((split(b, a), b)),
+            (false, false) => None,
+        }
+    }
+
+    // Screen columns from the seg's left end to its right end, clamped to
+    // [0, `screen_width`], with `BSP::FOV` spanning the whole width. None
+    // when the seg is behind the player or out of the view to either side.
+    pub fn project_seg(&self, seg: &Seg, screen_width: f32) -> Option<(i32, i32)> {
+        let (start, end) = self.seg_to_view(seg)?;
+        let (a, b) = Self::clip_to_near_plane(start, end)?;
+
+        let half = screen_width / 2.0;
+        let focal = Self::focal_length(screen_width);
+        let column = |(ahead, left): (f32, f32)| half - left / ahead * focal;
+
+        let (x1, x2) = (column(a), column(b));
+        let (left, right) = (x1.min(x2), x1.max(x2));
+
+        if right < 0.0 || left > screen_width {
+            return None;
+        }
+
+        Some((left.max(0.0).round() as i32, right.min(screen_width).round() as i32))
+    }
+
+    // The seg's ends as (distance ahead, distance to the left) in view space.
+    fn seg_to_view(&self, seg: &Seg) -> Option<((f32, f32), (f32, f32))> {
+        let vertexes = &self.map_data.vertexes;
+        let start = vertexes.get(seg.start_vertex as usize)?;
+        let end = vertexes.get(seg.end_vertex as usize)?;
+
+        let (px, py) = self.player.position;
+        let (sin, cos) = self.player.angle.to_radians().sin_cos();
+
+        let to_view = |v: &Vertex| {
+            let (dx, dy) = (v.x as f32 - px, v.y as f32 - py);
+
+            (dx * cos + dy * sin, dy * cos - dx * sin)
+        };
+
+        Some((to_view(start), to_view(end)))
+    }
+
+    // Columns per unit of left/ahead, `BSP::FOV` spans the whole width.
+    fn focal_length(screen_width: f32) -> f32 {
+        screen_width / 2.0 / (BSP::FOV / 2.0).to_radians().tan()
+    }
+
+    // The column of a `texture_width` wide texture the seg's wall shows at
+    // screen column `x`, lined up by `Seg::texture_offset`. None when the
+    // column doesn't see the seg.
+    pub fn wall_texture_column(
+        &self,
+        seg: &Seg,
+        sidedef: &SideDef,
+        x: f32,
+        screen_width: f32,
+        texture_width: u16,
+    ) -> Option<u16> {
+        let ((a0, l0), (a1, l1)) = self.seg_to_view(seg)?;
+
+        // The column looks along left = ratio * ahead, find where it crosses the seg.
+        let ratio = (screen_width / 2.0 - x) / Self::focal_length(screen_width);
+        let t = (ratio * a0 - l0) / ((l1 - l0) - ratio * (a1 - a0));
+
+        if !(0.0..=1.0).contains(&t) || a0 + t * (a1 - a0) < Self::NEAR_PLANE {
+            return None;
+        }
+
+        let length = ((a1 - a0).powi(2) + (l1 - l0).powi(2)).sqrt();
+
+        Some(seg.texture_column(sidedef, t * length, texture_width))
+    }
+}
+
+// - - -
+pub struct BSP <'a> {
+    pub map_data: &'a Map, 
+    pub root_node_id: usize,
+}
+
+impl <'a> BSP <'a> {
+    pub fn is_point_on_back_side(x: f32, y: f32, node: &Node) -> bool {
+        let dx = x - node.x_partition as f32;
+        let dy = y - node.y_partition as f32;
+
+        dx * node.dy_partition as f32 - dy * node.dx_partition as f32 <= 0.0
+    }
+
+    pub fn is_on_back_side(&self, renderer: &mut MapViewer, node: &Node) -> bool {
+        let (x, y) = renderer.engine.player.position;
+
+        Self::is_point_on_back_side(x, y, node)
+    }
+
+    pub fn render_sub_sector(&self, renderer: &mut MapViewer, sub_sector_id: u32) {
+        let (mode, show_minisegs) = (renderer.mode, renderer.show_minisegs);
+        let sub_sector_id = sub_sector_id as usize;
+
+        renderer.with_view_canvas(|drawer, canvas, stats| match mode {
+            ViewMode::Subsectors => {
+                stats.sub_sectors_visited += 1;
+                drawer.draw_sub_sector(canvas, sub_sector_id);
+            }
+            ViewMode::Segs => {
+                drawer.draw_sub_sector_segs(canvas, sub_sector_id, show_minisegs, stats)
+            }
+        });
+    }
+
+    pub fn render_bsp_node(&self, renderer: &mut MapViewer, node_id: u32) {
+        let sub_sector_identifier = Map::SUB_SECTOR_FLAG;
+
+        #[allow(unused_assignments)]
+        let mut sub_sector_id = Map::SUB_SECTOR_FLAG;
+ 
+        if node_id >= sub_sector_identifier {
+            sub_sector_id = node_id - sub_sector_identifier;
+
+            self.render_sub_sector(renderer, sub_sector_id);            
+            return
+        }
+
+        let node = &self.map_data.nodes[node_id as usize];
+
+        if self.is_on_back_side(renderer, node) {
+            self.render_bsp_node(renderer, node.back_child);
+            self.render_bsp_node(renderer, node.front_child);
+        } else {
+            self.render_bsp_node(renderer, node.front_child);
+            self.render_bsp_node(renderer, node.back_child);
+        }
+
+            
+    }
+
+    pub fn update(&self, renderer: &mut MapViewer) {
+        let mut visible = Vec::new();
+
+        self.walk_visible(&renderer.engine.player, Self::FOV, &mut |sub_sector_id| {
+            visible.push(sub_sector_id)
+        });
+
+        for sub_sector_id in visible {
+            self.render_sub_sector(renderer, sub_sector_id);
+        }
+    }
+}
+
+// `(a - start)` wrapped into [0, 360) falls within the arc's width.
+fn arc_contains(start: f32, width: f32, a: f32) -> bool {
+    (a - start).rem_euclid(360.0) <= width
+}
+
+// Wraps degrees into (-180, 180].
+fn normalize_degrees(angle: f32) -> f32 {
+    let angle = angle.rem_euclid(360.0);
+
+    match angle > 180.0 {
+        true => angle - 360.0,
+        false => angle,
+    }
+}
+
+impl <'a> BSP <'a> {
+    pub const FOV: f32 = 90.0;
+
+    // Whether part of `bbox` lies within `fov` degrees around the view direction.
+    pub fn is_bbox_visible(player: &Player, fov: f32, bbox: [i16; 4]) -> bool {
+        let (px, py) = player.position;
+
+        let top = bbox[0] as f32;
+        let bottom = bbox[1] as f32;
+        let left = bbox[2] as f32;
+        let right = bbox[3] as f32;
+
+        if px >= left && px <= right && py >= bottom && py <= top {
+            return true;
+        }
+
+        // Seen from outside, the box spans less than 180 degrees around the
+        // direction of its center.
+        let center = ((top + bottom) / 2.0 - py)
+            .atan2((left + right) / 2.0 - px)
+            .to_degrees();
+
+        let mut min: f32 = 0.0;
+        let mut max: f32 = 0.0;
+
+        for (x, y) in [(left, top), (right, top), (left, bottom), (right, bottom)] {
+            let offset = normalize_degrees((y - py).atan2(x - px).to_degrees() - center);
+
+            min = min.min(offset);
+            max = max.max(offset);
+        }
+
+        let box_start = center + min - player.angle;
+        let view_start = -fov / 2.0;
+
+        arc_contains(box_start, max - min, view_start) || arc_contains(view_start, fov, box_start)
+    }
+
+    // Visits the subsectors front to back, skipping nodes whose bounding box
+    // is outside the field of view (in degrees).
+    pub fn walk_visible(&self, player: &Player, fov: f32, visit: &mut dyn FnMut(u32)) {
+        if self.map_data.nodes.is_empty() {
+            visit(0);
+
+            return;
+        }
+
+        self.walk_visible_node(player, fov, self.root_node_id as u32, visit);
+    }
+
+    fn walk_visible_node(&self, player: &Player, fov: f32, node_id: u32, visit: &mut dyn FnMut(u32)) {
+        if node_id & Map::SUB_SECTOR_FLAG != 0 {
+            visit(node_id & !Map::SUB_SECTOR_FLAG);
+
+            return;
+        }
+
+        // Broken child references are skipped, like in `cross_node`.
+        let Some(node) = self.map_data.nodes.get(node_id as usize) else {
+            return;
+        };
+
+        let front = (node.front_child, node.front_bbox);
+        let back = (node.back_child, node.back_bbox);
+
+        let (x, y) = player.position;
+
+        let children = match Self::is_point_on_back_side(x, y, node) {
+            true => [back, front],
+            false => [front, back],
+        };
+
+        for (child, bbox) in children {
+            if Self::is_bbox_visible(player, fov, bbox) {
+                self.walk_visible_node(player, fov, child, visit);
+            }
+        }
+    }
+
+    // Segs the visible walk reaches, without drawing anything, as a
+    // measure of how well the culling works.
+    pub fn count_visible_segs(&self, player: &Player, fov: f32) -> usize {
+        let mut count = 0;
+
+        self.walk_visible(player, fov, &mut |sub_sector_id| {
+            count += self.map_data.subsector_segs(sub_sector_id as usize).len();
+        });
+
+        count
+    }
+
+    // Visits every subsector, nearest first or farthest first.
+    pub fn walk_ordered(&self, player: &Player, order: TraversalOrder, visit: &mut dyn FnMut(u32)) {
+        if self.map_data.nodes.is_empty() {
+            visit(0);
+
+            return;
+        }
+
+        self.walk_ordered_node(player, order, self.root_node_id as u32, visit);
+    }
+
+    fn walk_ordered_node(&self, player: &Player, order: TraversalOrder, node_id: u32, visit: &mut dyn FnMut(u32)) {
+        if node_id & Map::SUB_SECTOR_FLAG != 0 {
+            visit(node_id & !Map::SUB_SECTOR_FLAG);
+
+            return;
+        }
+
+        let Some(node) = self.map_data.nodes.get(node_id as usize) else {
+            return;
+        };
+
+        let (x, y) = player.position;
+
+        // The side the player is on is the near one.
+        let near_is_back = Self::is_point_on_back_side(x, y, node);
+
+        let children = match near_is_back == (order == TraversalOrder::FrontToBack) {
+            true => [node.back_child, node.front_child],
+            false => [node.front_child, node.back_child],
+        };
+
+        for child in children {
+            self.walk_ordered_node(player, order, child, visit);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraversalOrder {
+    // Nearest first, for renderers that clip against what's already drawn.
+    #[default]
+    FrontToBack,
+    // Farthest first, for painter's algorithm renderers.
+    BackToFront,
+}
+
+impl <'a> BSP <'a> {
+    // Whether `from` and `to` can see each other, ignoring heights: blocked
+    // by one-sided lines and two-sided ones with no opening between their
+    // sectors. Like DOOM, only the subsectors the sightline passes through
+    // are checked.
+    pub fn line_of_sight(&self, from: (f32, f32), to: (f32, f32)) -> bool {
+        let map = self.map_data;
+
+        let from_sector = map.subsector_sector(map.subsector_at(from.0, from.1));
+        let to_sector = map.subsector_sector(map.subsector_at(to.0, to.1));
+
+        if let (Some(from_sector), Some(to_sector)) = (from_sector, to_sector) {
+            if map.is_rejected(from_sector, to_sector) {
+                return false;
+            }
+        }
+
+        self.cross_node(self.root_node_id as u32, from, to)
+    }
+
+    fn cross_node(&self, node_id: u32, from: (f32, f32), to: (f32, f32)) -> bool {
+        if node_id & Map::SUB_SECTOR_FLAG != 0 {
+            return self.cross_sub_sector((node_id & !Map::SUB_SECTOR_FLAG) as usize, from, to);
+        }
+
+        let Some(node) = self.map_data.nodes.get(node_id as usize) else {
+            return true;
+        };
+
+        let side = |(x, y): (f32, f32)| Self::is_point_on_back_side(x, y, node);
+        let child = |back: bool| match back {
+            true => node.back_child,
+            false => node.front_child,
+        };
+
+        // Both ends on the same side, the sightline doesn't leave it.
+        if side(from) == side(to) {
+            return self.cross_node(child(side(from)), from, to);
+        }
+
+        self.cross_node(child(side(from)), from, to) && self.cross_node(child(side(to)), from, to)
+    }
+
+    fn cross_sub_sector(&self, sub_sector_id: usize, from: (f32, f32), to: (f32, f32)) -> bool {
+        let map = self.map_data;
+
+        // Positive on one side of the line through `a` and `b`, negative on the other.
+        let side = |a: (f32, f32), b: (f32, f32), p: (f32, f32)| {
+            (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+        };
+
+        for seg in map.subsector_segs(sub_sector_id) {
+            // Minisegs never block.
+            let (Some(v1), Some(v2), Some(line)) = (
+                map.vertexes.get(seg.start_vertex as usize),
+                map.vertexes.get(seg.end_vertex as usize),
+                map.seg_linedef(seg),
+            ) else {
+                continue;
+            };
+
+            let (v1, v2) = ((v1.x as f32, v1.y as f32), (v2.x as f32, v2.y as f32));
+
+            let crosses = side(v1, v2, from) * side(v1, v2, to) < 0.0
+                && side(from, to, v1) * side(from, to, v2) < 0.0;
+
+            if !crosses {
+                continue;
+            }
+
+            if map.line_opening(line).is_none_or(|opening| opening.height() <= 0) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl <'a> BSP <'a> {
+    pub fn new(map_data: &'a Map) -> Self {
+        let root_node_id = map_data.nodes.len() - 1;
+
+        Self {
+            map_data,
+            root_node_id,
+        }
+    }
+}
+
+// - - -
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sfml::{
+    graphics::{
+        CircleShape, Color, ConvexShape, FloatRect, Font, RectangleShape, RenderTarget,
+        RenderTexture, RenderWindow, Shape, Sprite, Text, Transformable, View,
+    },
+    system::Vector2f,
+    SfBox,
+    window::{ContextSettings, Event, Key, Style},
+};
+
+// - - -
+// Drawing primitives the map drawing code goes through, so it isn't tied to
+// an SFML window.
+pub trait Canvas {
+    fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, color: Color);
+    // `width` pixels across, canvases without line widths draw a plain line.
+    fn thick_line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, _width: f32, color: Color) {
+        self.line(x1, y1, x2, y2, color);
+    }
+    fn circle(&mut self, x: f32, y: f32, radius: f32, color: Color);
+    // Outline only.
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color);
+    // Filled, the points must form a convex polygon.
+    fn polygon(&mut self, points: &[(f32, f32)], color: Color);
+}
+
+pub struct SfmlCanvas <'w, T: RenderTarget> {
+    pub target: &'w mut T,
+}
+
+impl <'w, T: RenderTarget> SfmlCanvas <'w, T> {
+    pub fn new(target: &'w mut T) -> Self {
+        Self { target }
+    }
+}
+
+impl <T: RenderTarget> Canvas for SfmlCanvas <'_, T> {
+    fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, color: Color) {
+        self.thick_line(x1, y1, x2, y2, 1.0, color);
+    }
+
+    fn thick_line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, width: f32, color: Color) {
+        // Nothing to draw, and no direction to rotate it to.
+        if (x1, y1) == (x2, y2) {
+            return;
+        }
+
+        let length = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
+
+        // Centered on the line, so thick lines don't lean to one side.
+        let mut line = RectangleShape::new();
+        line.set_fill_color(color);
+        line.set_size(Vector2f::new(length, width));
+        line.set_origin(Vector2f::new(0.0, width / 2.0));
+        line.set_position(Vector2f::new(x1, y1));
+        line.set_rotation((y2 - y1).atan2(x2 - x1).to_degrees());
+
+        self.target.draw(&line);
+    }
+
+    fn circle(&mut self, x: f32, y: f32, radius: f32, color: Color) {
+        let mut circle = CircleShape::new(radius, 30);
+        circle.set_fill_color(color);
+        circle.set_position(Vector2f::new(x - radius, y - radius));
+
+        self.target.draw(&circle);
+    }
+
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        let mut rect = RectangleShape::new();
+        rect.set_fill_color(Color::TRANSPARENT);
+        rect.set_outline_color(color);
+        rect.set_outline_thickness(1.0);
+        rect.set_size(Vector2f::new(width, height));
+        rect.set_position(Vector2f::new(x, y));
+
+        self.target.draw(&rect);
+    }
+
+    fn polygon(&mut self, points: &[(f32, f32)], color: Color) {
+        let mut polygon = ConvexShape::new(points.len());
+        polygon.set_fill_color(color);
+
+        for (i, point) in points.iter().enumerate() {
+            polygon.set_point(i, Vector2f::new(point.0, point.1));
+        }
+
+        self.target.draw(&polygon);
+    }
+}
+
+// - - -
+// Extent of the map's vertexes, used to fit the map to the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MapBounds {
+    pub min_x: f32,
+    pub max_x: f32,
+    pub min_y: f32,
+    pub max_y: f32,
+}
+
+impl MapBounds {
+    pub fn from_vertexes(vertexes: &[Vertex]) -> Self {
+        if vertexes.is_empty() {
+            return Self::default();
+        }
+
+        let mut bounds = Self {
+            min_x: f32::MAX,
+            max_x: f32::MIN,
+            min_y: f32::MAX,
+            max_y: f32::MIN,
+        };
+
+        for vertex in vertexes.iter() {
+            bounds.min_x = bounds.min_x.min(vertex.x as f32);
+            bounds.max_x = bounds.max_x.max(vertex.x as f32);
+            bounds.min_y = bounds.min_y.min(vertex.y as f32);
+            bounds.max_y = bounds.max_y.max(vertex.y as f32);
+        }
+
+        bounds
+    }
+
+    // Border left around the map on every side, in pixels.
+    pub const DEFAULT_MARGIN: f32 = 30.0;
+
+    // Y as exported, negated for formats whose Y grows down.
+    pub fn orient_y(y: f32, flip_y: bool) -> f32 {
+        match flip_y {
+            true => -y,
+            false => y,
+        }
+    }
+
+    // The bounds of the exported points, see `orient_y`.
+    pub fn oriented(&self, flip_y: bool) -> Self {
+        match flip_y {
+            true => Self { min_y: -self.max_y, max_y: -self.min_y, ..*self },
+            false => *self,
+        }
+    }
+
+    // `w` - Screen width.
+    pub fn to_screen_x(&self, x: f32, w: f32, margin: f32) -> f32 {
+        (x.min(self.max_x).max(self.min_x) - self.min_x)
+            * ((w - margin) - margin)
+            / (self.max_x - self.min_x)
+            + margin
+    }
+
+    // `h` - Screen height, the Y axis is flipped.
+    pub fn to_screen_y(&self, y: f32, h: f32, margin: f32) -> f32 {
+        h
+            - (y.min(self.max_y).max(self.min_y) - self.min_y)
+                * ((h - margin) - margin)
+                / (self.max_y - self.min_y)
+            - margin
+    }
+
+    // Inverse of `to_screen_x`, for points within the map's extent.
+    pub fn from_screen_x(&self, sx: f32, w: f32, margin: f32) -> f32 {
+        (sx - margin) * (self.max_x - self.min_x) / ((w - margin) - margin) + self.min_x
+    }
+
+    // Inverse of `to_screen_y`, for points within the map's extent.
+    pub fn from_screen_y(&self, sy: f32, h: f32, margin: f32) -> f32 {
+        (h - margin - sy) * (self.max_y - self.min_y) / ((h - margin) - margin) + self.min_y
+    }
+
+    // Map-space lines at every multiple of `spacing` within the bounds,
+    // vertical ones first.
+    pub fn grid_lines(&self, spacing: f32) -> Vec<((f32, f32), (f32, f32))> {
+        if spacing <= 0.0 {
+            return Vec::new();
+        }
+
+        let multiples = |min: f32, max: f32| {
+            ((min / spacing).ceil() as i32..=(max / spacing).floor() as i32)
+                .map(move |i| i as f32 * spacing)
+        };
+
+        let vertical = multiples(self.min_x, self.max_x)
+            .map(|x| ((x, self.min_y), (x, self.max_y)));
+        let horizontal = multiples(self.min_y, self.max_y)
+            .map(|y| ((self.min_x, y), (self.max_x, y)));
+
+        vertical.chain(horizontal).collect()
+    }
+}
+
+// - - -
+// How the automap is laid out on screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AutomapMode {
+    // The whole map, fit to the screen.
+    #[default]
+    Fixed,
+    // Same scale, centered on the player.
+    Follow,
+    // Centered on the player and turned so they face up.
+    Rotate,
+}
+
+// Automap line categories, see `LineDef::automap_kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    OneSided,
+    TwoSided,
+    Secret,
+    // Lines with a special, doors and switches among them.
+    Action,
+}
+
+impl LineDef {
+    // Secret lines come first, they're meant to pass for walls.
+    pub fn automap_kind(&self) -> LineKind {
+        if self.has_flag(LineDefFlags::Secret) {
+            return LineKind::Secret;
+        }
+
+        match (self.special_type != 0, self.is_one_sided()) {
+            (true, _) => LineKind::Action,
+            (false, true) => LineKind::OneSided,
+            (false, false) => LineKind::TwoSided,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineColors {
+    pub one_sided: Color,
+    pub two_sided: Color,
+    pub secret: Color,
+    pub action: Color,
+}
+
+impl Default for LineColors {
+    fn default() -> Self {
+        Self {
+            one_sided: Color::WHITE,
+            two_sided: Color::rgb(191, 123, 75),
+            secret: Color::WHITE,
+            action: Color::CYAN,
+        }
+    }
+}
+
+impl LineColors {
+    pub fn color(&self, kind: LineKind) -> Color {
+        match kind {
+            LineKind::OneSided => self.one_sided,
+            LineKind::TwoSided => self.two_sided,
+            LineKind::Secret => self.secret,
+            LineKind::Action => self.action,
+        }
+    }
+}
+
+// The automap drawing code, independent of where it's drawn.
+#[derive(Clone, Copy)]
+pub struct MapDrawer <'a> {
+    pub map_data: &'a Map,
+    pub bounds: MapBounds,
+    pub width: f32,
+    pub height: f32,
+    pub margin: f32,
+    pub automap_mode: AutomapMode,
+    // Map position and angle (degrees) the follow modes center on.
+    pub view_position: (f32, f32),
+    pub view_angle: f32,
+    pub line_colors: LineColors,
+    pub color_mode: ColorMode,
+    // Magnification on top of the mode's own scale, 1.0 fits the map.
+    pub zoom: f32,
+    // Wall thickness in pixels at a zoom of 1.0.
+    pub line_width: f32,
+}
+
+impl <'a> MapDrawer <'a> {
+    pub fn new(map_data: &'a Map, width: f32, height: f32) -> Self {
+        Self {
+            map_data,
+            bounds: MapBounds::from_vertexes(&map_data.vertexes),
+            width,
+            height,
+            margin: MapBounds::DEFAULT_MARGIN,
+            automap_mode: AutomapMode::default(),
+            view_position: (0.0, 0.0),
+            view_angle: 90.0,
+            line_colors: LineColors::default(),
+            color_mode: ColorMode::default(),
+            zoom: 1.0,
+            line_width: 1.0,
+        }
+    }
+
+    pub fn traslate_vertex_x(&self, x: f32) -> f32 {
+        self.bounds.to_screen_x(x, self.width, self.margin)
+    }
+
+    pub fn traslate_vertex_y(&self, y: f32) -> f32 {
+        self.bounds.to_screen_y(y, self.height, self.margin)
+    }
+
+    // Map units to pixels in the follow modes, the smaller of the fixed
+    // mode's scales so both axes are scaled alike.
+    fn follow_scale(&self) -> f32 {
+        let bounds = self.bounds;
+
+        let x_scale = (self.width - 2.0 * self.margin) / (bounds.max_x - bounds.min_x);
+        let y_scale = (self.height - 2.0 * self.margin) / (bounds.max_y - bounds.min_y);
+
+        x_scale.min(y_scale)
+    }
+
+    // Magnified by `zoom` around the middle of the screen.
+    pub fn to_screen(&self, x: f32, y: f32) -> (f32, f32) {
+        let (sx, sy) = self.unzoomed_to_screen(x, y);
+        let (cx, cy) = (self.width / 2.0, self.height / 2.0);
+
+        (cx + (sx - cx) * self.zoom, cy + (sy - cy) * self.zoom)
+    }
+
+    fn unzoomed_to_screen(&self, x: f32, y: f32) -> (f32, f32) {
+        let (px, py) = self.view_position;
+        let (dx, dy) = (x - px, y - py);
+
+        let (dx, dy) = match self.automap_mode {
+            AutomapMode::Fixed => return (self.traslate_vertex_x(x), self.traslate_vertex_y(y)),
+            AutomapMode::Follow => (dx, dy),
+            // Turns the view angle onto 90 degrees, straight up.
+            AutomapMode::Rotate => {
+                let (sin, cos) = (90.0 - self.view_angle).to_radians().sin_cos();
+
+                (dx * cos - dy * sin, dx * sin + dy * cos)
+            }
+        };
+
+        let scale = self.follow_scale();
+
+        (self.width / 2.0 + dx * scale, self.height / 2.0 - dy * scale)
+    }
+
+    // Inverse of `to_screen`, for the map point under the mouse.
+    pub fn from_screen(&self, x: f32, y: f32) -> (f32, f32) {
+        let (cx, cy) = (self.width / 2.0, self.height / 2.0);
+        let (x, y) = (cx + (x - cx) / self.zoom, cy + (y - cy) / self.zoom);
+
+        if self.automap_mode == AutomapMode::Fixed {
+            return (
+                self.bounds.from_screen_x(x, self.width, self.margin),
+                self.bounds.from_screen_y(y, self.height, self.margin),
+            );
+        }
+
+        let scale = self.follow_scale();
+        let (dx, dy) = ((x - self.width / 2.0) / scale, (self.height / 2.0 - y) / scale);
+
+        let (dx, dy) = match self.automap_mode {
+            AutomapMode::Rotate => {
+                let (sin, cos) = (90.0 - self.view_angle).to_radians().sin_cos();
+
+                (dx * cos + dy * sin, dy * cos - dx * sin)
+            }
+            _ => (dx, dy),
+        };
+
+        (self.view_position.0 + dx, self.view_position.1 + dy)
+    }
+
+    // Zero-length lines, from degenerate linedefs and segs, are skipped.
+    fn map_line(&self, canvas: &mut dyn Canvas, from: (f32, f32), to: (f32, f32), color: Color) {
+        if from == to {
+            return;
+        }
+
+        let (x1, y1) = self.to_screen(from.0, from.1);
+        let (x2, y2) = self.to_screen(to.0, to.1);
+
+        canvas.thick_line(x1, y1, x2, y2, self.line_thickness(), color);
+    }
+
+    // Walls get thicker as the map is zoomed in, so they neither thin out
+    // to a hairline up close nor get lost among the grid from afar.
+    pub fn line_thickness(&self) -> f32 {
+        self.line_width * self.zoom
+    }
+}
+
+impl <'a> MapDrawer <'a> {
+    pub fn draw_bbox(&self, canvas: &mut dyn Canvas, bbox: [i16; 4], color: Color) {
+        self.draw_rect(canvas, Rect::from_bbox(bbox), color);
+    }
+
+    pub fn draw_rect(&self, canvas: &mut dyn Canvas, rect: Rect, color: Color) {
+        let (left, right) = (rect.left as f32, rect.right as f32);
+        let (top, bottom) = (rect.top as f32, rect.bottom as f32);
+
+        // Turned rects are drawn as their four sides.
+        if self.automap_mode == AutomapMode::Rotate {
+            let corners = [(left, top), (right, top), (right, bottom), (left, bottom)];
+
+            for (i, &corner) in corners.iter().enumerate() {
+                self.map_line(canvas, corner, corners[(i + 1) % 4], color);
+            }
+
+            return;
+        }
+
+        let (x, y) = self.to_screen(left, top);
+        let (x2, y2) = self.to_screen(right, bottom);
+
+        canvas.rect(x, y, x2 - x, y2 - y, color);
+    }
+
+    pub fn draw_node(&self, canvas: &mut dyn Canvas, node_id: usize) {
+        let node = &self.map_data.nodes[node_id];
+
+        self.draw_rect(canvas, node.front_rect(), Color::GREEN);
+        self.draw_rect(canvas, node.back_rect(), Color::RED);
+
+        let (x, y) = (node.x_partition as f32, node.y_partition as f32);
+        let (dx, dy) = (node.dx_partition as f32, node.dy_partition as f32);
+
+        self.map_line(canvas, (x, y), (x + dx, y + dy), Color::BLUE);
+    }
+
+    pub fn draw_grid(&self, canvas: &mut dyn Canvas, spacing: f32) {
+        for (from, to) in self.bounds.grid_lines(spacing) {
+            self.map_line(canvas, from, to, Color::rgb(25, 25, 35));
+        }
+    }
+
+    pub fn draw_linedefs(&self, canvas: &mut dyn Canvas) {
+        let linedefs = &self.map_data.line_defs;
+
+        for line in linedefs.iter() {
+            let vertex1 = self.map_data.vertexes[line.start_vertex as usize];
+            let vertex2 = self.map_data.vertexes[line.end_vertex as usize];
+
+            self.map_line(
+                canvas,
+                (vertex1.x as f32, vertex1.y as f32),
+                (vertex2.x as f32, vertex2.y as f32),
+                self.line_colors.color(line.automap_kind()),
+            );
+        } 
+    }
+
+    // The linedefs triggering the sectors with `tag`, over the plain ones.
+    pub fn draw_tagged_lines(&self, canvas: &mut dyn Canvas, tag: i16) {
+        for line_id in self.map_data.linedefs_with_tag(tag) {
+            let line = &self.map_data.line_defs[line_id];
+
+            let vertex1 = self.map_data.vertexes[line.start_vertex as usize];
+            let vertex2 = self.map_data.vertexes[line.end_vertex as usize];
+
+            self.map_line(
+                canvas,
+                (vertex1.x as f32, vertex1.y as f32),
+                (vertex2.x as f32, vertex2.y as f32),
+                Color::YELLOW,
+            );
+        }
+    }
+
+    pub fn draw_vertexes(&self, canvas: &mut dyn Canvas) {
+        let vertexes = &self.map_data.vertexes;
+
+        for vertex in vertexes.iter() {
+            let (x, y) = self.to_screen(vertex.x as f32, vertex.y as f32);
+
+            canvas.circle(x, y, 2.0, Color::WHITE);
+        }
+    }
+
+    pub fn thing_color(category: ThingCategory) -> Color {
+        match category {
+            ThingCategory::PlayerStart => Color::GREEN,
+            ThingCategory::Monster => Color::RED,
+            ThingCategory::Item => Color::YELLOW,
+            ThingCategory::Other => Color::rgb(110, 110, 110),
+        }
+    }
+
+    pub fn draw_things(&self, canvas: &mut dyn Canvas) {
+        let things = &self.map_data.things;
+
+        for thing in things.iter() {
+            let color = Self::thing_color(thing.category());
+
+            let (x, y) = self.to_screen(thing.x as f32, thing.y as f32);
+
+            // Thing angles are in degrees, counter-clockwise from east. The
+            // arrow's direction comes from the projection, so it turns with
+            // the rotating automap, and it's always 8 pixels long.
+            let angle = (thing.angle as f32).to_radians();
+            let (ax, ay) = self.to_screen(
+                thing.x as f32 + angle.cos(),
+                thing.y as f32 + angle.sin(),
+            );
+            let length = (ax - x).hypot(ay - y).max(f32::EPSILON);
+
+            canvas.circle(x, y, 2.0, color);
+            canvas.line(x, y, x + (ax - x) / length * 8.0, y + (ay - y) / length * 8.0, color);
+        }
+    }
+
+    pub fn draw_segment(&self, canvas: &mut dyn Canvas, seg: Seg) {
+        let vertex1 = self.map_data.vertexes[seg.start_vertex as usize];
+        let vertex2 = self.map_data.vertexes[seg.end_vertex as usize];
+
+        let color = Self::seg_color(&seg);
+
+        self.map_line(
+            canvas,
+            (vertex1.x as f32, vertex1.y as f32),
+            (vertex2.x as f32, vertex2.y as f32),
+            color,
+        );
+    }
+}
+
+impl MapDrawer <'_> {
+    pub fn seg_color(seg: &Seg) -> Color {
+        match seg.is_miniseg() {
+            true => Color::MAGENTA,
+            false => Color::rgb(110, 110, 110),
+        }
+    }
+
+    // Indexes of the minisegs among `segs`.
+    pub fn minisegs(segs: &[Seg]) -> Vec<usize> {
+        segs.iter()
+            .enumerate()
+            .filter(|(_, seg)| seg.is_miniseg())
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+impl <'a> MapDrawer <'a> {
+    // Nodes from the root down, `depth` levels of them: 1 is just the root.
+    pub fn nodes_within_depth(&self, depth: usize) -> Vec<usize> {
+        let nodes = &self.map_data.nodes;
+
+        let mut level = match nodes.is_empty() || depth == 0 {
+            true => Vec::new(),
+            false => vec![nodes.len() - 1],
+        };
+
+        let mut found = Vec::new();
+
+        for _ in 0..depth {
+            let children = level
+                .iter()
+                .flat_map(|&id| [nodes[id].front_child, nodes[id].back_child])
+                .filter(|&child| child & Map::SUB_SECTOR_FLAG == 0 && (child as usize) < nodes.len())
+                .map(|child| child as usize)
+                .collect();
+
+            found.append(&mut level);
+            level = children;
+        }
+
+        found
+    }
+
+    pub fn draw_nodes(&self, canvas: &mut dyn Canvas, depth: usize) {
+        for node_id in self.nodes_within_depth(depth) {
+            self.draw_node(canvas, node_id);
+        }
+    }
+}
+
+impl <'a> MapDrawer <'a> {
+    // Same color for a subsector on every frame.
+    pub fn sub_sector_color(sub_sector_id: usize) -> Color {
+        let mut rng = StdRng::seed_from_u64(sub_sector_id as u64);
+
+        Color::rgb(rng.gen_range(64..255), rng.gen_range(64..255), rng.gen_range(64..255))
+    }
+
+    // Same color for a sector on every frame, and across its subsectors and
+    // builds.
+    pub fn sector_color(sector_id: usize) -> Color {
+        let hash = Map::hash_bytes(Map::FNV_OFFSET, &(sector_id as u64).to_le_bytes());
+        let [r, g, b, ..] = hash.to_le_bytes();
+
+        Color::rgb(64 + r % 191, 64 + g % 191, 64 + b % 191)
+    }
+
+    // Subsectors whose sector can't be resolved keep their own color.
+    pub fn sub_sector_fill(&self, sub_sector_id: usize) -> Color {
+        let sector = match self.color_mode {
+            ColorMode::BySubsector => None,
+            ColorMode::BySector => self.map_data.subsector_sector(sub_sector_id),
+        };
+
+        sector.map_or_else(|| Self::sub_sector_color(sub_sector_id), Self::sector_color)
+    }
+
+    // Segs only cover the linedefs a subsector touches, the edge from its
+    // last seg's end vertex back to its first seg's start vertex is implicit.
+    pub fn closing_edge(&self, sub_sector_id: usize) -> Option<(usize, usize)> {
+        let segs = self.map_data.subsector_segs(sub_sector_id);
+
+        let (first, last) = (segs.first()?, segs.last()?);
+
+        Some((last.end_vertex as usize, first.start_vertex as usize))
+    }
+
+    pub fn draw_sub_sector(&self, canvas: &mut dyn Canvas, sub_sector_id: usize) {
+        let Some((end, start)) = self.closing_edge(sub_sector_id) else {
+            return
+        };
+
+        let to_screen = |vertex: usize| {
+            let vertex = self.map_data.vertexes[vertex];
+
+            self.to_screen(vertex.x as f32, vertex.y as f32)
+        };
+
+        let points = self.map_data.subsector_polygon(sub_sector_id)
+            .iter()
+            .map(|&(x, y)| self.to_screen(x, y))
+            .collect::<Vec<_>>();
+
+        canvas.polygon(&points, self.sub_sector_fill(sub_sector_id));
+
+        let (x1, y1) = to_screen(end);
+        let (x2, y2) = to_screen(start);
+
+        canvas.line(x1, y1, x2, y2, Color::WHITE);
+    }
+
+    // The linedefs drawn offscreen at the drawer's size, for map previews.
+    // None when SFML can't create the texture, e.g. without a GL context.
+    pub fn render_to_texture(&self) -> Option<RenderTexture> {
+        let mut texture = RenderTexture::new(self.width as u32, self.height as u32)?;
+
+        texture.clear(Color::BLACK);
+        self.draw_linedefs(&mut SfmlCanvas::new(&mut texture));
+        texture.display();
+
+        Some(texture)
+    }
+
+    // A subsector's walls, counted into `stats`. Minisegs are only drawn
+    // when asked to, for debugging.
+    pub fn draw_sub_sector_segs(
+        &self,
+        canvas: &mut dyn Canvas,
+        sub_sector_id: usize,
+        show_minisegs: bool,
+        stats: &mut FrameStats,
+    ) {
+        stats.sub_sectors_visited += 1;
+
+        let segs = self.map_data.subsector_segs(sub_sector_id)
+            .iter()
+            .filter(|seg| show_minisegs || !seg.is_miniseg());
+
+        for seg in segs {
+            self.draw_segment(canvas, *seg);
+            stats.segs_drawn += 1;
+        }
+    }
+}
+
+// - - -
+// Per-frame counters for the F3 overlay, reset as each frame starts.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub segs_drawn: usize,
+    pub sub_sectors_visited: usize,
+    // Time between the last two frames.
+    pub frame_time: Duration,
+    frame_start: Option<Instant>,
+}
+
+impl FrameStats {
+    pub fn begin_frame(&mut self, now: Instant) {
+        if let Some(start) = self.frame_start {
+            self.frame_time = now - start;
+        }
+
+        self.frame_start = Some(now);
+        self.segs_drawn = 0;
+        self.sub_sectors_visited = 0;
+    }
+
+    pub fn fps(&self) -> f32 {
+        match self.frame_time.is_zero() {
+            true => 0.0,
+            false => 1.0 / self.frame_time.as_secs_f32(),
+        }
+    }
+}
+
+// - - -
+// Average color of the flats. The flats are read up front since drawing only
+// has shared access to the WAD, each average is worked out the first time a
+// floor asks for it.
+#[derive(Clone, Debug, Default)]
+pub struct FlatColors {
+    palette: Option<Palette>,
+    // Palette indexes of the flats not averaged yet.
+    flats: HashMap<String, Vec<u8>>,
+    colors: HashMap<String, Color>,
+}
+
+impl FlatColors {
+    pub fn new(map_data: &mut WAD) -> Result<Self, WadError> {
+        let mut cache = TextureCache::from_wad(map_data)?;
+
+        Self::with_cache(map_data, &mut cache)
+    }
+
+    // The flats come from `cache`, those already decoded aren't read again.
+    pub fn with_cache(map_data: &mut WAD, cache: &mut TextureCache) -> Result<Self, WadError> {
+        let mut flats = HashMap::new();
+
+        for name in map_data.flat_names() {
+            let cached = cache.get_or_decode(map_data, &name)?;
+
+            flats.insert(name, cached.picture.pixels.iter().flatten().copied().collect());
+        }
+
+        Ok(Self { palette: Some(cache.palette().clone()), flats, colors: HashMap::new() })
+    }
+
+    pub fn average(flat: &[u8], palette: &Palette) -> Color {
+        let (r, g, b) = flat.iter().fold((0, 0, 0), |(r, g, b), &index| {
+            let (cr, cg, cb) = palette.colors[index as usize];
+
+            (r + cr as usize, g + cg as usize, b + cb as usize)
+        });
+
+        let len = flat.len().max(1);
+
+        Color::rgb((r / len) as u8, (g / len) as u8, (b / len) as u8)
+    }
+
+    pub fn get(&mut self, flat: &str) -> Option<Color> {
+        if let Some(&color) = self.colors.get(flat) {
+            return Some(color);
+        }
+
+        let color = Self::average(self.flats.get(flat)?, self.palette.as_ref()?);
+
+        self.flats.remove(flat);
+        self.colors.insert(flat.to_string(), color);
+
+        Some(color)
+    }
+
+    // Flats the WAD doesn't have, like F_SKY1 in a PWAD without it, are
+    // drawn gray. Light levels go from 0 (black) to 255 (full bright).
+    pub fn floor_color(&mut self, sector: &Sector) -> Color {
+        let color = self.get(&sector.floor_texture()).unwrap_or(Color::rgb(110, 110, 110));
+        let light = sector.light_level.clamp(0, 255) as u16;
+
+        let dim = |channel: u8| (channel as u16 * light / 255) as u8;
+
+        Color::rgb(dim(color.r), dim(color.g), dim(color.b))
+    }
+}
+
+// - - -
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ViewMode {
+    // Every visible seg as a line.
+    #[default]
+    Segs,
+    // Every visible subsector as a filled polygon.
+    Subsectors,
+}
+
+// How `ViewMode::Subsectors` picks each subsector's fill.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    // A color of its own for every subsector.
+    #[default]
+    BySubsector,
+    // One color per sector, so a sector reads as a single region.
+    BySector,
+}
+
+// Window settings for `MapViewer::new`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ViewerConfig {
+    pub width: f32,
+    pub height: f32,
+    // Multisampling level, 0 turns it off.
+    pub antialiasing: u32,
+    pub resizable: bool,
+    pub title: String,
+    // Player start the view begins at, 1 to 4. None, or a start the map
+    // doesn't have, uses the first thing.
+    pub spawn: Option<u8>,
+    // Size the BSP view is drawn at before being scaled to the window,
+    // lower trades quality for speed. None uses the window's size.
+    pub render_width: Option<f32>,
+    pub render_height: Option<f32>,
+}
+
+impl Default for ViewerConfig {
+    fn default() -> Self {
+        Self {
+            width: 320.0 * 4.0,
+            height: 200.0 * 4.0,
+            antialiasing: 0,
+            resizable: false,
+            title: "Where's All the Data? - Map Viewer".to_string(),
+            spawn: None,
+            render_width: None,
+            render_height: None,
+        }
+    }
+}
+
+impl ViewerConfig {
+    pub fn style(&self) -> Style {
+        match self.resizable {
+            true => Style::CLOSE | Style::RESIZE,
+            false => Style::CLOSE,
+        }
+    }
+
+    pub fn context_settings(&self) -> ContextSettings {
+        ContextSettings {
+            antialiasing_level: self.antialiasing,
+            ..Default::default()
+        }
+    }
+
+    pub fn render_size(&self) -> (f32, f32) {
+        (self.render_width.unwrap_or(self.width), self.render_height.unwrap_or(self.height))
+    }
+}
+
+pub struct MapViewer <'a> {
+    window: RenderWindow,
+
+    w_height: f32,
+    w_width: f32,
+
+    render_width: f32,
+    render_height: f32,
+    // Only there while the render size differs from the window's.
+    view_texture: Option<RenderTexture>,
+
+    bounds: MapBounds,
+    margin: f32,
+
+    map_vertexes: Vec<Vector2f>,
+    map_data: &'a Map,
+
+    show_things: bool,
+    show_grid: bool,
+    show_minisegs: bool,
+    show_tags: bool,
+    hovered_tag: Option<i16>,
+    font: Option<SfBox<Font>>,
+    show_stats: bool,
+    stats: FrameStats,
+    map_names: Vec<String>,
+    map_name: Option<String>,
+    show_nodes: bool,
+    node_depth: usize,
+    mode: ViewMode,
+    automap_mode: AutomapMode,
+    line_colors: LineColors,
+    color_mode: ColorMode,
+    zoom: f32,
+    line_width: f32,
+    flat_colors: FlatColors,
+    //bsp: BSP<'a>,
+
+    engine: Engine<'a>,
+}
+
+impl <'a> MapViewer <'a> {
+    // Doesn't borrow `self`, so it can draw into `self.window`.
+    pub fn drawer(&self) -> MapDrawer<'a> {
+        MapDrawer {
+            map_data: self.map_data,
+            bounds: self.bounds,
+            width: self.w_width,
+            height: self.w_height,
+            margin: self.margin,
+            automap_mode: self.automap_mode,
+            view_position: self.engine.player.position,
+            view_angle: self.engine.player.angle,
+            line_colors: self.line_colors,
+            color_mode: self.color_mode,
+            zoom: self.zoom,
+            line_width: self.line_width,
+        }
+    }
+
+    pub fn draw_line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, color: Color) {
+        SfmlCanvas::new(&mut self.window).line(x1, y1, x2, y2, color);
+    }
+
+    pub fn draw_circle(&mut self, x: f32, y: f32, radius: f32, color: Color) {
+        SfmlCanvas::new(&mut self.window).circle(x, y, radius, color);
+    }
+}
+
+impl <'a> MapViewer <'_> {
+    pub fn calc_map_bounds(&mut self) {
+        self.bounds = MapBounds::from_vertexes(&self.map_data.vertexes);
+    }
+}
+
+impl <'a> MapViewer <'a> {
+    pub fn traslate_vertex_x(&self, x: f32) -> f32 {
+        self.bounds.to_screen_x(x, self.w_width, self.margin)
+    }
+
+    pub fn traslate_vertex_y(&self, y: f32) -> f32 {
+        self.bounds.to_screen_y(y, self.w_height, self.margin)
+    }
+
+    // Fits the map to the window's new size. SFML keeps the old view and
+    // stretches it otherwise.
+    pub fn handle_resize(&mut self, width: f32, height: f32) {
+        self.window.set_view(&View::from_rect(FloatRect::new(0.0, 0.0, width, height)));
+
+        self.w_width = width;
+        self.w_height = height;
+
+        // A render size of its own stays, otherwise it follows the window.
+        if self.view_texture.is_none() {
+            self.render_width = width;
+            self.render_height = height;
+        }
+
+        self.calc_map_bounds();
+        self.calc_map_vertexes();
+    }
+
+    // The BSP view is drawn offscreen at this size and scaled up to the
+    // window. Falls back to the window's size if SFML can't make the texture.
+    pub fn set_render_size(&mut self, width: f32, height: f32) {
+        let matches_window = (width, height) == (self.w_width, self.w_height);
+
+        self.view_texture = match matches_window {
+            true => None,
+            false => RenderTexture::new(width as u32, height as u32),
+        };
+
+        (self.render_width, self.render_height) = match self.view_texture {
+            Some(_) => (width, height),
+            None => (self.w_width, self.w_height),
+        };
+    }
+
+    // Like `drawer`, sized for the BSP view.
+    pub fn view_drawer(&self) -> MapDrawer<'a> {
+        MapDrawer { width: self.render_width, height: self.render_height, ..self.drawer() }
+    }
+
+    // Passes the canvas the BSP view goes to, the offscreen texture or the window.
+    fn with_view_canvas(
+        &mut self,
+        draw: impl FnOnce(&MapDrawer, &mut dyn Canvas, &mut FrameStats),
+    ) {
+        let drawer = self.view_drawer();
+
+        match &mut self.view_texture {
+            Some(texture) => draw(&drawer, &mut SfmlCanvas::new(texture), &mut self.stats),
+            None => draw(&drawer, &mut SfmlCanvas::new(&mut self.window), &mut self.stats),
+        }
+    }
+
+    // Draws the offscreen view stretched over the window.
+    fn present_view(&mut self) {
+        let Some(texture) = &mut self.view_texture else {
+            return;
+        };
+
+        texture.display();
+
+        let mut sprite = Sprite::with_texture(texture.texture());
+        sprite.set_scale((self.w_width / self.render_width, self.w_height / self.render_height));
+
+        self.window.draw(&sprite);
+    }
+
+    // Small windows may want less than the default 30 pixels.
+    pub fn set_margin(&mut self, margin: f32) {
+        self.margin = margin;
+        self.calc_map_vertexes();
+    }
+
+    fn calc_map_vertexes(&mut self) {
+        self.map_vertexes = self.map_data.vertexes
+            .iter()
+            .map(|vertex| {
+                Vector2f::new(
+                    self.traslate_vertex_x(vertex.x as f32),
+                    self.traslate_vertex_y(vertex.y as f32),
+                )
+            })
+            .collect();
+    }
+}
+
+impl <'a> MapViewer <'a> {
+    pub fn draw_bbox(&mut self, bbox: [i16; 4], color: Color) {
+        self.drawer().draw_bbox(&mut SfmlCanvas::new(&mut self.window), bbox, color);
+    }
+
+    pub fn draw_node(&mut self, node_id: usize) {
+        self.drawer().draw_node(&mut SfmlCanvas::new(&mut self.window), node_id);
+    }
+
+    pub fn draw_linedefs(&mut self) {
+        self.drawer().draw_linedefs(&mut SfmlCanvas::new(&mut self.window));
+    }
+
+    // Map units between grid lines, like the automap's 128.
+    pub const GRID_SPACING: f32 = 128.0;
+
+    pub fn draw_grid(&mut self, spacing: f32) {
+        self.drawer().draw_grid(&mut SfmlCanvas::new(&mut self.window), spacing);
+    }
+
+    pub fn draw_vertexes(&mut self) {
+        self.drawer().draw_vertexes(&mut SfmlCanvas::new(&mut self.window));
+    }
+
+    pub fn rand_color(&mut self) -> Color {
+        let mut rng = rand::thread_rng();
+
+        Color::rgb(rng.gen_range(0..255), rng.gen_range(0..255), rng.gen_range(0..255))
+    }
+
+    pub fn draw_things(&mut self) {
+        self.drawer().draw_things(&mut SfmlCanvas::new(&mut self.window));
+    }
+
+    pub fn draw_segment(&mut self, seg: Seg) {
+        self.drawer().draw_segment(&mut SfmlCanvas::new(&mut self.window), seg);
+    }
+
+    pub fn draw_nodes(&mut self, depth: usize) {
+        self.drawer().draw_nodes(&mut SfmlCanvas::new(&mut self.window), depth);
+    }
+
+    pub fn draw_sub_sector(&mut self, sub_sector_id: usize) {
+        self.drawer().draw_sub_sector(&mut SfmlCanvas::new(&mut self.window), sub_sector_id);
+    }
+
+    // Draws minisegs in magenta along with the walls.
+    pub fn set_show_minisegs(&mut self, show_minisegs: bool) {
+        self.show_minisegs = show_minisegs;
+    }
+
+    // Needed for the tag numbers, SFML has no built-in font.
+    pub fn set_font(&mut self, font: SfBox<Font>) {
+        self.font = Some(font);
+    }
+
+    // Picks up the tag of the sector under the mouse for `draw_sector_tags`.
+    pub fn hover(&mut self, x: f32, y: f32) {
+        let (x, y) = self.drawer().from_screen(x, y);
+
+        self.hovered_tag = self.map_data
+            .sector_at(x, y)
+            .map(|sector| sector.tag)
+            .filter(|&tag| tag != 0);
+    }
+
+    // Each tagged sector's tag at its centroid, with the linedefs acting on
+    // the hovered tag highlighted and its sectors' numbers in yellow.
+    pub fn draw_sector_tags(&mut self) {
+        if let Some(tag) = self.hovered_tag {
+            self.drawer().draw_tagged_lines(&mut SfmlCanvas::new(&mut self.window), tag);
+        }
+
+        let Some(font) = &self.font else {
+            return;
+        };
+
+        let drawer = self.drawer();
+
+        for (tag, sectors) in self.map_data.sectors_by_tag() {
+            let color = match self.hovered_tag == Some(tag) {
+                true => Color::YELLOW,
+                false => Color::WHITE,
+            };
+
+            for (x, y) in sectors.iter().filter_map(|&id| self.map_data.sector_centroid(id)) {
+                let (x, y) = drawer.to_screen(x, y);
+
+                let mut text = Text::new(&tag.to_string(), font, 12);
+                let bounds = text.local_bounds();
+
+                text.set_fill_color(color);
+                text.set_position(Vector2f::new(x - bounds.width / 2.0, y - bounds.height / 2.0));
+
+                self.window.draw(&text);
+            }
+        }
+    }
+
+    // The BSP walk picks up the new position from the next frame on.
+    pub fn set_view_thing(&mut self, thing: Thing) {
+        self.engine.set_view_thing(thing);
+    }
+
+    // Where the seg lands across the window, see `Engine::project_seg`.
+    pub fn project_seg(&self, seg: &Seg) -> Option<(i32, i32)> {
+        self.engine.project_seg(seg, self.render_width)
+    }
+
+    // The maps PageUp/PageDown cycle through, in game order.
+    pub fn set_map_names(&mut self, map_names: Vec<String>, current: Option<String>) {
+        self.map_names = map_names;
+        self.map_name = current;
+    }
+
+    pub fn next_map(&self) -> Option<String> {
+        cycle_map_name(&self.map_names, self.map_name.as_deref(), 1)
+    }
+
+    pub fn prev_map(&self) -> Option<String> {
+        cycle_map_name(&self.map_names, self.map_name.as_deref(), -1)
+    }
+
+    // The map fitted into a `size` preview, the window is left alone.
+    pub fn render_to_texture(&self, size: (u32, u32)) -> Option<RenderTexture> {
+        let drawer = MapDrawer { width: size.0 as f32, height: size.1 as f32, ..self.drawer() };
+
+        drawer.render_to_texture()
+    }
+
+    // FPS and this frame's counters in the top left corner, needs a font.
+    pub fn draw_stats(&mut self) {
+        let Some(font) = &self.font else {
+            return;
+        };
+
+        let stats = self.stats;
+        let string = format!(
+            "FPS: {:.0}\nSegs: {}\nSubsectors: {}",
+            stats.fps(),
+            stats.segs_drawn,
+            stats.sub_sectors_visited,
+        );
+
+        let mut text = Text::new(&string, font, 14);
+        text.set_fill_color(Color::GREEN);
+        text.set_position(Vector2f::new(8.0, 8.0));
+
+        self.window.draw(&text);
+    }
+
+    pub fn set_show_nodes(&mut self, show_nodes: bool) {
+        self.show_nodes = show_nodes;
+    }
+
+    // How many levels of the BSP tree to draw partition lines for, starting
+    // at the root.
+    pub fn set_node_depth(&mut self, node_depth: usize) {
+        self.node_depth = node_depth;
+    }
+
+    pub fn set_mode(&mut self, mode: ViewMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_automap_mode(&mut self, automap_mode: AutomapMode) {
+        self.automap_mode = automap_mode;
+    }
+
+    pub fn set_line_colors(&mut self, line_colors: LineColors) {
+        self.line_colors = line_colors;
+    }
+
+    pub fn set_color_mode(&mut self, color_mode: ColorMode) {
+        self.color_mode = color_mode;
+    }
+
+    pub const MIN_ZOOM: f32 = 0.25;
+    pub const MAX_ZOOM: f32 = 16.0;
+
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+    }
+
+    // Wall thickness at a zoom of 1.0, it scales along with the zoom.
+    pub fn set_line_width(&mut self, line_width: f32) {
+        self.line_width = line_width;
+    }
+
+    pub fn set_flat_colors(&mut self, flat_colors: FlatColors) {
+        self.flat_colors = flat_colors;
+    }
+
+    pub fn floor_color(&mut self, sector: &Sector) -> Color {
+        self.flat_colors.floor_color(sector)
+    }
+}
+
+impl <'a> MapViewer <'a> {
+    // Same move/turn speeds as DOOM's keyboard controls, shift to run.
+    fn keyboard_tic_cmd() -> TicCmd {
+        let run = Key::LShift.is_pressed() || Key::RShift.is_pressed();
+
+        let (forward, side, turn): (i8, i8, i16) = match run {
+            true => (50, 40, 1280),
+            false => (25, 24, 640),
+        };
+
+        let mut cmd = TicCmd::default();
+
+        if Key::Up.is_pressed() || Key::W.is_pressed() {
+            cmd.forward_move += forward;
+        }
+
+        if Key::Down.is_pressed() || Key::S.is_pressed() {
+            cmd.forward_move -= forward;
+        }
+
+        if Key::D.is_pressed() {
+            cmd.side_move += side;
+        }
+
+        if Key::A.is_pressed() {
+            cmd.side_move -= side;
+        }
+
+        if Key::Left.is_pressed() {
+            cmd.angle_turn += turn;
+        }
+
+        if Key::Right.is_pressed() {
+            cmd.angle_turn -= turn;
+        }
+
+        cmd
+    }
+
+    // Some when `run` should stop, holding what `run` returns.
+    pub fn handle_event(&mut self, event: Event) -> Option<Option<String>> {
+        match event {
+            Event::Closed => return Some(None),
+            Event::KeyPressed { code, .. } => match code {
+                Key::Escape => return Some(None),
+                Key::PageDown => return Some(self.next_map()),
+                Key::PageUp => return Some(self.prev_map()),
+                Key::T => self.show_things = !self.show_things,
+                Key::G => self.show_grid = !self.show_grid,
+                Key::L => self.show_tags = !self.show_tags,
+                Key::F3 => self.show_stats = !self.show_stats,
+                // DOOM's follow key, cycling through the rotating mode too.
+                Key::F => self.set_automap_mode(match self.automap_mode {
+                    AutomapMode::Fixed => AutomapMode::Follow,
+                    AutomapMode::Follow => AutomapMode::Rotate,
+                    AutomapMode::Rotate => AutomapMode::Fixed,
+                }),
+                Key::M => self.set_mode(match self.mode {
+                    ViewMode::Segs => ViewMode::Subsectors,
+                    ViewMode::Subsectors => ViewMode::Segs,
+                }),
+                // DOOM's automap zoom keys.
+                Key::Equal => self.set_zoom(self.zoom * 2.0),
+                Key::Hyphen => self.set_zoom(self.zoom / 2.0),
+                Key::C => self.set_color_mode(match self.color_mode {
+                    ColorMode::BySubsector => ColorMode::BySector,
+                    ColorMode::BySector => ColorMode::BySubsector,
+                }),
+                _ => {}
+            },
+            Event::Resized { width, height } => self.handle_resize(width as f32, height as f32),
+            Event::MouseMoved { x, y } => self.hover(x as f32, y as f32),
+            _ => {}
+        }
+
+        None
+    }
+
+    // Returns the map to switch to when PageUp/PageDown was pressed, None
+    // when the window was closed.
+    pub fn run(&mut self, bsp: &BSP) -> Option<String> {
+        //let mut window = &self.window;
+
+        //let vertexes = &self.map_vertexes;
+        //let linedefs = &self.map_data.line_defs;
+        //
+
+        let mut last_frame = Instant::now();
+        let mut lag = Duration::ZERO;
+
+        loop {
+            while let Some(event) = self.window.poll_event() {
+                if let Some(next) = self.handle_event(event) {
+                    return next;
+                }
+            }
+
+            // Run as many fixed-length tics as the elapsed time covers,
+            // rendering happens once per frame regardless.
+            let now = Instant::now();
+            lag += now - last_frame;
+            last_frame = now;
+
+            while lag >= Engine::tic_duration() {
+                self.engine.tic(Self::keyboard_tic_cmd());
+                lag -= Engine::tic_duration();
+            }
+
+            self.stats.begin_frame(now);
+            self.window.clear(Color::BLACK);
+
+            // Draw vertexes
+            /*let mut circle = CircleShape::new(2.0, 12);
+            circle.set_fill_color(Color::WHITE);
+
+            for vertex in vertexes.iter() {
+                let vertex = Vector2f::new(vertex.x - 2.0, vertex.y - 2.0);
+
+                circle.set_position(vertex);
+                self.window.draw(&circle);
+            }*/
+
+            // Draw linedefs
+            /*for linedef in linedefs.iter() {
+                let mut line = RectangleShape::new();
+                line.set_size(Vector2f::new(1.0, 1.0));
+                line.set_fill_color(Color::WHITE);
+
+                let p_1 = vertexes[linedef.start_vertex as usize];
+                let p_2 = vertexes[linedef.end_vertex as usize];
+
+                let a = (p_2.y - p_1.y).atan2(p_2.x - p_1.x);
+
+                let length = ((p_2.y - p_1.y).powi(2) + (p_2.x - p_1.x).powi(2)).sqrt();
+
+                line.set_size(Vector2f::new(length, 1.0));
+                line.set_rotation(a.to_degrees());
+                line.set_position(p_1);
+
+                self.window.draw(&line);
+            }*/
+
+            if self.show_grid {
+                self.draw_grid(Self::GRID_SPACING);
+            }
+
+            self.draw_linedefs();
+            //self.draw_vertexes();
+ 
+            if self.show_nodes {
+                self.draw_nodes(self.node_depth);
+            }
+
+            if let Some(texture) = &mut self.view_texture {
+                texture.clear(Color::TRANSPARENT);
+            }
+
+            bsp.update(self);
+            self.present_view();
+
+            if self.show_things {
+                self.draw_things();
+            }
+
+            if self.show_tags {
+                self.draw_sector_tags();
+            }
+
+            if self.show_stats {
+                self.draw_stats();
+            }
+
+            // Draw Player
+            /*let mut player = CircleShape::new(2.0, 12);
+
+            player.set_fill_color(Color::BLUE);
+
+            let player_x = self.traslate_vertex_x(self.player.position.0);
+            let player_y = self.traslate_vertex_y(self.player.position.1);
+
+            player.set_position(Vector2f::new(player_x, player_y));
+            player.set_rotation(self.player.angle.to_degrees());
+
+            self.window.draw(&player);
+            self.window.display();*/
+ 
+            self.window.display();
+        }
+    }
+}
+
+impl <'a> MapViewer <'a> {
+    pub fn new(config: ViewerConfig, map_data: &'a Map) -> Self {
+        let player_thing = config.spawn
+            .and_then(|number| map_data.player_start(number))
+            .unwrap_or(map_data.things[0]);
+        let player = Player::new(player_thing.clone());
+        //let bsp = BSP::new(&map_data);
+
+        //let engine = Engine::new(&mut window, &map_data);
+
+        //let w_height = self.w_height as u32;
+        //let w_width = self.w_width as u32;
+
+        //let root_node_id = map_data.nodes.len() - 1;
+        let (width, height) = (config.width, config.height);
+        let context = config.context_settings();
+
+        //let mut window = RenderWindow::new((w_width, w_height), w_title, Style::CLOSE, &context);
+        //window.set_vertical_sync_enabled(true);
+
+        let mut viewer = Self {
+            window: RenderWindow::new(
+                (width as u32, height as u32),
+                config.title.as_str(),
+                config.style(),
+                &context,
+            ),
+            //window: & mut window,
+
+            w_height: height,
+            w_width: width,
+
+            render_width: width,
+            render_height: height,
+            view_texture: None,
+
+            bounds: MapBounds::default(),
+            margin: MapBounds::DEFAULT_MARGIN,
+
+            map_vertexes: Vec::new(),
+            map_data,
+
+            show_things: false,
+            show_grid: false,
+            show_minisegs: false,
+            show_tags: false,
+            hovered_tag: None,
+            font: None,
+            show_stats: false,
+            stats: FrameStats::default(),
+            map_names: Vec::new(),
+            map_name: None,
+            show_nodes: true,
+            node_depth: 1,
+            mode: ViewMode::default(),
+            automap_mode: AutomapMode::default(),
+            line_colors: LineColors::default(),
+            color_mode: ColorMode::default(),
+            zoom: 1.0,
+            line_width: 1.0,
+            flat_colors: FlatColors::default(),
+            //bsp: BSP { map_data, root_node_id  }
+
+
+            engine: Engine::new(map_data, player),
+        };
+
+        viewer.calc_map_bounds();
+        viewer.calc_map_vertexes();
+
+        let (render_width, render_height) = config.render_size();
+        viewer.set_render_size(render_width, render_height);
+
+        viewer
+    }
+}
+
+// `step` maps away from `current` in `names`, wrapping around both ends.
+// Without a current map, or one that isn't listed, it's the first map.
+pub fn cycle_map_name(names: &[String], current: Option<&str>, step: isize) -> Option<String> {
+    if names.is_empty() {
+        return None;
+    }
+
+    let i = match current.and_then(|current| names.iter().position(|name| name == current)) {
+        Some(i) => (i as isize + step).rem_euclid(names.len() as isize) as usize,
+        None => 0,
+    };
+
+    Some(names[i].clone())
+}
+
+// Views the WAD's current map, or its first one, rebuilding the BSP and the
+// viewer whenever PageUp/PageDown switch maps.
+pub fn view_wad(wad: &mut WAD, config: ViewerConfig) -> Result<(), WadError> {
+    let names = wad.map_names_sorted();
+
+    let mut name = match wad.current_map_name() {
+        Some(name) => name,
+        None => cycle_map_name(&names, None, 0).ok_or(WadError::MapNotFound(String::new()))?,
+    };
+
+    // Kept across maps so each flat is only decoded once, WADs without a
+    // PLAYPAL are drawn with gray floors.
+    let mut cache = TextureCache::from_wad(wad).ok();
+
+    loop {
+        wad.change_map(&name)?;
+
+        let flat_colors = match cache.as_mut() {
+            Some(cache) => FlatColors::with_cache(wad, cache).ok(),
+            None => None,
+        };
+
+        let bsp = BSP::new(&wad.map);
+
+        let mut viewer = MapViewer::new(config.clone(), &wad.map);
+        viewer.set_map_names(names.clone(), Some(name));
+
+        if let Some(flat_colors) = flat_colors {
+            viewer.set_flat_colors(flat_colors);
+        }
+
+        match viewer.run(&bsp) {
+            Some(next) => name = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+const DOOM_WAD_PATH: &str = "/home/flames/Downloads/DOOM.wad";
+
+#[test]
+fn test_map_viewer() {
+    let mut map_data = WAD::new(DOOM_WAD_PATH).unwrap();
+    let _ = map_data.change_map("E1M8");
+
+    let root_node_id = map_data.nodes.len() - 1;
+
+    let mut map_viewer = MapViewer::new(ViewerConfig::default(), &map_data);
+    map_viewer.run(
+        &BSP { map_data: &map_data, root_node_id }
+    );
+}
+
+// - - -
+// Synthetic WADs for tests that don't need the real DOOM.wad.
+#[cfg(test)]
+fn test_lump_name(name: &str) -> [u8; 8] {
+    let mut bytes = [0; 8];
+
+    for (dst, src) in bytes.iter_mut().zip(name.bytes()) {
+        *dst = src;
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+fn test_lump_bytes<T: Record>(items: &[T]) -> Vec<u8> {
+    records_to_bytes(items)
+}
+
+// A PWAD holding the given lumps.
+#[cfg(test)]
+fn test_wad_bytes(lumps: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let data_size: usize = lumps.iter().map(|(_, data)| data.len()).sum();
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"PWAD");
+    bytes.extend_from_slice(&(lumps.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&((WAD::HEADER_SIZE + data_size) as u32).to_le_bytes());
+
+    let mut directory = Vec::new();
+
+    for (name, data) in lumps {
+        directory.push(Directory {
+            offset: bytes.len() as u32,
+            size: data.len() as u32,
+            name: test_lump_name(name),
+        });
+
+        bytes.extend_from_slice(data);
+    }
+
+    bytes.extend_from_slice(&test_lump_bytes(&directory));
+
+    bytes
+}
+
+// Writes a PWAD with the given lumps into the temp directory and returns its path.
+#[cfg(test)]
+fn write_test_wad(file_name: &str, lumps: &[(String, Vec<u8>)]) -> String {
+    let path = std::env::temp_dir().join(file_name);
+    fs::write(&path, test_wad_bytes(lumps)).unwrap();
+
+    path.to_str().unwrap().to_string()
+}
+
+// A 128x128 square room: one sector, four one-sided walls and a player start
+// in the middle. The map has a single subsector, so NODES is empty.
+#[cfg(test)]
+fn test_map_lumps(marker: &str) -> Vec<(String, Vec<u8>)> {
+    let things = [Thing { x: 64, y: 64, angle: 90, t_type: 1, flags: 7 }];
+
+    let vertexes = [
+        Vertex { x: 0, y: 0 },
+        Vertex { x: 128, y: 0 },
+        Vertex { x: 128, y: 128 },
+        Vertex { x: 0, y: 128 },
+    ];
+
+    let line_defs = (0..4)
+        .map(|i| LineDef {
+            start_vertex: i,
+            end_vertex: (i + 1) % 4,
+            flags: 1,
+            special_type: 0,
+            sector_tag: 0,
+            right_sidedef: i,
+            left_sidedef: -1,
+        })
+        .collect::<Vec<_>>();
+
+    let side_defs = (0..4)
+        .map(|_| SideDef {
+            x_offset: 0,
+            y_offset: 0,
+            upper_texture: test_lump_name("-"),
+            lower_texture: test_lump_name("-"),
+            middle_texture: test_lump_name("STARTAN3"),
+            sector: 0,
+        })
+        .collect::<Vec<_>>();
+
+    let segs = line_defs
+        .iter()
+        .enumerate()
+        .map(|(i, line)| Seg {
+            start_vertex: line.start_vertex as u32,
+            end_vertex: line.end_vertex as u32,
+            angle: (i as i32 * 0x4000) as i16,
+            linedef: i as i32,
+            direction: 0,
+            offset: 0,
+        })
+        .collect::<Vec<_>>();
+
+    let ssectors = [SubSector { num_segs: 4, first_seg: 0 }];
+
+    let sectors = [Sector {
+        floor_height: 0,
+        ceiling_height: 128,
+        floor_texture: test_lump_name("FLOOR4_8"),
+        ceiling_texture: test_lump_name("CEIL3_5"),
+        light_level: 160,
+        special_type: 0,
+        tag: 0,
+    }];
+
+    vec![
+        (marker.to_string(), Vec::new()),
+        ("THINGS".to_string(), test_lump_bytes(&things)),
+        ("LINEDEFS".to_string(), test_lump_bytes(&line_defs)),
+        ("SIDEDEFS".to_string(), test_lump_bytes(&side_defs)),
+        ("VERTEXES".to_string(), test_lump_bytes(&vertexes)),
+        ("SEGS".to_string(), test_lump_bytes(&segs)),
+        ("SSECTORS".to_string(), test_lump_bytes(&ssectors)),
+        ("NODES".to_string(), Vec::new()),
+        ("SECTORS".to_string(), test_lump_bytes(&sectors)),
+        ("REJECT".to_string(), vec![0]),
+        ("BLOCKMAP".to_string(), Vec::new()),
+    ]
+}
+
+// XNOD data for the `test_map_lumps` room, without the signature.
+#[cfg(test)]
+fn test_extended_nodes_data() -> Vec<u8> {
+    let mut nodes = Vec::new();
+
+    // Four original vertexes, one new vertex at (64, 64).
+    nodes.extend_from_slice(&4u32.to_le_bytes());
+    nodes.extend_from_slice(&1u32.to_le_bytes());
+    nodes.extend_from_slice(&(64i32 << 16).to_le_bytes());
+    nodes.extend_from_slice(&(64i32 << 16).to_le_bytes());
+
+    // Two subsectors with two segs each.
+    nodes.extend_from_slice(&2u32.to_le_bytes());
+    nodes.extend_from_slice(&2u32.to_le_bytes());
+    nodes.extend_from_slice(&2u32.to_le_bytes());
+
+    nodes.extend_from_slice(&4u32.to_le_bytes());
+
+    for line in 0..4u32 {
+        nodes.extend_from_slice(&line.to_le_bytes());
+        nodes.extend_from_slice(&((line + 1) % 4).to_le_bytes());
+        nodes.extend_from_slice(&(line as u16).to_le_bytes());
+        nodes.push(0);
+    }
+
+    // A single diagonal partition splitting the room in two.
+    nodes.extend_from_slice(&1u32.to_le_bytes());
+
+    for value in [0i16, 0, 128, 128, 128, 0, 0, 128, 128, 0, 0, 128] {
+        nodes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    nodes.extend_from_slice(&0x8000_0000u32.to_le_bytes());
+    nodes.extend_from_slice(&0x8000_0001u32.to_le_bytes());
+
+    nodes
+}
+
+#[cfg(test)]
+fn assert_extended_nodes(file_name: &str, nodes: Vec<u8>) {
+    let mut lumps = test_map_lumps("MAP01");
+    lumps[5].1.clear();
+    lumps[6].1.clear();
+    lumps[7].1 = nodes;
+
+    let path = write_test_wad(file_name, &lumps);
+
+    let mut map_data = WAD::new(&path).unwrap();
+    map_data.change_map("MAP01").unwrap();
+
+    assert_eq!(map_data.nodes.len(), 1);
+    assert_eq!(map_data.ssectors.len(), 2);
+    assert_eq!(map_data.segs.len(), 4);
+    assert_eq!(map_data.vertexes.len(), 5);
+
+    let root = map_data.nodes[map_data.nodes.len() - 1];
+
+    assert_eq!((root.dx_partition, root.dy_partition), (128, 128));
+    assert_eq!(root.front_child, Map::SUB_SECTOR_FLAG);
+    assert_eq!(root.back_child, Map::SUB_SECTOR_FLAG | 1);
+
+    assert_eq!(map_data.ssectors[1].first_seg, 2);
+    assert_eq!(map_data.segs[1].angle as u16, 0x4000);
+}
+
+#[test]
+fn test_extended_nodes() {
+    let mut nodes = b"XNOD".to_vec();
+    nodes.extend(test_extended_nodes_data());
+
+    assert_extended_nodes("doom_rs_extended_nodes.wad", nodes);
+
+    // Indexes past the vanilla 16 bits are kept as is.
+    let mut data = test_extended_nodes_data();
+    let end = data.len();
+    data[end - 4..].copy_from_slice(&(Map::SUB_SECTOR_FLAG | 0x1_0000).to_le_bytes());
+
+    let (mut vertexes, mut segs, mut ssectors) = (vec![Vertex { x: 0, y: 0 }; 4], vec![], vec![]);
+    let nodes =
+        WAD::read_extended_nodes(&data, &[], &mut vertexes, &mut segs, &mut ssectors).unwrap();
+
+    assert_eq!(nodes[0].back_child, Map::SUB_SECTOR_FLAG | 0x1_0000);
+}
+
+#[test]
+fn test_compressed_nodes() {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&test_extended_nodes_data()).unwrap();
+
+    let mut nodes = b"ZNOD".to_vec();
+    nodes.extend(encoder.finish().unwrap());
+
+    assert_extended_nodes("doom_rs_compressed_nodes.wad", nodes);
+}
+
+#[test]
+fn test_demo() {
+    // v1.9 header recorded on E1M1, ultra-violence, single player.
+    let mut demo = vec![109, 3, 1, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0];
+
+    for _ in 0..3 {
+        demo.extend_from_slice(&[25, 0xF6, 0x02, 1]);
+    }
+
+    demo.push(0x80);
+
+    let mut lumps = test_map_lumps("E1M1");
+    lumps.push(("DEMO1".to_string(), demo));
+
+    let path = write_test_wad("doom_rs_demo.wad", &lumps);
+
+    let mut map_data = WAD::new(&path).unwrap();
+    let demo = map_data.demo("DEMO1").unwrap();
+
+    assert_eq!((demo.version, demo.skill), (109, 3));
+    assert_eq!((demo.episode, demo.map), (1, 1));
+    assert_eq!(demo.player_count(), 1);
+    assert_eq!(demo.tics.len(), 3);
+    assert_eq!(
+        demo.tics[0],
+        TicCmd { forward_move: 25, side_move: -10, angle_turn: 0x200, buttons: 1 }
+    );
+
+    assert!(matches!(map_data.demo("DEMO9"), Err(WadError::LumpNotFound(_))));
+}
+
+#[test]
+fn test_linedef_context() {
+    let mut map_data = test_room_map("doom_rs_linedef_context.wad");
+
+    // Turn the first wall into a window onto an outdoor sector.
+    let mut outside = map_data.sectors[0];
+    outside.floor_height = 32;
+    outside.ceiling_texture = test_lump_name("F_SKY1");
+    map_data.sectors.push(outside);
+
+    let mut back_side = map_data.side_defs[0];
+    back_side.sector = 1;
+    map_data.side_defs.push(back_side);
+
+    map_data.side_defs[0].upper_texture = test_lump_name("STARTAN3");
+    map_data.line_defs[0].left_sidedef = 4;
+
+    let window = map_data.line_defs[0];
+    let context = map_data.linedef_context(&window);
+
+    assert_eq!(context.front_sector.unwrap().floor_height, 0);
+    assert_eq!(context.back_sector.unwrap().floor_height, 32);
+    assert_eq!(context.front_side.unwrap().upper_texture(), "STARTAN3");
+    assert!(context.is_sky_wall());
+    assert!(context.back_sector.unwrap().is_sky());
+    assert!(!context.front_sector.unwrap().is_sky());
+    assert!(!context.draws_upper());
+
+    let wall = map_data.line_defs[1];
+    let context = map_data.linedef_context(&wall);
+
+    assert!(context.back_side.is_none());
+    assert!(context.back_sector.is_none());
+    assert!(!context.is_sky_wall());
+    assert!(!context.draws_upper());
+
+    map_data.sectors[1].ceiling_texture = test_lump_name("CEIL3_5");
+    assert!(map_data.linedef_context(&window).draws_upper());
+}
+
+#[test]
+fn test_thing_colors() {
+    let thing = |t_type| Thing { x: 0, y: 0, angle: 0, t_type, flags: 7 };
+
+    // Player 1 start, imp, shotgun, barrel.
+    assert_eq!(MapDrawer::thing_color(thing(1).category()), Color::GREEN);
+    assert_eq!(MapDrawer::thing_color(thing(3001).category()), Color::RED);
+    assert_eq!(MapDrawer::thing_color(thing(2001).category()), Color::YELLOW);
+    assert_eq!(thing(2035).category(), ThingCategory::Other);
+}
+
+#[test]
+fn test_engine_tic() {
+    let map_data = test_room_map("doom_rs_engine_tic.wad");
+
+    // The player starts in the middle of the room facing north.
+    let mut engine = Engine::new(&map_data, Player::new(map_data.things[0]));
+    let forward = TicCmd { forward_move: 24, ..Default::default() };
+
+    for _ in 0..3 {
+        engine.tic(forward);
+    }
+
+    let (x, y) = engine.player.position;
+    assert!((x - 64.0).abs() < 0.01);
+    assert!((y - 88.0).abs() < 0.01);
+
+    // The north wall stops the player one radius away from it.
+    for _ in 0..10 {
+        engine.tic(forward);
+    }
+
+    assert!(engine.player.position.1 <= 112.0);
+
+    engine.tic(TicCmd { angle_turn: 0x4000, ..Default::default() });
+    assert!((engine.player.angle - 180.0).abs() < 0.01);
+
+    // The north wall pointing past VERTEXES no longer blocks, nor panics.
+    let mut broken = map_data.map.clone();
+    broken.line_defs[2].start_vertex = 999;
+
+    let engine = Engine::new(&broken, Player::new(broken.things[0]));
+    assert!(engine.can_move_to(64.0, 127.0));
+    assert!(!engine.can_move_to(1.0, 64.0));
+}
+
+// `test_map_lumps`' room as the current map.
+#[cfg(test)]
+fn test_room_map(file_name: &str) -> WAD {
+    let path = write_test_wad(file_name, &test_map_lumps("MAP01"));
+
+    let mut map_data = WAD::new(&path).unwrap();
+    map_data.change_map("MAP01").unwrap();
+
+    map_data
+}
+
+// Splits the test room in two sectors along x = 64, the east half raised by `step`.
+#[cfg(test)]
+fn test_step_map(file_name: &str, step: i16) -> WAD {
+    let mut map_data = test_room_map(file_name);
+
+    let mut east = map_data.sectors[0];
+    east.floor_height = step;
+    map_data.sectors.push(east);
+
+    let mut west_side = map_data.side_defs[0];
+    west_side.middle_texture = test_lump_name("-");
+
+    let mut east_side = west_side;
+    east_side.sector = 1;
+
+    map_data.side_defs.push(west_side);
+    map_data.side_defs.push(east_side);
+
+    map_data.vertexes.push(Vertex { x: 64, y: 0 });
+    map_data.vertexes.push(Vertex { x: 64, y: 128 });
+
+    // Two-sided, so it doesn't block by itself.
+    map_data.line_defs.push(LineDef {
+        start_vertex: 5,
+        end_vertex: 4,
+        flags: 4,
+        special_type: 0,
+        sector_tag: 0,
+        right_sidedef: 4,
+        left_sidedef: 5,
+    });
+
+    for direction in 0..2 {
+        map_data.segs.push(Seg {
+            start_vertex: 5,
+            end_vertex: 4,
+            angle: -0x4000,
+            linedef: 4,
+            direction,
+            offset: 0,
+        });
+    }
+
+    map_data.ssectors = vec![
+        SubSector { num_segs: 1, first_seg: 4 },
+        SubSector { num_segs: 1, first_seg: 5 },
+    ];
+
+    // Partition pointing north: the east half is in front.
+    map_data.nodes.push(Node {
+        x_partition: 64,
+        y_partition: 0,
+        dx_partition: 0,
+        dy_partition: 128,
+        front_bbox: [128, 0, 64, 128],
+        back_bbox: [128, 0, 0, 64],
+        front_child: Map::SUB_SECTOR_FLAG | 1,
+        back_child: Map::SUB_SECTOR_FLAG,
+    });
+
+    map_data
+}
+
+#[test]
+fn test_step_height() {
+    let east = TicCmd { forward_move: 24, ..Default::default() };
+    let start = Thing { x: 32, y: 64, angle: 0, t_type: 1, flags: 7 };
+
+    let map_data = test_step_map("doom_rs_step_16.wad", 16);
+    assert_eq!(map_data.sector_at(96.0, 64.0).unwrap().floor_height, 16);
+
+    let mut engine = Engine::new(&map_data, Player::new(start));
+    assert_eq!(engine.player.view_z, 41.0);
+
+    for _ in 0..10 {
+        engine.tic(east);
+    }
+
+    assert!(engine.player.position.0 > 64.0);
+    assert_eq!(engine.player.view_z, 57.0);
+
+    let map_data = test_step_map("doom_rs_step_32.wad", 32);
+
+    let mut engine = Engine::new(&map_data, Player::new(start));
+
+    for _ in 0..10 {
+        engine.tic(east);
+    }
+
+    assert!(engine.player.position.0 <= 64.0);
+    assert_eq!(engine.player.view_z, 41.0);
+}
+
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+enum DrawCall {
+    Line(f32, f32, f32, f32),
+    Circle(f32, f32),
+    Rect(f32, f32, f32, f32),
+    Polygon(Vec<(f32, f32)>),
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct RecordingCanvas {
+    calls: Vec<DrawCall>,
+}
+
+#[cfg(test)]
+impl Canvas for RecordingCanvas {
+    fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, _color: Color) {
+        self.calls.push(DrawCall::Line(x1, y1, x2, y2));
+    }
+
+    fn circle(&mut self, x: f32, y: f32, _radius: f32, _color: Color) {
+        self.calls.push(DrawCall::Circle(x, y));
+    }
+
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, _color: Color) {
+        self.calls.push(DrawCall::Rect(x, y, width, height));
+    }
+
+    fn polygon(&mut self, points: &[(f32, f32)], _color: Color) {
+        self.calls.push(DrawCall::Polygon(points.to_vec()));
+    }
+}
+
+#[test]
+fn test_canvas_draw_calls() {
+    let mut map_data = test_room_map("doom_rs_canvas.wad");
+    map_data.line_defs.truncate(2);
+
+    // 128 map units over 188 - 2 * 30 pixels.
+    let drawer = MapDrawer::new(&map_data, 188.0, 188.0);
+    let mut canvas = RecordingCanvas::default();
+
+    drawer.draw_linedefs(&mut canvas);
+
+    assert_eq!(
+        canvas.calls,
+        vec![
+            DrawCall::Line(30.0, 158.0, 158.0, 158.0),
+            DrawCall::Line(158.0, 158.0, 158.0, 30.0),
+        ]
+    );
+}
+
+#[test]
+fn test_dehacked() {
+    let patch = "\
+Patch File for DeHackEd v3.0
+# Tougher imps.
+Doom version = 21
+Patch format = 6
+
+Thing 12 (Imp)
+Hit points = 120
+Speed = 10
+
+Frame 15
+Duration = 4
+
+Weapon 1 (Pistol)
+Ammo type = 1
+";
+
+    let mut lumps = test_map_lumps("MAP01");
+    lumps.push(("DEHACKED".to_string(), patch.as_bytes().to_vec()));
+
+    let path = write_test_wad("doom_rs_dehacked.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    let dehacked = Dehacked::parse(&map_data.dehacked().unwrap());
+
+    assert_eq!(dehacked.things[&12]["Hit points"], "120");
+    assert_eq!(dehacked.things[&12]["Speed"], "10");
+    assert_eq!(dehacked.frames[&15]["Duration"], "4");
+    assert_eq!(dehacked.weapons[&1]["Ammo type"], "1");
+    assert_eq!(dehacked.things.len(), 1);
+}
+
+#[test]
+fn test_mus_to_midi() {
+    let score = [
+        0x40, 0x00, 0x22, // Instrument 34 on channel 0.
+        0x90, 0xBC, 0x64, 0x23, // Play note 60 at volume 100, then wait 35 ticks.
+        0x00, 0x3C, // Release note 60.
+        0x60, // Score end.
+    ];
+
+    let mut mus = b"MUS\x1A".to_vec();
+
+    for value in [score.len() as u16, 16, 1, 0, 0, 0] {
+        mus.extend_from_slice(&value.to_le_bytes());
+    }
+
+    mus.extend_from_slice(&score);
+
+    let mut lumps = test_map_lumps("E1M1");
+    lumps.push(("D_E1M1".to_string(), mus));
+
+    let path = write_test_wad("doom_rs_music.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    let midi = mus_to_midi(&map_data.music("D_E1M1").unwrap());
+
+    assert_eq!(&midi[0..4], b"MThd");
+    assert_eq!(&midi[14..18], b"MTrk");
+
+    let track = &midi[22..];
+
+    assert_eq!(&track[7..10], &[0x00, 0xC0, 0x22]);
+    assert_eq!(&track[10..14], &[0x00, 0x90, 0x3C, 0x64]);
+    assert_eq!(&track[14..18], &[0x23, 0x80, 0x3C, 0x40]);
+    assert_eq!(&track[track.len() - 3..], &[0xFF, 0x2F, 0x00]);
+}
+
+#[test]
+fn test_sound() {
+    let samples = (0..64).map(|i| (128 + i) as u8).collect::<Vec<_>>();
+
+    let mut dmx = Vec::new();
+    dmx.extend_from_slice(&3u16.to_le_bytes());
+    dmx.extend_from_slice(&11025u16.to_le_bytes());
+    dmx.extend_from_slice(&(samples.len() as u32 + 32).to_le_bytes());
+    dmx.extend_from_slice(&[128; 16]);
+    dmx.extend_from_slice(&samples);
+    dmx.extend_from_slice(&[128; 16]);
+
+    let mut lumps = test_map_lumps("E1M1");
+    lumps.push(("DSPISTOL".to_string(), dmx));
+    lumps.push(("DSBROKEN".to_string(), vec![3, 0, 0x11, 0x2B, 0xFF, 0, 0, 0]));
+
+    let path = write_test_wad("doom_rs_sound.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    let sound = map_data.sound("DSPISTOL").unwrap();
+
+    assert_eq!(sound.sample_rate, 11025);
+    assert_eq!(sound.samples, samples);
+
+    assert!(matches!(map_data.sound("DSBROKEN"), Err(WadError::InvalidLump(_))));
+}
+
+#[test]
+fn test_walk_visible() {
+    let mut map_data = test_room_map("doom_rs_walk_visible.wad");
+
+    // Partition along the Y axis, pointing north: east is in front.
+    map_data.nodes.push(Node {
+        x_partition: 0,
+        y_partition: 0,
+        dx_partition: 0,
+        dy_partition: 128,
+        front_bbox: [64, -64, 64, 128],
+        back_bbox: [64, -64, -128, -64],
+        front_child: Map::SUB_SECTOR_FLAG,
+        back_child: Map::SUB_SECTOR_FLAG | 1,
+    });
+
+    let bsp = BSP::new(&map_data);
+
+    // Standing on the partition, looking east.
+    let player = Player::new(Thing { x: 0, y: 0, angle: 0, t_type: 1, flags: 7 });
+
+    let mut visited = Vec::new();
+    bsp.walk_visible(&player, 90.0, &mut |id| visited.push(id));
+
+    assert_eq!(visited, vec![0]);
+
+    // Looking west, only the rear box is visible.
+    let player = Player::new(Thing { x: 0, y: 0, angle: 180, t_type: 1, flags: 7 });
+
+    let mut visited = Vec::new();
+    bsp.walk_visible(&player, 90.0, &mut |id| visited.push(id));
+
+    assert_eq!(visited, vec![1]);
+}
+
+#[test]
+fn test_map_hash() {
+    let mut lumps = test_map_lumps("MAP01");
+    let mut map02 = test_map_lumps("MAP02");
+
+    // Same room with one corner moved.
+    map02[4].1[0] = 8;
+    lumps.append(&mut map02);
+
+    let path = write_test_wad("doom_rs_map_hash.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    map_data.change_map("MAP01").unwrap();
+    let first = map_data.map_hash();
+
+    map_data.change_map("MAP02").unwrap();
+    let other = map_data.map_hash();
+
+    map_data.change_map("MAP01").unwrap();
+
+    assert_eq!(map_data.map_hash(), first);
+    assert_ne!(first, other);
+
+    // The same as hashing the lumps as stored in the WAD.
+    let stored = [
+        MapLumpIndex::Things,
+        MapLumpIndex::LineDefs,
+        MapLumpIndex::SideDefs,
+        MapLumpIndex::Vertexes,
+        MapLumpIndex::Sectors,
+    ];
+
+    let hash = stored.iter().fold(Map::FNV_OFFSET, |hash, &index| {
+        let bytes = map_data.map_lump(index).unwrap();
+        let hash = Map::hash_bytes(hash, &(bytes.len() as u64).to_le_bytes());
+
+        Map::hash_bytes(hash, &bytes)
+    });
+
+    assert_eq!(first, hash);
+}
+
+#[test]
+fn test_from_reader() {
+    let bytes = test_wad_bytes(&test_map_lumps("MAP01"));
+
+    let mut map_data = WAD::from_reader(io::Cursor::new(bytes)).unwrap();
+
+    assert_eq!(map_data.header.identification(), "PWAD");
+    map_data.change_map("MAP01").unwrap();
+    assert_eq!(map_data.line_defs.len(), 4);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_open_mmap() {
+    let palette = (0..768 * 14).map(|i| i as u8).collect::<Vec<_>>();
+
+    let mut lumps = test_map_lumps("MAP01");
+    lumps.push(("PLAYPAL".to_string(), palette.clone()));
+
+    let path = write_test_wad("doom_rs_mmap.wad", &lumps);
+
+    let mut mapped = WAD::open_mmap(&path).unwrap();
+    let mut file = WAD::new(&path).unwrap();
+
+    assert_eq!(mapped.read_lump_bytes("PLAYPAL").unwrap(), palette);
+    assert_eq!(
+        mapped.read_lump_bytes("PLAYPAL").unwrap(),
+        file.read_lump_bytes("PLAYPAL").unwrap()
+    );
+}
+
+// Synthetic maps instead of DOOM.wad, so the test runs without the IWAD and
+// covers a map that fails to load.
+#[test]
+fn test_map_stats_parallel() {
+    let mut lumps = test_map_lumps("MAP01");
+    let mut map02 = test_map_lumps("MAP02");
+
+    // An imp in the second map, and a secret sector.
+    let imp = Thing { x: 32, y: 32, angle: 0, t_type: 3001, flags: 7 };
+    map02[1].1.extend_from_slice(&test_lump_bytes(&[imp]));
+    map02[8].1[22] = 9;
+    lumps.append(&mut map02);
+
+    let path = write_test_wad("doom_rs_map_stats.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    let mut sequential = Vec::new();
+
+    for name in map_data.map_names() {
+        map_data.change_map(&name).unwrap();
+        sequential.push(MapStats::new(&name, &map_data));
+    }
+
+    let parallel = map_data.map_stats_parallel().unwrap();
+    let parallel = parallel.into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+    assert_eq!(parallel, sequential);
+    assert_eq!(parallel[1].monsters, 1);
+    assert_eq!(parallel[1].secrets, 1);
+    assert_eq!(parallel[1].things, 2);
+
+    // Without a path there's no file for the workers to open.
+    let memory = WAD::from_reader(io::Cursor::new(fs::read(&path).unwrap())).unwrap();
+    assert!(matches!(memory.map_stats_parallel(), Err(WadError::NoPath)));
+
+    // A map that fails to load has its error in its place.
+    let mut map03 = test_map_lumps("MAP03");
+    map03[2].1.truncate(15);
+    lumps.append(&mut map03);
+
+    let path = write_test_wad("doom_rs_map_stats_broken.wad", &lumps);
+    let stats = WAD::new(&path).unwrap().map_stats_parallel().unwrap();
+
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats[1].as_ref().unwrap(), &sequential[1]);
+    assert!(matches!(stats[2], Err(WadError::TruncatedLump { .. })));
+}
+
+#[test]
+fn test_e1m1_secrets() {
+    let mut map_data = WAD::new(DOOM_WAD_PATH).unwrap();
+    map_data.change_map("E1M1").unwrap();
+
+    assert_eq!(map_data.secret_sectors(), 3);
+}
+
+#[test]
+fn test_secret_counts() {
+    let mut map_data = test_room_map("doom_rs_secrets.wad");
+
+    map_data.sectors[0].special_type = 9;
+    map_data.line_defs[2].flags |= LineDefFlags::Secret as i16;
+    map_data.things[0].t_type = 3004;
+
+    assert_eq!(map_data.secret_sectors(), 1);
+    assert_eq!(map_data.secret_linedefs(), 1);
+    assert_eq!(map_data.monster_count(), 1);
+}
+
+#[test]
+fn test_nearest_linedef() {
+    let mut map_data = test_room_map("doom_rs_nearest_linedef.wad");
+
+    // On the east wall, then just inside the north wall.
+    let (index, distance) = map_data.nearest_linedef(128.0, 40.0).unwrap();
+    assert_eq!(index, 1);
+    assert!(distance.abs() < 0.001);
+
+    let (index, distance) = map_data.nearest_linedef(50.0, 120.0).unwrap();
+    assert_eq!(index, 2);
+    assert!((distance - 8.0).abs() < 0.001);
+
+    map_data.line_defs.clear();
+    assert!(map_data.nearest_linedef(0.0, 0.0).is_none());
+}
+
+#[test]
+fn test_map_bounds_round_trip() {
+    let bounds = MapBounds {
+        min_x: -768.0,
+        max_x: 3808.0,
+        min_y: -4864.0,
+        max_y: -2048.0,
+    };
+
+    let (w, h) = (1280.0, 800.0);
+
+    for i in 0..=20 {
+        let x = bounds.min_x + (bounds.max_x - bounds.min_x) * i as f32 / 20.0;
+        let y = bounds.min_y + (bounds.max_y - bounds.min_y) * i as f32 / 20.0;
+
+        let margin = MapBounds::DEFAULT_MARGIN;
+
+        assert!((bounds.from_screen_x(bounds.to_screen_x(x, w, margin), w, margin) - x).abs() < 0.01);
+        assert!((bounds.from_screen_y(bounds.to_screen_y(y, h, margin), h, margin) - y).abs() < 0.01);
+    }
+}
+
+#[test]
+fn test_sub_sector_closing_edge() {
+    let mut map_data = test_room_map("doom_rs_closing_edge.wad");
+
+    // Leave the room's last wall implicit, as a node builder would for a
+    // subsector bordered by a partition line.
+    map_data.ssectors[0].num_segs = 3;
+
+    let drawer = MapDrawer::new(&map_data, 188.0, 188.0);
+    assert_eq!(drawer.closing_edge(0), Some((3, 0)));
+    assert_eq!(drawer.closing_edge(1), None);
+
+    let mut canvas = RecordingCanvas::default();
+    drawer.draw_sub_sector(&mut canvas, 0);
+
+    assert_eq!(
+        canvas.calls,
+        vec![
+            DrawCall::Polygon(vec![(30.0, 158.0), (158.0, 158.0), (158.0, 30.0), (30.0, 30.0)]),
+            DrawCall::Line(30.0, 30.0, 30.0, 158.0),
+        ]
+    );
+}
+
+#[test]
+fn test_validate_current_map() {
+    let mut map_data = test_room_map("doom_rs_validate.wad");
+
+    assert_eq!(map_data.validate_current_map(), Vec::new());
+
+    map_data.line_defs[2].right_sidedef = 9999;
+
+    assert_eq!(
+        map_data.validate_current_map(),
+        vec![MapIssue { kind: MapIssueKind::InvalidSidedefRef, index: 2 }]
+    );
+}
+
+#[test]
+fn test_bam_conversion() {
+    assert_eq!(degrees_to_bam(90.0), 0x4000);
+    assert!((bam_to_degrees(degrees_to_bam(90.0)) - 90.0).abs() < 0.01);
+
+    assert_eq!(degrees_to_bam(-90.0), 0xC000);
+    assert_eq!(degrees_to_bam(360.0), 0);
+
+    let player = Player::new(Thing { x: 0, y: 0, angle: -90, t_type: 1, flags: 7 });
+    assert_eq!(player.angle, 270.0);
+}
+
+#[test]
+fn test_animated_flats() {
+    let flat = |name: &str| (name.to_string(), vec![0; 4096]);
+    let marker = |name: &str| (name.to_string(), Vec::new());
+
+    let mut texture1 = Vec::new();
+    texture1.extend_from_slice(&3i32.to_le_bytes());
+
+    for i in 0..3 {
+        texture1.extend_from_slice(&(16 + i * 22i32).to_le_bytes());
+    }
+
+    for name in ["SFALL1", "SFALL2", "STARTAN3"] {
+        texture1.extend_from_slice(&test_lump_name(name));
+        texture1.extend_from_slice(&[0; 14]);
+    }
+
+    let lumps = vec![
+        marker("F_START"),
+        marker("F1_START"),
+        flat("FLOOR4_8"),
+        flat("NUKAGE1"),
+        flat("NUKAGE2"),
+        flat("NUKAGE3"),
+        flat("BLOOD1"),
+        marker("F1_END"),
+        marker("F_END"),
+        ("TEXTURE1".to_string(), texture1),
+    ];
+
+    let path = write_test_wad("doom_rs_animated_flats.wad", &lumps);
+    let mut wad = WAD::new(&path).unwrap();
+
+    assert_eq!(wad.animated_flats().unwrap(), vec![vec!["NUKAGE1", "NUKAGE2", "NUKAGE3"]]);
+
+    assert_eq!(wad.texture_names().unwrap(), vec!["SFALL1", "SFALL2", "STARTAN3"]);
+    assert_eq!(wad.animated_textures().unwrap(), Vec::<Vec<String>>::new());
+}
+
+#[test]
+fn test_endoom() {
+    let mut endoom = vec![0; 4000];
+
+    // A blinking yellow on blue 'D', then a shaded block.
+    endoom[0] = b'D';
+    endoom[1] = 0x80 | (1 << 4) | 14;
+    endoom[2] = 0xB1;
+    endoom[3] = 0x07;
+
+    let path = write_test_wad("doom_rs_endoom.wad", &[("ENDOOM".to_string(), endoom)]);
+    let mut wad = WAD::new(&path).unwrap();
+
+    let screen = wad.endoom().unwrap();
+
+    assert_eq!(screen.cells.len(), 2000);
+    assert_eq!(
+        screen.cells[0],
+        TextCell { ch: 'D', fg: (255, 255, 85), bg: (0, 0, 170), blink: true }
+    );
+    assert_eq!(screen.cell(1, 0).unwrap().ch, '▒');
+
+    let ansi = screen.to_ansi();
+    assert!(ansi.starts_with("\x1b[0;5;38;2;255;255;85;48;2;0;0;170mD"));
+    assert_eq!(ansi.lines().count(), 25);
+
+    let path = write_test_wad("doom_rs_endoom_short.wad", &[("ENDOOM".to_string(), vec![0; 100])]);
+    assert!(matches!(WAD::new(&path).unwrap().endoom(), Err(WadError::InvalidLump(_))));
+}
+
+#[test]
+fn test_floor_color() {
+    let mut playpal = vec![0; Palette::SIZE * 14];
+    playpal[3..6].copy_from_slice(&[200, 100, 50]);
+
+    let lumps = vec![
+        ("PLAYPAL".to_string(), playpal),
+        ("F_START".to_string(), Vec::new()),
+        ("FLOOR4_8".to_string(), vec![1; WAD::FLAT_SIZE]),
+        ("F_END".to_string(), Vec::new()),
+    ];
+
+    let path = write_test_wad("doom_rs_floor_color.wad", &lumps);
+    let mut wad = WAD::new(&path).unwrap();
+
+    assert_eq!(wad.palette(0).unwrap().colors[1], (200, 100, 50));
+    assert!(matches!(wad.palette(14), Err(WadError::InvalidLump(_))));
+
+    // Nothing is averaged until it's looked up, then only once.
+    let mut flat_colors = FlatColors::new(&mut wad).unwrap();
+    assert!(flat_colors.colors.is_empty());
+
+    assert_eq!(flat_colors.get("FLOOR4_8"), Some(Color::rgb(200, 100, 50)));
+    assert_eq!(flat_colors.get("FLOOR4_8"), Some(Color::rgb(200, 100, 50)));
+    assert_eq!((flat_colors.colors.len(), flat_colors.flats.len()), (1, 0));
+
+    let sector = |light_level| Sector {
+        floor_height: 0,
+        ceiling_height: 128,
+        floor_texture: test_lump_name("FLOOR4_8"),
+        ceiling_texture: test_lump_name("F_SKY1"),
+        light_level,
+        special_type: 0,
+        tag: 0,
+    };
+
+    let bright = flat_colors.floor_color(&sector(255));
+    let dark = flat_colors.floor_color(&sector(96));
+
+    assert_eq!(bright, Color::rgb(200, 100, 50));
+    assert!(dark.r < bright.r && dark.g < bright.g && dark.b < bright.b);
+}
+
+#[test]
+fn test_thing_appears_on() {
+    let thing = |flags| Thing { x: 0, y: 0, angle: 0, t_type: 3001, flags };
+
+    let hard_only = thing(ThingFlags::Hard as i16 | ThingFlags::Deaf as i16);
+    assert!(!hard_only.appears_on(Skill::Easy, false));
+    assert!(hard_only.appears_on(Skill::Hard, false));
+    assert!(hard_only.appears_on(Skill::Nightmare, false));
+
+    let coop = thing(7 | ThingFlags::MultiplayerOnly as i16);
+    assert!(!coop.appears_on(Skill::Medium, false));
+    assert!(coop.appears_on(Skill::Medium, true));
+}
+
+#[test]
+fn test_subsector_segs() {
+    let mut map_data = test_room_map("doom_rs_subsector_segs.wad");
+
+    let segs = map_data.subsector_segs(0);
+    assert_eq!(segs.len(), map_data.ssectors[0].num_segs as usize);
+    assert_eq!(segs[3].linedef, 3);
+
+    assert!(map_data.subsector_segs(1).is_empty());
+
+    map_data.ssectors[0].num_segs = 5;
+    assert!(map_data.subsector_segs(0).is_empty());
+}
+
+#[test]
+fn test_node_rects() {
+    let map_data = test_step_map("doom_rs_node_rects.wad", 0);
+    let node = map_data.nodes[0];
+
+    let front = node.front_rect();
+    let back = node.back_rect();
+
+    assert_eq!(front, Rect::from_bbox(node.front_bbox));
+    assert_eq!(back, Rect::from_bbox(node.back_bbox));
+
+    assert_eq!((front.width(), front.height()), (64, 128));
+    assert!(front.contains(front.left as f32 + 16.0, 64.0));
+    assert!(!front.contains(front.left as f32 - 16.0, 64.0));
+    assert!(!front.contains(front.left as f32 + 16.0, 160.0));
+}
+
+#[test]
+fn test_open_readonly() {
+    let path = write_test_wad("doom_rs_open_readonly.wad", &test_map_lumps("MAP01"));
+
+    let mut wad = WAD::open_readonly(&path).unwrap();
+
+    assert!(wad.things.is_empty());
+    assert!(matches!(wad.map_lump(MapLumpIndex::Reject), Err(WadError::NoMapLoaded)));
+
+    wad.change_map("MAP01").unwrap();
+    assert_eq!(wad.things.len(), 1);
+    assert_eq!(wad.map_lump(MapLumpIndex::Reject).unwrap(), vec![0]);
+}
+
+#[test]
+fn test_change_map_failure_keeps_map() {
+    let mut lumps = test_map_lumps("MAP01");
+    let mut broken = test_map_lumps("MAP02");
+
+    // A different player start, then SECTORS cut off mid-record. It's read
+    // last, after everything else of MAP02 was parsed.
+    broken[MapLumpIndex::Things as usize].1[0] = 32;
+    broken[MapLumpIndex::Sectors as usize].1.truncate(Sector::SIZE - 2);
+    lumps.extend(broken);
+
+    let path = write_test_wad("doom_rs_change_map_failure.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    map_data.change_map("MAP01").unwrap();
+
+    let things = records_to_bytes(&map_data.things);
+    let line_defs = records_to_bytes(&map_data.line_defs);
+    let sectors = records_to_bytes(&map_data.sectors);
+
+    assert!(matches!(map_data.change_map("MAP02"), Err(WadError::TruncatedLump { .. })));
+
+    assert_eq!(records_to_bytes(&map_data.things), things);
+    assert_eq!(records_to_bytes(&map_data.line_defs), line_defs);
+    assert_eq!(records_to_bytes(&map_data.sectors), sectors);
+    assert_eq!(map_data.current_map_name().as_deref(), Some("MAP01"));
+}
+
+#[test]
+fn test_load_map_outlives_wad() {
+    let path = write_test_wad("doom_rs_load_map.wad", &test_map_lumps("MAP01"));
+
+    let map = {
+        let mut wad = WAD::new(&path).unwrap();
+        let map = wad.load_map("MAP01").unwrap();
+
+        // Loading doesn't touch the current map.
+        assert!(wad.things.is_empty());
+        assert!(matches!(
+            wad.load_map("MAP02"),
+            Err(WadError::MapNotFound(name)) if name == "MAP02"
+        ));
+
+        map
+    };
+
+    assert_eq!(map.line_defs.len(), 4);
+    assert_eq!(map.sector_at(64.0, 64.0).unwrap().ceiling_height, 128);
+
+    let engine = Engine::new(&map, Player::new(map.things[0]));
+    assert_eq!(engine.player.view_z, 41.0);
+}
+
+#[test]
+fn test_render_margin() {
+    let map_data = test_room_map("doom_rs_render_margin.wad");
+
+    let mut drawer = MapDrawer::new(&map_data, 100.0, 100.0);
+    assert_eq!(drawer.traslate_vertex_x(0.0), 30.0);
+
+    drawer.margin = 0.0;
+
+    assert_eq!(drawer.traslate_vertex_x(0.0), 0.0);
+    assert_eq!(drawer.traslate_vertex_y(0.0), 100.0);
+    assert_eq!(drawer.traslate_vertex_x(128.0), 100.0);
+}
+
+#[test]
+fn test_thing_histogram() {
+    let mut map_data = test_room_map("doom_rs_thing_histogram.wad");
+
+    let imp = Thing { x: 32, y: 32, angle: 0, t_type: 3001, flags: 7 };
+    map_data.things.extend([imp, imp, Thing { t_type: 2001, ..imp }]);
+
+    let histogram = map_data.thing_histogram();
+
+    assert_eq!(histogram.values().sum::<usize>(), map_data.things.len());
+    assert_eq!(histogram, BTreeMap::from([(1, 1), (2001, 1), (3001, 2)]));
+    assert_eq!(map_data.count_thing(3001), 2);
+    assert_eq!(map_data.count_thing(3002), 0);
+}
+
+#[test]
+fn test_current_map_name() {
+    let mut lumps = test_map_lumps("E1M1");
+    lumps.extend(test_map_lumps("E1M2"));
+
+    let path = write_test_wad("doom_rs_current_map_name.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    assert_eq!(map_data.current_map_name(), None);
+
+    map_data.change_map("E1M2").unwrap();
+
+    assert_eq!(map_data.current_map_name().as_deref(), Some("E1M2"));
+    assert_eq!(map_data.current_map_index(), Some(11));
+}
+
+#[test]
+fn test_map_without_nodes() {
+    let lumps = test_map_lumps("MAP01")
+        .into_iter()
+        .filter(|(name, _)| !matches!(name.as_str(), "SEGS" | "SSECTORS" | "NODES"))
+        .chain(test_map_lumps("MAP02"))
+        .collect::<Vec<_>>();
+
+    let path = write_test_wad("doom_rs_map_without_nodes.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    map_data.change_map("MAP01").unwrap();
+
+    assert!(map_data.nodes.is_empty());
+    assert!(map_data.segs.is_empty());
+    assert!(map_data.ssectors.is_empty());
+    assert_eq!(map_data.sectors.len(), 1);
+    assert_eq!(map_data.sectors[0].ceiling_height, 128);
+
+    assert!(matches!(map_data.map_lump(MapLumpIndex::Nodes), Err(WadError::LumpNotFound(_))));
+    assert_eq!(map_data.map_lump(MapLumpIndex::Reject).unwrap(), vec![0]);
+
+    // The geometry lumps aren't optional, MAP01 stays loaded.
+    let lumps = test_map_lumps("MAP02")
+        .into_iter()
+        .filter(|(name, _)| name != "LINEDEFS")
+        .collect::<Vec<_>>();
+
+    let path = write_test_wad("doom_rs_map_without_linedefs.wad", &lumps);
+    let mut without_linedefs = WAD::new(&path).unwrap();
+
+    assert!(matches!(
+        without_linedefs.change_map("MAP02"),
+        Err(WadError::InvalidLump(name)) if name == "LINEDEFS"
+    ));
+    assert_eq!(without_linedefs.current_map_name(), None);
+}
+
+#[test]
+fn test_sector_perimeter() {
+    let mut map_data = test_room_map("doom_rs_sector_perimeter.wad");
+
+    // Stretch the room to 256x128.
+    map_data.vertexes[1].x = 256;
+    map_data.vertexes[2].x = 256;
+
+    let walls = map_data.line_defs
+        .iter()
+        .map(|line| map_data.linedef_length(line))
+        .collect::<Vec<_>>();
+
+    assert_eq!(walls, vec![256.0, 128.0, 256.0, 128.0]);
+    assert_eq!(map_data.sector_perimeter(0), walls.iter().sum::<f32>());
+    assert_eq!(map_data.sector_perimeter(1), 0.0);
+    assert_eq!(map_data.total_map_length(), 768.0);
+
+    // A two-sided diagonal with the sector on both sides.
+    let side = map_data.side_defs[0];
+    map_data.side_defs.extend([side; 2]);
+    map_data.line_defs.push(LineDef {
+        start_vertex: 0,
+        end_vertex: 2,
+        flags: 4,
+        special_type: 0,
+        sector_tag: 0,
+        right_sidedef: 4,
+        left_sidedef: 5,
+    });
+
+    assert_eq!(map_data.sector_perimeter(0), 768.0);
+    assert!((map_data.total_map_length() - 768.0 - 128.0 * 5f32.sqrt()).abs() < 1e-3);
+}
+
+#[test]
+fn test_sector_area() {
+    let mut map_data = test_room_map("doom_rs_sector_area.wad");
+
+    assert!((map_data.sector_area(0) - 16384.0).abs() < 0.5);
+    assert_eq!(map_data.sector_area(1), 0.0);
+
+    // Split along the diagonal, each triangle's edge on it is implicit.
+    map_data.ssectors = vec![
+        SubSector { num_segs: 2, first_seg: 0 },
+        SubSector { num_segs: 2, first_seg: 2 },
+    ];
+
+    assert_eq!(map_data.subsector_polygon(0), vec![(0.0, 0.0), (128.0, 0.0), (128.0, 128.0)]);
+    assert!((map_data.sector_area(0) - 16384.0).abs() < 0.5);
+}
+
+#[test]
+fn test_nodes_within_depth() {
+    let mut map_data = test_step_map("doom_rs_nodes_within_depth.wad", 0);
+
+    // A root with two child nodes, one of which has a child node of its own.
+    let leaf = map_data.nodes[0];
+    let parent = |front_child, back_child| Node { front_child, back_child, ..leaf };
+
+    map_data.nodes = vec![leaf, parent(0, Map::SUB_SECTOR_FLAG), leaf, parent(1, 2)];
+
+    let drawer = MapDrawer::new(&map_data, 188.0, 188.0);
+
+    assert_eq!(drawer.nodes_within_depth(0), Vec::<usize>::new());
+    assert_eq!(drawer.nodes_within_depth(1), vec![3]);
+    assert_eq!(drawer.nodes_within_depth(2), vec![3, 1, 2]);
+    assert_eq!(drawer.nodes_within_depth(10).len(), 4);
+
+    let mut canvas = RecordingCanvas::default();
+    drawer.draw_nodes(&mut canvas, 2);
+
+    // Two boxes and a partition line per node.
+    assert_eq!(canvas.calls.len(), 9);
+}
+
+#[test]
+fn test_export_things() {
+    let mut map_data = test_room_map("doom_rs_export_things.wad");
+
+    let imp = Thing { x: 32, y: 32, angle: 0, t_type: 3001, flags: 7 };
+    map_data.things.push(imp);
+
+    let svg = map_data.export_svg(SvgOptions::default());
+
+    assert_eq!(svg.matches("<circle").count(), 2);
+    assert_eq!(svg.matches("<line").count(), 4 + 2);
+    assert!(svg.contains("<line x1=\"32\" y1=\"-32\" x2=\"48\" y2=\"-32\" stroke=\"red\"/>"));
+    let svg_options = SvgOptions { include_things: false, ..Default::default() };
+    assert_eq!(map_data.export_svg(svg_options).matches("<circle").count(), 0);
+
+    let obj = map_data.export_obj(true, ObjOptions::default());
+
+    assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), 4);
+    assert_eq!(obj.lines().filter(|line| line.starts_with("l ")).count(), 2);
+    assert!(obj.contains("v 0 0 0\nv 128 0 0\nv 128 0 128\nv 0 0 128\nf 1 2 3 4\n"));
+    assert!(!map_data.export_obj(false, ObjOptions::default()).contains("l "));
+}
+
+#[test]
+fn test_truncated_directory() {
+    let mut bytes = vec![0; 1024];
+    bytes[..4].copy_from_slice(b"PWAD");
+    bytes[4..8].copy_from_slice(&1_000_000u32.to_le_bytes());
+    bytes[8..12].copy_from_slice(&12u32.to_le_bytes());
+
+    let started = Instant::now();
+
+    assert!(matches!(
+        WAD::from_reader(io::Cursor::new(bytes.clone())),
+        Err(WadError::TruncatedLump { .. })
+    ));
+
+    let path = std::env::temp_dir().join("doom_rs_truncated_directory.wad");
+    fs::write(&path, bytes).unwrap();
+
+    assert!(matches!(
+        WAD::open_readonly(path.to_str().unwrap()),
+        Err(WadError::TruncatedLump { .. })
+    ));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_lump_reader() {
+    let data = (0..64 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+
+    let path = write_test_wad(
+        "doom_rs_lump_reader.wad",
+        &[("D_E1M1".to_string(), data.clone()), ("D_E1M2".to_string(), vec![0xFF; 4])],
+    );
+    let mut wad = WAD::new(&path).unwrap();
+
+    let mut prefix = [0; 16];
+    wad.lump_reader("D_E1M1").unwrap().read_exact(&mut prefix).unwrap();
+    assert_eq!(prefix[..], data[..16]);
+
+    // Doesn't read into the next lump.
+    let mut rest = Vec::new();
+    wad.lump_reader("D_E1M2").unwrap().read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, vec![0xFF; 4]);
+
+    assert!(matches!(wad.lump_reader("D_E1M3"), Err(WadError::LumpNotFound(_))));
+}
+
+#[test]
+fn test_load_all_maps() {
+    let mut lumps = test_map_lumps("E1M1");
+    lumps.extend(test_map_lumps("E1M2"));
+    lumps.push(("ENDOOM".to_string(), vec![0; 4000]));
+    lumps.extend(test_map_lumps("E1M3"));
+
+    let path = write_test_wad("doom_rs_load_all_maps.wad", &lumps);
+    let mut wad = WAD::new(&path).unwrap();
+
+    let maps = wad.load_all_maps().unwrap();
+
+    let mut names = maps.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+
+    assert_eq!(names, vec!["E1M1", "E1M2", "E1M3"]);
+    assert!(maps.values().all(|map| !map.vertexes.is_empty()));
+    assert_eq!(wad.current_map_name(), None);
+}
+
+#[test]
+fn test_line_of_sight() {
+    let mut map_data = test_step_map("doom_rs_line_of_sight.wad", 24);
+
+    let (west, east) = ((32.0, 64.0), (96.0, 64.0));
+
+    // Over the step.
+    assert!(BSP::new(&map_data).line_of_sight(west, east));
+    assert!(BSP::new(&map_data).line_of_sight((16.0, 16.0), (48.0, 100.0)));
+
+    // A solid wall between them.
+    map_data.line_defs[4].left_sidedef = -1;
+    assert!(!BSP::new(&map_data).line_of_sight(west, east));
+    assert!(!BSP::new(&map_data).line_of_sight(east, west));
+
+    // Back to two-sided, but with the east floor up to the ceiling.
+    map_data.line_defs[4].left_sidedef = 5;
+    map_data.sectors[1].floor_height = 128;
+    assert!(!BSP::new(&map_data).line_of_sight(west, east));
+
+    // Reject says sector 0 can't see sector 1: bit 0 * 2 + 1.
+    map_data.sectors[1].floor_height = 24;
+    map_data.reject = vec![0b0010];
+    assert!(!BSP::new(&map_data).line_of_sight(west, east));
+    assert!(BSP::new(&map_data).line_of_sight(east, west));
+}
+
+#[test]
+fn test_used_resources() {
+    let map_data = test_step_map("doom_rs_used_resources.wad", 24);
+
+    assert_eq!(map_data.used_textures(), BTreeSet::from(["STARTAN3".to_string()]));
+    assert_eq!(
+        map_data.used_flats(),
+        BTreeSet::from(["CEIL3_5".to_string(), "FLOOR4_8".to_string()])
+    );
+}
+
+#[test]
+fn test_obj_options() {
+    let map_data = test_step_map("doom_rs_obj_options.wad", 24);
+
+    let vertexes = |options| {
+        map_data
+            .export_obj(false, options)
+            .lines()
+            .filter_map(|line| line.strip_prefix("v "))
+            .map(|line| line.split(' ').map(|n| n.parse::<f32>().unwrap()).collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+    };
+
+    // The step's quad along x = 64, from the west floor up to the east one.
+    let scaled = vertexes(ObjOptions { scale: 32.0, ..Default::default() });
+    assert!(scaled.contains(&vec![2.0, 4.0, 0.0]));
+    assert!(scaled.contains(&vec![2.0, 0.0, 0.75]));
+
+    let y_up = vertexes(ObjOptions { scale: 32.0, y_up: true, ..Default::default() });
+    assert!(y_up.contains(&vec![2.0, 0.75, 0.0]));
+    assert!(y_up.contains(&vec![2.0, 0.0, -4.0]));
+}
+
+#[test]
+fn test_reload() {
+    let path = write_test_wad("doom_rs_reload.wad", &test_map_lumps("MAP01"));
+    let bytes = fs::read(&path).unwrap();
+
+    let mut map_data = WAD::new(&path).unwrap();
+    map_data.change_map("MAP01").unwrap();
+
+    fs::write(&path, &bytes[..8]).unwrap();
+    assert!(map_data.reload().is_err());
+    assert_eq!(map_data.current_map_name().as_deref(), Some("MAP01"));
+
+    // The directory reads fine but the map doesn't.
+    let mut lumps = test_map_lumps("MAP01");
+    lumps[2].1.truncate(15);
+
+    fs::write(&path, test_wad_bytes(&lumps)).unwrap();
+    assert!(matches!(map_data.reload(), Err(WadError::TruncatedLump { .. })));
+    assert_eq!(map_data.current_map_name().as_deref(), Some("MAP01"));
+    assert_eq!(map_data.directory[2].size, 4 * 14);
+    assert_eq!(map_data.line_defs.len(), 4);
+
+    fs::write(&path, &bytes).unwrap();
+    map_data.things.clear();
+    map_data.reload().unwrap();
+    assert_eq!(map_data.current_map_name().as_deref(), Some("MAP01"));
+    assert_eq!(map_data.things.len(), 1);
+
+    // Same WAD with the map renamed.
+    let renamed = write_test_wad("doom_rs_reload_renamed.wad", &test_map_lumps("MAP02"));
+    fs::write(&path, fs::read(renamed).unwrap()).unwrap();
+    map_data.reload().unwrap();
+    assert_eq!(map_data.current_map_name(), None);
+    assert!(map_data.things.is_empty());
+}
+
+#[test]
+fn test_sector_special() {
+    let mut sector = Sector {
+        floor_height: 0,
+        ceiling_height: 128,
+        floor_texture: test_lump_name("NUKAGE1"),
+        ceiling_texture: test_lump_name("CEIL3_5"),
+        light_level: 160,
+        special_type: 5,
+        tag: 0,
+    };
+
+    assert_eq!(sector.special(), SectorSpecial::DamageHellslime);
+    assert_eq!(sector.special().damage(), 10);
+    assert!(sector.is_damaging());
+
+    sector.special_type = 9;
+    assert_eq!(sector.special(), SectorSpecial::Secret);
+    assert!(!sector.is_damaging());
+
+    sector.special_type = 6;
+    assert_eq!(sector.special(), SectorSpecial::Unknown(6));
+}
+
+#[test]
+fn test_walk_ordered() {
+    let map_data = test_step_map("doom_rs_walk_ordered.wad", 24);
+    let bsp = BSP::new(&map_data);
+
+    let west = Player::new(Thing { x: 32, y: 64, angle: 0, t_type: 1, flags: 7 });
+
+    let walk = |player: &Player, order| {
+        let mut visited = Vec::new();
+        bsp.walk_ordered(player, order, &mut |id| visited.push(id));
+        visited
+    };
+
+    let front_to_back = walk(&west, TraversalOrder::FrontToBack);
+    let mut back_to_front = walk(&west, TraversalOrder::BackToFront);
+
+    assert_eq!(front_to_back, vec![0, 1]);
+    back_to_front.reverse();
+    assert_eq!(back_to_front, front_to_back);
+
+    let east = Player::new(Thing { x: 96, y: 64, angle: 0, t_type: 1, flags: 7 });
+    assert_eq!(walk(&east, TraversalOrder::FrontToBack), vec![1, 0]);
+
+    // A child pointing past NODES is skipped, like in `cross_node`.
+    let mut broken = map_data.map.clone();
+    broken.nodes[0].front_child = 5;
+
+    let mut visited = Vec::new();
+    BSP::new(&broken).walk_ordered(&west, TraversalOrder::FrontToBack, &mut |id| visited.push(id));
+    assert_eq!(visited.len(), 1);
+}
+
+#[test]
+fn test_miniseg() {
+    let mut map_data = test_step_map("doom_rs_miniseg.wad", 24);
+
+    let mut miniseg = map_data.segs[4];
+    miniseg.linedef = -1;
+
+    let context = map_data.seg_context(&miniseg);
+    assert!(context.front_sector.is_none() && context.back_sector.is_none());
+
+    // The west seg faces the lower floor, the east one the step.
+    assert_eq!(map_data.seg_context(&map_data.segs[4]).front_sector.unwrap().floor_height, 0);
+    assert_eq!(map_data.seg_context(&map_data.segs[5]).front_sector.unwrap().floor_height, 24);
+
+    map_data.segs[4] = miniseg;
+    assert_eq!(map_data.subsector_sector(0), None);
+    assert!(map_data.validate_current_map().is_empty());
+    assert!(BSP::new(&map_data).line_of_sight((32.0, 64.0), (96.0, 64.0)));
+}
+
+#[test]
+fn test_grid_lines() {
+    let bounds = MapBounds { min_x: -100.0, max_x: 300.0, min_y: 0.0, max_y: 128.0 };
+
+    let lines = bounds.grid_lines(64.0);
+
+    // x = -64 to 256 and y = 0 to 128.
+    assert_eq!(lines.len(), 6 + 3);
+    assert_eq!(lines[0], ((-64.0, 0.0), (-64.0, 128.0)));
+    assert_eq!(lines[6], ((-100.0, 0.0), (300.0, 0.0)));
+
+    assert_eq!(bounds.grid_lines(128.0).len(), 3 + 2);
+    assert!(bounds.grid_lines(0.0).is_empty());
+}
+
+#[test]
+fn test_udmf_map() {
+    let textmap = r#"
+        namespace = "zdoom";
+
+        // A 128 unit square room.
+        vertex { x = 0.0; y = 0.0; }
+        vertex { x = 128.0; y = 0.0; }
+        vertex { x = 128.0; y = 128.0; }
+        vertex { x = 0.0; y = 128.0; }
+
+        linedef { v1 = 0; v2 = 1; sidefront = 0; blocking = true; }
+        linedef { v1 = 1; v2 = 2; sidefront = 0; blocking = true; }
+        linedef { v1 = 2; v2 = 3; sidefront = 0; blocking = true; special = 11; arg0 = 3; }
+        linedef { v1 = 3; v2 = 0; sidefront = 0; blocking = true; }
+
+        sidedef { sector = 0; texturemiddle = "startan3"; }
+
+        /* Heights may be written as floats in ZDoom maps. */
+        sector {
+            heightfloor = -16;
+            heightceiling = 112.0;
+            texturefloor = "FLOOR4_8";
+            textureceiling = "F_SKY1";
+            lightlevel = 192;
+            id = 3;
+        }
+
+        thing {
+            x = 64.0; y = 64.0; angle = 90; type = 1;
+            skill1 = true; skill4 = true; single = true;
+        }
+    "#;
+
+    let lumps = vec![
+        ("MAP01".to_string(), Vec::new()),
+        ("TEXTMAP".to_string(), textmap.as_bytes().to_vec()),
+        ("ENDMAP".to_string(), Vec::new()),
+    ];
+
+    let path = write_test_wad("doom_rs_udmf.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    assert_eq!(map_data.map_names(), vec!["MAP01".to_string()]);
+    map_data.change_map("MAP01").unwrap();
+
+    assert_eq!(map_data.vertexes.len(), 4);
+    assert_eq!(map_data.line_defs.len(), 4);
+    assert_eq!(map_data.line_defs[2].sector_tag, 3);
+    assert!(map_data.line_defs[0].is_one_sided());
+    assert_eq!(map_data.side_defs[0].middle_texture(), "STARTAN3");
+
+    let sector = map_data.sectors[0];
+    assert_eq!((sector.floor_height, sector.ceiling_height), (-16, 112));
+    assert!(sector.is_sky());
+
+    assert_eq!(map_data.things[0].flags, 5);
+    assert!(map_data.things[0].appears_on(Skill::Hard, false));
+
+    assert!(Map::from_udmf("namespace = \"strife\";").is_err());
+    assert!(Map::from_udmf("vertex { x = 0.0 }").is_err());
+
+    // TEXTMAP is found past other lumps, but not in the next map.
+    let mut lumps = test_map_lumps("MAP01");
+    lumps.extend([
+        ("MAP02".to_string(), Vec::new()),
+        ("SCRIPTS".to_string(), Vec::new()),
+        ("TEXTMAP".to_string(), textmap.as_bytes().to_vec()),
+        ("ENDMAP".to_string(), Vec::new()),
+    ]);
+
+    let path = write_test_wad("doom_rs_udmf_scripts.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    assert_eq!(map_data.map_names(), vec!["MAP01".to_string(), "MAP02".to_string()]);
+
+    map_data.change_map("MAP01").unwrap();
+    assert_eq!(map_data.sectors[0].floor_height, 0);
+
+    map_data.change_map("MAP02").unwrap();
+    assert_eq!(map_data.sectors[0].floor_height, -16);
+}
+
+#[test]
+fn test_line_opening() {
+    // A door: the east sector's ceiling is down on its floor when closed.
+    let mut map_data = test_step_map("doom_rs_line_opening.wad", 8);
+    map_data.sectors[1].ceiling_height = 8;
+
+    let door = map_data.line_defs[4];
+
+    let closed = map_data.line_opening(&door).unwrap();
+    assert_eq!(closed, Opening { top: 8, bottom: 8, floor_gap: 8 });
+    assert_eq!(closed.height(), 0);
+
+    map_data.sectors[1].ceiling_height = 80;
+    let open = map_data.line_opening(&door).unwrap();
+    assert_eq!((open.top, open.bottom, open.height()), (80, 8, 72));
+
+    assert_eq!(map_data.line_opening(&map_data.line_defs[0]), None);
+
+    // Heights at both ends of i16 don't overflow.
+    map_data.sectors[0].floor_height = i16::MIN;
+    map_data.sectors[0].ceiling_height = i16::MIN;
+    map_data.sectors[1].floor_height = i16::MAX;
+    map_data.sectors[1].ceiling_height = i16::MAX;
+
+    let extreme = map_data.line_opening(&door).unwrap();
+    assert_eq!((extreme.floor_gap, extreme.height()), (65535, -65535));
+}
+
+#[test]
+fn test_player_dims() {
+    let map_data = test_room_map("doom_rs_player_dims.wad");
+
+    let start = Thing { x: 64, y: 64, angle: 0, t_type: 1, flags: 7 };
+
+    // 20 units from the west wall.
+    let engine = Engine::new(&map_data, Player::new(start));
+    assert!(engine.can_move_to(20.0, 64.0));
+
+    let wide = PlayerDims { radius: 32.0, ..Default::default() };
+    let engine = Engine::new(&map_data, Player::with_dims(start, wide));
+    assert!(!engine.can_move_to(20.0, 64.0));
+    assert!(engine.can_move_to(40.0, 64.0));
+
+    let tall = PlayerDims { height: 129.0, view_height: 100.0, ..Default::default() };
+    let engine = Engine::new(&map_data, Player::with_dims(start, tall));
+    assert_eq!(engine.player.view_z, 100.0);
+    assert!(!engine.can_move_to(64.0, 64.0));
+}
+
+#[test]
+fn test_picture_to_rgba() {
+    let mut palette = Palette { colors: [(0, 0, 0); 256] };
+    palette.colors[7] = (10, 20, 30);
+
+    // 2x3: the first column has rows 0 and 1, the second only row 2.
+    let mut patch = Vec::new();
+    for value in [2u16, 3, 0, 0] {
+        patch.extend(value.to_le_bytes());
+    }
+    patch.extend(16u32.to_le_bytes());
+    patch.extend(23u32.to_le_bytes());
+    patch.extend([0, 2, 0, 7, 7, 0, 0xFF]);
+    patch.extend([2, 1, 0, 7, 0, 0xFF]);
+
+    let picture = Picture::parse(&patch).unwrap();
+    let rgba = picture.to_rgba(&palette);
+
+    assert_eq!(rgba.len(), 2 * 3 * 4);
+    assert_eq!(&rgba[..4], &[10, 20, 30, 255]);
+
+    // Row 0 column 1, row 2 column 0 and row 1 column 1.
+    for pixel in [1, 4, 3] {
+        assert_eq!(rgba[pixel * 4 + 3], 0);
+    }
+    assert_eq!(rgba[5 * 4 + 3], 255);
+
+    let flat = [7; WAD::FLAT_SIZE];
+    let rgba = flat_to_rgba(&flat, &palette);
+    assert_eq!(rgba.len(), WAD::FLAT_SIZE * 4);
+    assert_eq!(&rgba[4..8], &[10, 20, 30, 255]);
+
+    assert!(Picture::parse(&patch[..18]).is_err());
+}
+
+#[test]
+fn test_automap_rotate() {
+    let map_data = test_room_map("doom_rs_automap_rotate.wad");
+
+    let mut drawer = MapDrawer::new(&map_data, 200.0, 100.0);
+    drawer.view_position = (64.0, 64.0);
+    drawer.view_angle = 0.0;
+
+    // Centered on the player, who stays facing east.
+    drawer.automap_mode = AutomapMode::Follow;
+    assert_eq!(drawer.to_screen(64.0, 64.0), (100.0, 50.0));
+
+    let (x, y) = drawer.to_screen(96.0, 64.0);
+    assert!(x > 100.0 && (y - 50.0).abs() < 0.001);
+
+    // A point ahead of the player ends up straight above them.
+    drawer.automap_mode = AutomapMode::Rotate;
+
+    let (x, y) = drawer.to_screen(96.0, 64.0);
+    assert!((x - 100.0).abs() < 0.001 && y < 50.0);
+
+    // And one to their right, to the right.
+    let (x, y) = drawer.to_screen(64.0, 32.0);
+    assert!(x > 100.0 && (y - 50.0).abs() < 0.001);
+}
+
+#[test]
+fn test_animated_boom() {
+    let boom_name = |name: &str| {
+        let mut bytes = test_lump_name(name).to_vec();
+        bytes.push(0);
+        bytes
+    };
+
+    let mut animated = vec![0];
+    animated.extend(boom_name("BLOOD3"));
+    animated.extend(boom_name("FLOOR4_8"));
+    animated.extend(8u32.to_le_bytes());
+    animated.push(0xFF);
+
+    let mut switches = boom_name("SW1BRCOM");
+    switches.extend(boom_name("SW2BRCOM"));
+    switches.extend(1u16.to_le_bytes());
+    switches.extend([0; 20]);
+
+    let flat = |name: &str| (name.to_string(), vec![0; 4096]);
+
+    let lumps = vec![
+        ("F_START".to_string(), Vec::new()),
+        flat("FLOOR4_8"),
+        flat("NUKAGE1"),
+        flat("NUKAGE2"),
+        flat("NUKAGE3"),
+        flat("BLOOD3"),
+        ("F_END".to_string(), Vec::new()),
+        ("ANIMATED".to_string(), animated),
+        ("SWITCHES".to_string(), switches),
+    ];
+
+    let path = write_test_wad("doom_rs_animated_boom.wad", &lumps);
+    let mut wad = WAD::new(&path).unwrap();
+
+    assert_eq!(
+        wad.animated_boom().unwrap(),
+        vec![AnimDef {
+            is_texture: false,
+            first: "FLOOR4_8".to_string(),
+            last: "BLOOD3".to_string(),
+            speed: 8,
+        }]
+    );
+
+    assert_eq!(
+        wad.switches_boom().unwrap(),
+        vec![SwitchDef { off: "SW1BRCOM".to_string(), on: "SW2BRCOM".to_string(), episode: 1 }]
+    );
+
+    // ANIMATED replaces the NUKAGE cycle.
+    assert_eq!(wad.animated_flats().unwrap().len(), 1);
+    assert_eq!(wad.animated_flats().unwrap()[0].len(), 5);
+}
+
+#[test]
+fn test_change_map_not_found() {
+    let path = write_test_wad("doom_rs_change_map_not_found.wad", &test_map_lumps("MAP01"));
+    let mut map_data = WAD::new(&path).unwrap();
+
+    assert!(matches!(
+        map_data.change_map("MAP02"),
+        Err(WadError::MapNotFound(name)) if name == "MAP02"
+    ));
+
+    // Lumps that aren't map markers aren't maps either.
+    let mut lumps = test_map_lumps("MAP01");
+    lumps.insert(0, ("PLAYPAL".to_string(), vec![0; Palette::SIZE]));
+
+    let path = write_test_wad("doom_rs_change_map_lump.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    for name in ["PLAYPAL", "THINGS"] {
+        assert!(matches!(map_data.change_map(name), Err(WadError::MapNotFound(n)) if n == name));
+    }
+    assert_eq!(map_data.current_map_name(), None);
+
+    // Just a header, with no lumps.
+    let header = b"PWAD\0\0\0\0\x0c\0\0\0".to_vec();
+    let mut empty = WAD::from_reader(io::Cursor::new(header)).unwrap();
+    assert!(matches!(empty.change_map("MAP01"), Err(WadError::DirectoryNotLoaded)));
+    assert!(matches!(empty.load_map("MAP01"), Err(WadError::DirectoryNotLoaded)));
+}
+
+#[test]
+fn test_seg_texture_offset() {
+    let map_data = test_room_map("doom_rs_seg_texture_offset.wad");
+
+    let mut side = map_data.side_defs[0];
+    side.x_offset = 8;
+
+    let seg = Seg { offset: 64, ..map_data.segs[0] };
+
+    assert_eq!(seg.texture_offset(&side), 72);
+    assert_eq!(seg.texture_column(&side, 0.0, 128), 72);
+    assert_eq!(seg.texture_column(&side, 60.5, 128), 4);
+
+    side.x_offset = -80;
+    assert_eq!(seg.texture_column(&side, 0.0, 64), 48);
+
+    // A wall 100 units ahead, from 50 to the left to 50 to the right.
+    let vertexes = [Vertex { x: 100, y: 50 }, Vertex { x: 100, y: -50 }];
+    let (map_data, segs) = test_view_segs("doom_rs_wall_column.wad", &vertexes, &[(0, 1)]);
+
+    let engine = test_origin_engine(&map_data);
+    let seg = Seg { offset: 64, ..segs[0] };
+    side.x_offset = 8;
+
+    // The seg's start shows column 72, the middle of the screen 50 units on.
+    assert_eq!(engine.wall_texture_column(&seg, &side, 80.0, 320.0, 128), Some(72));
+    assert_eq!(engine.wall_texture_column(&seg, &side, 160.0, 320.0, 128), Some(122));
+    assert_eq!(engine.wall_texture_column(&seg, &side, 240.0, 320.0, 256), Some(172));
+    assert_eq!(engine.wall_texture_column(&seg, &side, 20.0, 320.0, 128), None);
+}
+
+#[test]
+fn test_picture_sample() {
+    // 3x2, the middle of the bottom row transparent.
+    let picture = Picture {
+        width: 3,
+        height: 2,
+        left_offset: 0,
+        top_offset: 0,
+        pixels: vec![Some(1), Some(2), Some(3), Some(4), None, Some(6)],
+    };
+
+    assert_eq!(picture.sample(3, 0), Some(1));
+    assert_eq!(picture.sample(-1, 0), Some(3));
+    assert_eq!(picture.sample(-4, 1), Some(6));
+    assert_eq!(picture.sample(1, 1), None);
+
+    assert_eq!(picture.sample(0, -5), Some(1));
+    assert_eq!(picture.sample(0, 9), Some(4));
+}
+
+#[test]
+fn test_lumps_between() {
+    let lump = |name: &str, size: usize| (name.to_string(), vec![0; size]);
+
+    let lumps = vec![
+        lump("PLAYPAL", 768),
+        lump("S_START", 0),
+        lump("TROOA1", 16),
+        lump("S1_START", 0),
+        lump("POSSA1", 16),
+        lump("S1_END", 0),
+        lump("S_END", 0),
+        lump("P_START", 0),
+        lump("WALL00_1", 16),
+        lump("P_END", 0),
+    ];
+
+    let path = write_test_wad("doom_rs_lumps_between.wad", &lumps);
+    let wad = WAD::new(&path).unwrap();
+
+    let sprites = wad.lumps_between("S_START", "S_END");
+    let names = sprites.iter().map(|lump| lump.name.as_str()).collect::<Vec<_>>();
+
+    assert_eq!(names, vec!["TROOA1", "POSSA1"]);
+    assert_eq!((sprites[0].index, sprites[0].size), (2, 16));
+    assert_eq!(wad.lumps_between("P_START", "P_END")[0].name, "WALL00_1");
+    assert!(wad.lumps_between("F_START", "F_END").is_empty());
+}
+
+#[test]
+fn test_thing_sprite() {
+    assert_eq!(thing_sprite(3001), Some(("TROO", 'A')));
+    assert_eq!(thing_sprite(3004), Some(("POSS", 'A')));
+    assert_eq!(thing_sprite(20), Some(("TROO", 'M')));
+    assert_eq!(thing_sprite(14), None);
+
+    let lump = |name: &str| (name.to_string(), vec![0; 16]);
+
+    let lumps = vec![
+        lump("S_START"),
+        lump("TROOA2A8"),
+        lump("TROOA1"),
+        lump("CLIPA0"),
+        lump("S_END"),
+    ];
+
+    let path = write_test_wad("doom_rs_thing_sprite.wad", &lumps);
+    let wad = WAD::new(&path).unwrap();
+
+    assert_eq!(wad.thing_sprite_lump(3001).unwrap().name, "TROOA1");
+    assert_eq!(wad.thing_sprite_lump(2007).unwrap().name, "CLIPA0");
+    assert_eq!(wad.thing_sprite_lump(3004), None);
+}
+
+#[test]
+fn test_count_visible_segs() {
+    let map_data = test_step_map("doom_rs_count_visible_segs.wad", 24);
+    let bsp = BSP::new(&map_data);
+
+    // Facing west from the east half, the whole map is ahead.
+    let west = Player::new(Thing { x: 96, y: 64, angle: 180, t_type: 1, flags: 7 });
+    assert_eq!(bsp.count_visible_segs(&west, BSP::FOV), 2);
+
+    // Facing east from right next to the east wall, only their own half.
+    let east = Player::new(Thing { x: 120, y: 64, angle: 0, t_type: 1, flags: 7 });
+    let count = bsp.count_visible_segs(&east, BSP::FOV);
+
+    assert!(count > 0 && count <= map_data.segs.len());
+    assert_eq!(count, 1);
+    // A child pointing past NODES is skipped instead of panicking.
+    let mut broken = map_data.map.clone();
+    broken.nodes[0].back_child = 5;
+
+    let bsp = BSP::new(&broken);
+    assert_eq!(bsp.count_visible_segs(&west, BSP::FOV), 1);
+}
+
+#[test]
+fn test_sidedef_has_texture() {
+    let side = SideDef {
+        x_offset: 0,
+        y_offset: 0,
+        upper_texture: test_lump_name("STARTAN3"),
+        lower_texture: [0; 8],
+        middle_texture: test_lump_name("-"),
+        sector: 0,
+    };
+
+    assert!(side.has_upper());
+    assert!(!side.has_lower());
+    assert!(!side.has_middle());
+    assert_eq!(side.textures(), vec!["STARTAN3".to_string()]);
+}
+
+#[test]
+fn test_map_names_sorted() {
+    let mut lumps = Vec::new();
+
+    for name in ["MAP10", "E2M1", "MAP02", "E1M9", "E1M10", "MAP01"] {
+        lumps.extend(test_map_lumps(name));
+    }
+
+    let path = write_test_wad("doom_rs_map_names_sorted.wad", &lumps);
+    let wad = WAD::new(&path).unwrap();
+
+    assert_eq!(wad.map_names()[0], "MAP10");
+    assert_eq!(
+        wad.map_names_sorted(),
+        vec!["E1M9", "E1M10", "E2M1", "MAP01", "MAP02", "MAP10"]
+    );
+}
+
+#[test]
+fn test_minisegs() {
+    let map_data = test_step_map("doom_rs_minisegs.wad", 24);
+
+    let mut segs = map_data.segs.clone();
+    segs[1].linedef = -1;
+    segs[5].linedef = -1;
+
+    assert_eq!(MapDrawer::minisegs(&segs), vec![1, 5]);
+    assert_eq!(MapDrawer::seg_color(&segs[1]), Color::MAGENTA);
+    assert_eq!(MapDrawer::seg_color(&segs[0]), Color::rgb(110, 110, 110));
+}
+
+#[test]
+fn test_lump_name_stops_at_nul() {
+    assert_eq!(WAD::lump_name(b"FOO\0BAR\0"), "FOO");
+    assert_eq!(WAD::lump_name(b"startan3"), "STARTAN3");
+    assert_eq!(WAD::lump_name(b"\0\0\0\0\0\0\0\0"), "");
+    assert_eq!(WAD::slice_to_string(b"FOO\0BAR"), "FOOBAR");
+
+    let dir = Directory { offset: 0, size: 0, name: *b"E1M1\0M\0\0" };
+    assert_eq!(dir.name(), "E1M1");
+}
+
+#[test]
+fn test_sector_centroid() {
+    let mut map_data = test_room_map("doom_rs_sector_centroid.wad");
+
+    let (x, y) = map_data.sector_centroid(0).unwrap();
+    assert!((x - 64.0).abs() < 0.01 && (y - 64.0).abs() < 0.01);
+    assert_eq!(map_data.sector_centroid(1), None);
+
+    // Only the south-east triangle of the room has segs enough for an area.
+    map_data.ssectors = vec![
+        SubSector { num_segs: 2, first_seg: 0 },
+        SubSector { num_segs: 1, first_seg: 2 },
+    ];
+
+    let (x, y) = map_data.sector_centroid(0).unwrap();
+    assert!((x - 256.0 / 3.0).abs() < 0.01 && (y - 128.0 / 3.0).abs() < 0.01);
+
+    // No subsector with an area at all, the line ends are averaged.
+    map_data.ssectors = vec![SubSector { num_segs: 1, first_seg: 0 }];
+    assert_eq!(map_data.sector_centroid(0), Some((64.0, 64.0)));
+}
+
+#[test]
+fn test_sector_tags() {
+    let mut map_data = test_step_map("doom_rs_sector_tags.wad", 24);
+
+    let mut third = map_data.sectors[0];
+    third.tag = 3;
+    map_data.sectors.push(third);
+
+    map_data.sectors[1].tag = 3;
+    map_data.line_defs[4].sector_tag = 3;
+
+    // The west sector is untagged, the east and the third share tag 3.
+    assert_eq!(map_data.sectors_by_tag(), BTreeMap::from([(3, vec![1, 2])]));
+    assert_eq!(map_data.linedefs_with_tag(3), vec![4]);
+    assert!(map_data.linedefs_with_tag(0).is_empty());
+
+    let mut drawer = MapDrawer::new(&map_data, 200.0, 100.0);
+    drawer.view_position = (64.0, 64.0);
+    drawer.view_angle = 30.0;
+
+    // The hovered sector is looked up through the inverse projection.
+    for mode in [AutomapMode::Fixed, AutomapMode::Follow, AutomapMode::Rotate] {
+        drawer.automap_mode = mode;
+
+        let (x, y) = drawer.to_screen(96.0, 32.0);
+        let (x, y) = drawer.from_screen(x, y);
+        assert!((x - 96.0).abs() < 0.01 && (y - 32.0).abs() < 0.01);
+    }
+}
+
+#[test]
+fn test_viewer_config() {
+    let config = ViewerConfig::default();
+
+    assert!(!config.style().contains(Style::RESIZE));
+    assert_eq!(config.context_settings().antialiasing_level, 0);
+
+    let config = ViewerConfig { antialiasing: 8, resizable: true, ..config };
+
+    assert!(config.style().contains(Style::CLOSE | Style::RESIZE));
+    assert_eq!(config.context_settings().antialiasing_level, 8);
+}
+
+#[test]
+fn test_handle_resize() {
+    let map_data = test_room_map("doom_rs_handle_resize.wad");
+
+    let config = ViewerConfig { width: 320.0, height: 200.0, ..Default::default() };
+    let mut viewer = MapViewer::new(config, &map_data);
+    let margin = viewer.margin;
+
+    viewer.handle_resize(640.0, 480.0);
+    assert_eq!((viewer.w_width, viewer.w_height), (640.0, 480.0));
+
+    // The room's corners still land on the margin of the bigger window, both
+    // for the viewer's vertexes and the frame's drawer.
+    let drawer = viewer.drawer();
+    assert_eq!((drawer.width, drawer.height), (640.0, 480.0));
+
+    for (vertex, (corner_x, corner_y)) in [(0, (0.0, 0.0)), (2, (128.0, 128.0))] {
+        let (x, y) = drawer.to_screen(corner_x, corner_y);
+        assert_eq!(viewer.map_vertexes[vertex], Vector2f::new(x, y));
+    }
+
+    let (x, y) = drawer.to_screen(0.0, 0.0);
+    assert!((x - margin).abs() < 0.01 && (y - (480.0 - margin)).abs() < 0.01);
+
+    let (x, y) = drawer.to_screen(128.0, 128.0);
+    assert!((x - (640.0 - margin)).abs() < 0.01 && (y - margin).abs() < 0.01);
+}
+
+#[test]
+fn test_resize_event() {
+    let map_data = test_room_map("doom_rs_resize_event.wad");
+
+    let config =
+        ViewerConfig { width: 320.0, height: 200.0, resizable: true, ..Default::default() };
+    let mut viewer = MapViewer::new(config, &map_data);
+    let before = viewer.map_vertexes.clone();
+
+    // Resizing doesn't stop the viewer.
+    assert_eq!(viewer.handle_event(Event::Resized { width: 640, height: 480 }), None);
+
+    assert_eq!(viewer.window.view().size(), Vector2f::new(640.0, 480.0));
+    assert_eq!((viewer.w_width, viewer.w_height), (640.0, 480.0));
+    assert_eq!((viewer.render_width, viewer.render_height), (640.0, 480.0));
+
+    // The map is fitted to the new size, the top right corner moves over.
+    let margin = viewer.margin;
+    let corner = viewer.map_vertexes[2];
+    assert_ne!(viewer.map_vertexes, before);
+    assert!((corner.x - (640.0 - margin)).abs() < 0.01 && (corner.y - margin).abs() < 0.01);
+
+    assert_eq!(viewer.handle_event(Event::Closed), Some(None));
+}
+
+#[test]
+fn test_cycle_map_name() {
+    let names = ["E1M1", "E1M2", "E1M3"].map(String::from);
+
+    assert_eq!(cycle_map_name(&names, Some("E1M1"), 1).as_deref(), Some("E1M2"));
+    assert_eq!(cycle_map_name(&names, Some("E1M2"), -1).as_deref(), Some("E1M1"));
+
+    // Both ends wrap around.
+    assert_eq!(cycle_map_name(&names, Some("E1M3"), 1).as_deref(), Some("E1M1"));
+    assert_eq!(cycle_map_name(&names, Some("E1M1"), -1).as_deref(), Some("E1M3"));
+
+    assert_eq!(cycle_map_name(&names, None, 1).as_deref(), Some("E1M1"));
+    assert_eq!(cycle_map_name(&names, Some("MAP01"), -1).as_deref(), Some("E1M1"));
+    assert_eq!(cycle_map_name(&[], Some("E1M1"), 1), None);
+}
+
+#[test]
+fn test_map_snapshot() {
+    let map_data = test_step_map("doom_rs_map_snapshot.wad", 24);
+
+    let snapshot = map_data.map_snapshot();
+    let restored = Map::from_snapshot(&snapshot).unwrap();
+
+    assert_eq!(restored.map_snapshot(), snapshot);
+    assert_eq!(restored.map_hash(), map_data.map_hash());
+    assert_eq!(restored.sectors.len(), 2);
+
+    // The first count is the things'.
+    assert_eq!(snapshot[..4], (map_data.things.len() as u32).to_le_bytes());
+
+    // Indexes past the vanilla 16 bits survive.
+    let mut wide = map_data.map.clone();
+    wide.segs[0].start_vertex = 0x1_0000;
+    wide.nodes[0].back_child = Map::SUB_SECTOR_FLAG | 0x1_0000;
+
+    let restored = Map::from_snapshot(&wide.map_snapshot()).unwrap();
+    assert_eq!(restored.segs[0].start_vertex, 0x1_0000);
+    assert_eq!(restored.nodes[0].back_child, Map::SUB_SECTOR_FLAG | 0x1_0000);
+
+    assert!(matches!(
+        Map::from_snapshot(&snapshot[..snapshot.len() - 1]),
+        Err(WadError::TruncatedLump { .. })
+    ));
+
+    let mut trailing = snapshot;
+    trailing.push(0);
+    assert!(matches!(Map::from_snapshot(&trailing), Err(WadError::InvalidLump(_))));
+}
+
+#[test]
+fn test_things_in_rect() {
+    let mut map_data = test_room_map("doom_rs_things_in_rect.wad");
+
+    map_data.things.push(Thing { x: 100, y: 20, angle: 0, t_type: 3004, flags: 7 });
+    map_data.things.push(Thing { x: 20, y: 100, angle: 0, t_type: 2001, flags: 7 });
+
+    assert_eq!(map_data.things_in_rect((99, 19), (101, 21)), vec![1]);
+    assert_eq!(map_data.things_in_rect((64, 64), (64, 64)), vec![0]);
+    assert_eq!(map_data.things_in_rect((0, 0), (128, 128)), vec![0, 1, 2]);
+    assert!(map_data.things_in_rect((200, 200), (300, 300)).is_empty());
+}
+
+#[test]
+fn test_degenerate_linedefs() {
+    let mut map_data = test_room_map("doom_rs_degenerate_linedefs.wad");
+
+    assert!(map_data.degenerate_linedefs().is_empty());
+
+    // One line from a vertex to itself, one to a duplicate of it.
+    let vertex = map_data.vertexes[0];
+    map_data.vertexes.push(vertex);
+
+    let mut line = map_data.line_defs[0];
+    line.end_vertex = line.start_vertex;
+    map_data.line_defs.push(line);
+
+    line.end_vertex = 4;
+    map_data.line_defs.push(line);
+
+    assert_eq!(map_data.degenerate_linedefs(), vec![4, 5]);
+
+    let mut canvas = RecordingCanvas::default();
+    MapDrawer::new(&map_data, 320.0, 200.0).draw_linedefs(&mut canvas);
+
+    assert_eq!(canvas.calls.len(), 4);
+    assert!(canvas.calls.iter().all(|call| match call {
+        DrawCall::Line(x1, y1, x2, y2) => [x1, y1, x2, y2].iter().all(|v| v.is_finite()),
+        _ => false,
+    }));
+}
+
+#[test]
+fn test_record_layout() {
+    // Distinct values in both bytes, so swapped bytes or fields show up.
+    let le = |values: &[i16]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>();
+
+    let thing = parse_records::<Thing>(&le(&[0x0102, -2, 270, 3004, 0x0407])).unwrap()[0];
+    assert_eq!((thing.x, thing.y, thing.angle), (0x0102, -2, 270));
+    assert_eq!((thing.t_type, thing.flags), (3004, 0x0407));
+    assert_eq!(records_to_bytes(&[thing]), le(&[0x0102, -2, 270, 3004, 0x0407]));
+
+    let line = parse_records::<LineDef>(&le(&[1, 2, 0x0304, 97, 0x0506, 7, -1])).unwrap()[0];
+    assert_eq!((line.start_vertex, line.end_vertex, line.flags), (1, 2, 0x0304));
+    assert_eq!((line.special_type, line.sector_tag), (97, 0x0506));
+    assert_eq!((line.right_sidedef, line.left_sidedef), (7, -1));
+
+    let mut bytes = le(&[-16, 0x0108]);
+    bytes.extend_from_slice(b"STARTAN3BROWN1\0\0-\0\0\0\0\0\0\0");
+    bytes.extend(le(&[0x0203]));
+
+    let side = parse_records::<SideDef>(&bytes).unwrap()[0];
+    assert_eq!((side.x_offset, side.y_offset, side.sector), (-16, 0x0108, 0x0203));
+    assert_eq!(side.upper_texture(), "STARTAN3");
+    assert_eq!(side.lower_texture(), "BROWN1");
+    assert_eq!(side.middle_texture(), "-");
+    assert_eq!(records_to_bytes(&[side]), bytes);
+
+    let vertex = parse_records::<Vertex>(&le(&[-0x0102, 0x0304])).unwrap()[0];
+    assert_eq!((vertex.x, vertex.y), (-0x0102, 0x0304));
+
+    let seg = parse_records::<Seg>(&le(&[1, 2, -0x4000, 0x0304, 1, 0x0506])).unwrap()[0];
+    assert_eq!((seg.start_vertex, seg.end_vertex, seg.angle), (1, 2, -0x4000));
+    assert_eq!((seg.linedef, seg.direction, seg.offset), (0x0304, 1, 0x0506));
+
+    let sub_sector = parse_records::<SubSector>(&le(&[0x0102, 0x0304])).unwrap()[0];
+    assert_eq!((sub_sector.num_segs, sub_sector.first_seg), (0x0102, 0x0304));
+
+    let node_fields = [64, -64, 0x0102, 0, 128, 0, 0, 64, 128, 0, 64, 128, 0x0304, -0x8000];
+    let node = parse_records::<Node>(&le(&node_fields)).unwrap()[0];
+    assert_eq!((node.x_partition, node.y_partition), (64, -64));
+    assert_eq!((node.dx_partition, node.dy_partition), (0x0102, 0));
+    assert_eq!((node.front_bbox, node.back_bbox), ([128, 0, 0, 64], [128, 0, 64, 128]));
+    assert_eq!((node.front_child, node.back_child), (0x0304, Map::SUB_SECTOR_FLAG));
+    assert_eq!(records_to_bytes(&[node]), le(&node_fields));
+
+    let mut bytes = le(&[-24, 0x0180]);
+    bytes.extend_from_slice(b"FLOOR4_8F_SKY1\0\0");
+    bytes.extend(le(&[160, 9, 0x0203]));
+
+    let sector = parse_records::<Sector>(&bytes).unwrap()[0];
+    assert_eq!((sector.floor_height, sector.ceiling_height), (-24, 0x0180));
+    assert_eq!(sector.floor_texture(), "FLOOR4_8");
+    assert_eq!(sector.ceiling_texture(), "F_SKY1");
+    assert_eq!((sector.light_level, sector.special_type, sector.tag), (160, 9, 0x0203));
+    assert_eq!(records_to_bytes(&[sector]), bytes);
+
+    let mut bytes = b"PWAD".to_vec();
+    bytes.extend([0x02, 0x01, 0, 0, 0x04, 0x03, 0x02, 0x01]);
+
+    let header = parse_records::<Header>(&bytes).unwrap()[0];
+    assert_eq!(&header.identifier, b"PWAD");
+    assert_eq!((header.count, header.offset), (0x0102, 0x0102_0304));
+    assert_eq!(records_to_bytes(&[header]), bytes);
+
+    let mut bytes = vec![0x0c, 0, 0, 0, 0x08, 0x07, 0, 0];
+    bytes.extend_from_slice(b"E1M1\0\0\0\0");
+
+    let entry = parse_records::<Directory>(&bytes).unwrap()[0];
+    assert_eq!((entry.offset, entry.size, &entry.name), (12, 0x0708, b"E1M1\0\0\0\0"));
+
+    // A trailing partial record is dropped.
+    assert_eq!(parse_records::<Vertex>(&le(&[1, 2, 3])).unwrap().len(), 1);
+}
+
+#[test]
+fn test_into_memory() {
+    let path = write_test_wad("doom_rs_into_memory.wad", &test_map_lumps("MAP01"));
+
+    let mut map_data = WAD::new(&path).unwrap().into_memory().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    map_data.change_map("MAP01").unwrap();
+    assert_eq!(map_data.line_defs.len(), 4);
+    assert_eq!(map_data.map_lump(MapLumpIndex::Reject).unwrap(), vec![0]);
+
+    // Still re-reads, from memory.
+    map_data.reload().unwrap();
+    assert_eq!(map_data.current_map_name().as_deref(), Some("MAP01"));
+}
+
+#[test]
+fn test_frame_stats() {
+    let map_data = test_step_map("doom_rs_frame_stats.wad", 24);
+    let drawer = MapDrawer::new(&map_data, 320.0, 200.0);
+
+    let start = Instant::now();
+    let mut stats = FrameStats::default();
+
+    stats.begin_frame(start);
+    assert_eq!((stats.segs_drawn, stats.sub_sectors_visited), (0, 0));
+    assert_eq!(stats.fps(), 0.0);
+
+    let mut canvas = RecordingCanvas::default();
+
+    for sub_sector_id in 0..map_data.ssectors.len() {
+        drawer.draw_sub_sector_segs(&mut canvas, sub_sector_id, false, &mut stats);
+    }
+
+    assert_eq!(stats.segs_drawn, canvas.calls.len());
+    assert_eq!(stats.sub_sectors_visited, 2);
+
+    // The next frame starts from zero again.
+    stats.begin_frame(start + Duration::from_millis(25));
+    assert_eq!((stats.segs_drawn, stats.sub_sectors_visited), (0, 0));
+    assert!((stats.fps() - 40.0).abs() < 0.01);
+}
+
+#[test]
+fn test_orphan_sidedefs() {
+    let mut map_data = test_step_map("doom_rs_orphan_sidedefs.wad", 24);
+
+    assert!(map_data.orphan_sidedefs().is_empty());
+
+    let side = map_data.side_defs[0];
+    map_data.side_defs.push(side);
+
+    // Dropping the step line's back side leaves that one unused too.
+    map_data.line_defs[4].left_sidedef = -1;
+
+    assert_eq!(map_data.orphan_sidedefs(), vec![5, 6]);
+}
+
+#[test]
+fn test_render_to_texture() {
+    let map_data = test_room_map("doom_rs_render_to_texture.wad");
+
+    // Headless machines may have no GL context to render offscreen with.
+    if let Some(texture) = MapDrawer::new(&map_data, 160.0, 100.0).render_to_texture() {
+        assert_eq!((texture.size().x, texture.size().y), (160, 100));
+    }
+}
+
+#[test]
+fn test_map_bounds() {
+    let mut map_data = test_step_map("doom_rs_map_bounds.wad", 24);
+
+    assert_eq!(map_data.map_bounds(), Some((0, 0, 128, 128)));
+
+    map_data.vertexes.push(Vertex { x: -32, y: 256 });
+    assert_eq!(map_data.map_bounds(), Some((-32, 0, 128, 256)));
+
+    assert_eq!(Map::default().map_bounds(), None);
+}
+
+#[test]
+fn test_generalized_special() {
+    let line = |special_type: u16| LineDef {
+        start_vertex: 0,
+        end_vertex: 1,
+        flags: 0,
+        special_type: special_type as i16,
+        sector_tag: 0,
+        right_sidedef: 0,
+        left_sidedef: -1,
+    };
+
+    // SR, fast, open-wait-close, monsters allowed, waits 4 seconds.
+    let door = line(0x3C00 | 0x3 | 2 << 3 | 1 << 7 | 1 << 8).generalized().unwrap();
+
+    assert_eq!(door.kind, GeneralizedKind::Door);
+    assert_eq!(door.trigger, GeneralizedTrigger::SR);
+    assert_eq!(door.speed, GeneralizedSpeed::Fast);
+    assert_eq!(door.delay, Some(4));
+    assert!(door.monsters);
+    assert_eq!(door.flags & 3, 0);
+
+    // W1 slow lift waiting 10 seconds.
+    let lift = line(0x3400 | 3 << 6).generalized().unwrap();
+    assert_eq!((lift.kind, lift.trigger), (GeneralizedKind::Lift, GeneralizedTrigger::W1));
+    assert_eq!(lift.speed, GeneralizedSpeed::Slow);
+    assert_eq!((lift.delay, lift.monsters), (Some(10), false));
+
+    assert_eq!(line(0x7FFF).generalized().unwrap().kind, GeneralizedKind::Floor);
+    assert_eq!(line(0x2F80).generalized().unwrap().kind, GeneralizedKind::Crusher);
+
+    // Vanilla specials aren't generalized.
+    assert_eq!(line(1).generalized(), None);
+    assert_eq!(line(0x2F7F).generalized(), None);
+}
+
+#[test]
+fn test_tag_lookup() {
+    let mut map_data = test_step_map("doom_rs_tag_lookup.wad", 24);
+
+    let sector = map_data.sectors[0];
+    map_data.sectors.push(sector);
+
+    // A switch on the step line acting on the west and the third sector.
+    map_data.line_defs[4].sector_tag = 7;
+    map_data.sectors[0].tag = 7;
+    map_data.sectors[2].tag = 7;
+
+    assert_eq!(map_data.linedefs_with_tag(7), vec![4]);
+    assert_eq!(map_data.sectors_with_tag(7), vec![0, 2]);
+
+    assert!(map_data.linedefs_with_tag(8).is_empty());
+    assert!(map_data.sectors_with_tag(0).is_empty());
+}
+
+#[test]
+fn test_line_colors() {
+    let mut map_data = test_step_map("doom_rs_line_colors.wad", 24);
+
+    map_data.line_defs[1].flags |= LineDefFlags::Secret as i16;
+    map_data.line_defs[2].special_type = 1;
+
+    let kinds = map_data.line_defs.iter().map(LineDef::automap_kind).collect::<Vec<_>>();
+    assert_eq!(kinds, vec![
+        LineKind::OneSided,
+        LineKind::Secret,
+        LineKind::Action,
+        LineKind::OneSided,
+        LineKind::TwoSided,
+    ]);
+
+    let colors = LineColors::default();
+
+    assert_eq!(colors.color(LineKind::OneSided), Color::WHITE);
+    assert_eq!(colors.color(LineKind::TwoSided), Color::rgb(191, 123, 75));
+    assert_eq!(colors.color(LineKind::Secret), colors.color(LineKind::OneSided));
+    assert_eq!(colors.color(LineKind::Action), Color::CYAN);
+
+    let colors = LineColors { action: Color::RED, ..colors };
+    assert_eq!(colors.color(LineKind::Action), Color::RED);
+}
+
+#[test]
+fn test_truncated_lump() {
+    let lumps = [("COLORMAP", vec![0; 32]), ("DEMO1", vec![0; 64])]
+        .map(|(name, data)| (name.to_string(), data));
+    let mut bytes = test_wad_bytes(&lumps);
+
+    // DEMO1 claims more bytes than the data and directory after it hold.
+    let directory = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+    let entry = directory + WAD::DIRECTORY_SIZE;
+    bytes[entry + 4..entry + 8].copy_from_slice(&4096u32.to_le_bytes());
+
+    let got = bytes.len() - (WAD::HEADER_SIZE + 32);
+    let mut wad = WAD::from_reader(io::Cursor::new(bytes)).unwrap();
+
+    assert_eq!(wad.read_lump_bytes("COLORMAP").unwrap().len(), 32);
+
+    match wad.read_lump_bytes("DEMO1") {
+        Err(WadError::TruncatedLump { name, expected, got: read }) => {
+            assert_eq!((name.as_str(), expected, read), ("DEMO1", 4096, got));
+        }
+        result => panic!("expected a truncated DEMO1, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_set_view_thing() {
+    let mut map_data = test_step_map("doom_rs_set_view_thing.wad", 24);
+
+    // A deathmatch start in the raised east half.
+    map_data.things.push(Thing { x: 96, y: 32, angle: 180, t_type: 11, flags: 7 });
+    map_data.things.push(Thing { x: 32, y: 96, angle: 0, t_type: 2, flags: 7 });
+
+    assert_eq!(map_data.player_start(2).map(|thing| (thing.x, thing.y)), Some((32, 96)));
+    assert!(map_data.player_start(3).is_none());
+
+    let dims = PlayerDims { radius: 8.0, ..PlayerDims::default() };
+    let player = Player::with_dims(map_data.player_start(1).unwrap(), dims);
+
+    let mut engine = Engine::new(&map_data, player);
+    engine.set_view_thing(map_data.things[1]);
+
+    assert_eq!(engine.player.position, (96.0, 32.0));
+    assert_eq!(engine.player.angle, 180.0);
+    assert_eq!(engine.player.view_z, 24.0 + dims.view_height);
+    assert_eq!(engine.player.dims.radius, 8.0);
+}
+
+#[test]
+fn test_walls() {
+    let mut map_data = test_step_map("doom_rs_walls.wad", 24);
+    map_data.line_defs[3].right_sidedef = 9999;
+
+    let walls = map_data.walls().collect::<Vec<_>>();
+    assert_eq!(walls.len(), map_data.line_defs.len());
+
+    // The room's walls are one-sided.
+    assert!(walls[..3].iter().all(|wall| wall.front.is_some() && wall.back.is_none()));
+    assert!(walls[3].front.is_none());
+
+    let step = walls[4];
+    assert_eq!(step.linedef.left_sidedef, 5);
+    assert_eq!((step.front.unwrap().sector, step.back.unwrap().sector), (0, 1));
+}
+
+#[test]
+fn test_effective_light() {
+    let mut map_data = test_step_map("doom_rs_effective_light.wad", 24);
+
+    map_data.sectors[0].light_level = 96;
+    map_data.sectors[1].light_level = 192;
+    map_data.sectors[1].special_type = 2;
+
+    assert_eq!(map_data.neighbor_sectors(1), vec![0]);
+
+    let at = |tic: u64| Duration::from_secs_f64((tic as f64 + 0.5) / Engine::TIC_RATE as f64);
+
+    // Bright for 5 tics, then down to the west sector's level for 15.
+    let levels = (0..40).map(|tic| map_data.effective_light(1, at(tic))).collect::<Vec<_>>();
+
+    assert!(levels[..5].iter().all(|&level| level == 192));
+    assert!(levels[5..20].iter().all(|&level| level == 96));
+    assert_eq!(levels[20..], levels[..20]);
+
+    // The plain sector doesn't change.
+    assert!((0..40).all(|tic| map_data.effective_light(0, at(tic)) == 96));
+
+    // Glowing goes down to the neighbor's level and back.
+    map_data.sectors[1].special_type = 8;
+
+    assert_eq!(map_data.effective_light(1, at(0)), 192);
+    assert_eq!(map_data.effective_light(1, at(12)), 96);
+    assert_eq!(map_data.effective_light(1, at(24)), 192);
+
+    // Flashing goes down to the neighbor's level too, but next to a sector as
+    // bright it stays lit where a strobe would go dark.
+    map_data.sectors[1].special_type = 1;
+    assert_eq!(map_data.effective_light(1, at(0)), 192);
+    assert_eq!(map_data.effective_light(1, at(64)), 96);
+
+    map_data.sectors[0].light_level = 192;
+    assert_eq!(map_data.effective_light(1, at(64)), 192);
+
+    map_data.sectors[1].special_type = 2;
+    assert_eq!(map_data.effective_light(1, at(5)), 0);
+}
+
+#[test]
+fn test_export_things_csv() {
+    let mut map_data = test_step_map("doom_rs_things_csv.wad", 24);
+    map_data.things.push(Thing { x: 96, y: -32, angle: 180, t_type: 3001, flags: 12 });
+    map_data.things.push(Thing { x: 0, y: 0, angle: 0, t_type: 9999, flags: 7 });
+
+    let path = std::env::temp_dir().join("doom_rs_things.csv");
+    map_data.export_things_csv(path.to_str().unwrap()).unwrap();
+
+    let csv = fs::read_to_string(&path).unwrap();
+    let rows = csv.lines().collect::<Vec<_>>();
+
+    assert_eq!(rows.len(), 1 + map_data.things.len());
+    assert_eq!(rows[0], "x,y,angle,type,type_name,category,flags");
+    assert_eq!(rows[1], "64,64,90,1,Player 1 start,PlayerStart,7");
+    assert_eq!(rows[2], "96,-32,180,3001,Imp,Monster,12");
+    assert_eq!(rows[3], "0,0,0,9999,,Other,7");
+}
+
+#[test]
+fn test_color_by_sector() {
+    let mut map_data = test_step_map("doom_rs_color_by_sector.wad", 24);
+
+    // A second subsector on the west sector's side of the step line.
+    map_data.ssectors.push(SubSector { num_segs: 1, first_seg: 4 });
+
+    let mut drawer = MapDrawer::new(&map_data, 320.0, 200.0);
+    assert_eq!(drawer.color_mode, ColorMode::BySubsector);
+    assert_ne!(drawer.sub_sector_fill(0), drawer.sub_sector_fill(2));
+
+    drawer.color_mode = ColorMode::BySector;
+    assert_eq!(map_data.subsector_sector(0), map_data.subsector_sector(2));
+    assert_ne!(map_data.subsector_sector(0), map_data.subsector_sector(1));
+
+    assert_eq!(drawer.sub_sector_fill(0), drawer.sub_sector_fill(2));
+    assert_eq!(drawer.sub_sector_fill(0), MapDrawer::sector_color(0));
+    assert_ne!(drawer.sub_sector_fill(0), drawer.sub_sector_fill(1));
+
+    // FNV-1a of the id, the same whichever Rust built it.
+    assert_eq!(MapDrawer::sector_color(0), Color::rgb(70, 121, 90));
+    assert_eq!(MapDrawer::sector_color(1), Color::rgb(228, 112, 106));
+}
+
+#[test]
+fn test_genmidi() {
+    let mut lump = GenMidiInstrument::HEADER.to_vec();
+
+    for i in 0..GenMidiInstrument::COUNT {
+        let mut record = vec![0; GenMidiInstrument::SIZE];
+        record[0] = if i == 128 { 0x01 } else { 0x04 };
+        record[3] = 60;
+        // First voice: modulator attack, feedback, carrier level, note offset.
+        record[5] = 0xF1;
+        record[10] = 0x0E;
+        record[16] = 0x3F;
+        record[18..20].copy_from_slice(&(-12i16).to_le_bytes());
+
+        lump.extend(record);
+    }
+
+    for i in 0..GenMidiInstrument::COUNT {
+        let mut name = format!("Instrument {}", i).into_bytes();
+        name.resize(32, 0);
+
+        lump.extend(name);
+    }
+
+    let path = write_test_wad("doom_rs_genmidi.wad", &[("GENMIDI".to_string(), lump.clone())]);
+    let instruments = WAD::new(&path).unwrap().genmidi().unwrap();
+
+    assert_eq!(instruments.len(), 175);
+
+    let piano = &instruments[0];
+    assert!(piano.is_double_voice() && !piano.is_fixed_pitch());
+    assert_eq!(piano.fixed_note, 60);
+    assert_eq!(piano.voices[0].modulator.attack, 0xF1);
+    assert_eq!(piano.voices[0].feedback, 0x0E);
+    assert_eq!(piano.voices[0].carrier.level, 0x3F);
+    assert_eq!(piano.voices[0].base_note_offset, -12);
+    assert_eq!(piano.voices[1], GenMidiVoice::default());
+    assert_eq!(piano.name, "Instrument 0");
+    assert_eq!(records_to_bytes(&instruments[..1]), lump[8..8 + GenMidiInstrument::SIZE]);
+
+    assert!(instruments[128].is_fixed_pitch());
+
+    // Anything but the header is rejected.
+    lump[0] = b'!';
+    let path = write_test_wad("doom_rs_genmidi_bad.wad", &[("GENMIDI".to_string(), lump)]);
+    assert!(matches!(WAD::new(&path).unwrap().genmidi(), Err(WadError::InvalidLump(_))));
+}
+
+#[test]
+fn test_lump_record_size() {
+    let record_sizes = MapLumpIndex::ALL.map(|index| index.record_size());
+    assert_eq!(record_sizes, [10, 14, 30, 4, 12, 4, 28, 26, 1, 2]);
+
+    let mut lumps = test_map_lumps("MAP01");
+    let line_defs = lumps.iter_mut().find(|(name, _)| name == "LINEDEFS").unwrap();
+    line_defs.1.truncate(15);
+
+    let path = write_test_wad("doom_rs_lump_record_size.wad", &lumps);
+    let mut wad = WAD::new(&path).unwrap();
+
+    match wad.change_map("MAP01") {
+        Err(WadError::TruncatedLump { name, expected, got }) => {
+            assert_eq!((name.as_str(), expected, got), ("LINEDEFS", 28, 15));
+        }
+        result => panic!("expected a truncated LINEDEFS, got {:?}", result),
+    }
+
+    // Vanilla NODES are checked the same way.
+    let mut lumps = test_map_lumps("MAP01");
+    lumps.iter_mut().find(|(name, _)| name == "NODES").unwrap().1 = vec![0; 30];
+
+    let path = write_test_wad("doom_rs_lump_record_size_nodes.wad", &lumps);
+    let mut wad = WAD::new(&path).unwrap();
+
+    match wad.change_map("MAP01") {
+        Err(WadError::TruncatedLump { name, expected, got }) => {
+            assert_eq!((name.as_str(), expected, got), ("NODES", 56, 30));
+        }
+        result => panic!("expected a truncated NODES, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_angle_to_point() {
+    let mut player = Player::new(Thing { x: 64, y: 64, angle: 90, t_type: 1, flags: 7 });
+
+    assert!(player.angle_to_point(64.0, 200.0).abs() < 1e-4);
+    assert!((player.angle_to_point(0.0, 64.0) - 90.0).abs() < 1e-4);
+    assert!((player.angle_to_point(128.0, 64.0) + 90.0).abs() < 1e-4);
+    assert!((player.angle_to_point(64.0, 0.0) - 180.0).abs() < 1e-4);
+
+    // Both sides of east, across the 0/360 seam.
+    player.angle = 350.0;
+    assert!((player.angle_to_point(164.0, 64.0) - 10.0).abs() < 1e-4);
+
+    player.angle = 10.0;
+    assert!((player.angle_to_point(164.0, 64.0) + 10.0).abs() < 1e-4);
+}
+
+// `test_step_map` with `vertexes` added, and a seg from the first to the
+// second of each pair, indexing into `vertexes`.
+#[cfg(test)]
+fn test_view_segs(file_name: &str, vertexes: &[Vertex], pairs: &[(u32, u32)]) -> (WAD, Vec<Seg>) {
+    let mut map_data = test_step_map(file_name, 24);
+
+    let first = map_data.vertexes.len() as u32;
+    map_data.vertexes.extend_from_slice(vertexes);
+
+    let segs = pairs
+        .iter()
+        .map(|&(start, end)| Seg {
+            start_vertex: first + start,
+            end_vertex: first + end,
+            angle: 0,
+            linedef: 0,
+            direction: 0,
+            offset: 0,
+        })
+        .collect();
+
+    (map_data, segs)
+}
+
+// At the origin, facing east.
+#[cfg(test)]
+fn test_origin_engine(map_data: &Map) -> Engine<'_> {
+    Engine::new(map_data, Player::new(Thing { x: 0, y: 0, angle: 0, t_type: 1, flags: 7 }))
+}
+
+#[test]
+fn test_project_seg() {
+    let vertexes = [
+        Vertex { x: 100, y: 100 },
+        Vertex { x: 100, y: -100 },
+        Vertex { x: -50, y: 10 },
+        Vertex { x: -50, y: -10 },
+        Vertex { x: 50, y: 5 },
+        Vertex { x: -50, y: 5 },
+    ];
+
+    let pairs = [(0, 1), (1, 0), (2, 3), (4, 5)];
+    let (map_data, segs) = test_view_segs("doom_rs_project_seg.wad", &vertexes, &pairs);
+    let engine = test_origin_engine(&map_data);
+
+    assert_eq!(engine.project_seg(&segs[0], 320.0), Some((0, 320)));
+    assert_eq!(engine.project_seg(&segs[1], 320.0), Some((0, 320)));
+    assert_eq!(engine.project_seg(&segs[2], 320.0), None);
+
+    // Passing by the player's left, clipped to the edge of the screen.
+    let (left, right) = engine.project_seg(&segs[3], 320.0).unwrap();
+    assert_eq!(left, 0);
+    assert!(right > 0 && right < 160);
+}
+
+#[test]
+fn test_project_seg_near_clip() {
+    let vertexes = [Vertex { x: 200, y: 50 }, Vertex { x: -200, y: -150 }];
+    let (map_data, segs) =
+        test_view_segs("doom_rs_project_seg_near_clip.wad", &vertexes, &[(0, 1)]);
+    let (engine, seg) = (test_origin_engine(&map_data), segs[0]);
+
+    // Unclipped, the end behind the player would land left of the center
+    // instead of off the right edge.
+    let (left, right) = engine.project_seg(&seg, 320.0).unwrap();
+    assert_eq!((left, right), (120, 320));
+
+    let (a, b) = Engine::clip_to_near_plane((200.0, 50.0), (-200.0, -150.0)).unwrap();
+    assert_eq!(a, (200.0, 50.0));
+    assert_eq!(b, (Engine::NEAR_PLANE, -49.5));
+    assert_eq!(Engine::clip_to_near_plane((0.5, 3.0), (-1.0, 3.0)), None);
+}
+
+#[test]
+fn test_render_size() {
+    let config = ViewerConfig::default();
+    assert_eq!(config.render_size(), (1280.0, 800.0));
+
+    let config = ViewerConfig { render_width: Some(320.0), render_height: Some(200.0), ..config };
+    assert_eq!(config.render_size(), (320.0, 200.0));
+
+    let vertexes = [Vertex { x: 100, y: 50 }, Vertex { x: 100, y: -20 }];
+    let (map_data, segs) = test_view_segs("doom_rs_render_size.wad", &vertexes, &[(0, 1)]);
+
+    let mut viewer = MapViewer::new(config, &map_data);
+    viewer.engine = test_origin_engine(&map_data);
+
+    // The BSP view is drawn at the render size, the automap at the window's.
+    let view = viewer.view_drawer();
+    assert_eq!((view.width, view.height), (320.0, 200.0));
+    assert_eq!((viewer.drawer().width, viewer.drawer().height), (1280.0, 800.0));
+
+    // Columns follow the render width, whatever the window's is.
+    assert_eq!(viewer.project_seg(&segs[0]), Some((80, 192)));
+
+    // A resize keeps the render size of its own.
+    viewer.handle_resize(640.0, 400.0);
+    assert_eq!(viewer.project_seg(&segs[0]), Some((80, 192)));
+
+    // Back at the window's size, the columns span the window.
+    viewer.set_render_size(640.0, 400.0);
+    assert_eq!((viewer.render_width, viewer.render_height), (640.0, 400.0));
+    assert_eq!(viewer.project_seg(&segs[0]), Some((80 * 2, 192 * 2)));
+}
+
+#[test]
+fn test_doors() {
+    let mut map_data = test_step_map("doom_rs_doors.wad", 0);
+
+    // The east half as a shut door, opened from the west.
+    map_data.sectors[1].ceiling_height = 0;
+    map_data.line_defs[4].special_type = 1;
+    map_data.line_defs[0].special_type = 11;
+
+    assert_eq!(map_data.doors(), vec![4]);
+    assert!(map_data.door(0).is_none());
+
+    let door = map_data.door(4).unwrap();
+    assert_eq!(door.linedef, 4);
+    assert_eq!(door.closed.height(), 0);
+    assert_eq!((door.open.bottom, door.open.top), (0, 124));
+
+    // A blazing door, and one-sided lines can't be doors.
+    map_data.line_defs[1].special_type = 117;
+    assert_eq!(map_data.doors(), vec![1, 4]);
+    assert!(map_data.door(1).is_none());
+}
+
+#[test]
+fn test_line_thickness() {
+    let map_data = test_step_map("doom_rs_line_thickness.wad", 24);
+
+    let mut drawer = MapDrawer::new(&map_data, 320.0, 200.0);
+    assert_eq!(drawer.line_thickness(), 1.0);
+
+    drawer.line_width = 1.5;
+    drawer.zoom = 4.0;
+    assert_eq!(drawer.line_thickness(), 4.0 * 1.5);
+
+    // Points move away from the middle of the screen by the same factor.
+    let unzoomed = MapDrawer::new(&map_data, 320.0, 200.0).to_screen(0.0, 0.0);
+    let (x, y) = drawer.to_screen(0.0, 0.0);
+    assert!((x - (160.0 + (unzoomed.0 - 160.0) * 4.0)).abs() < 0.01);
+    assert!((y - (100.0 + (unzoomed.1 - 100.0) * 4.0)).abs() < 0.01);
+
+    let (map_x, map_y) = drawer.from_screen(x, y);
+    assert!(map_x.abs() < 0.01 && map_y.abs() < 0.01);
+}
+
+#[test]
+fn test_export_flip_y() {
+    let mut map_data = test_step_map("doom_rs_export_flip_y.wad", 24);
+    map_data.things[0] = Thing { x: 32, y: 96, angle: 0, t_type: 1, flags: 7 };
+
+    let vertexes = |options| {
+        map_data
+            .export_obj(false, options)
+            .lines()
+            .filter_map(|line| line.strip_prefix("v "))
+            .map(|line| line.split(' ').map(|n| n.parse::<f32>().unwrap()).collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+    };
+
+    // The north end of the step line, at its floor.
+    assert!(vertexes(ObjOptions::default()).contains(&vec![64.0, 128.0, 0.0]));
+
+    let flipped = vertexes(ObjOptions { flip_y: true, ..Default::default() });
+    assert!(flipped.contains(&vec![64.0, -128.0, 0.0]));
+
+    let raw = map_data.export_svg(SvgOptions { flip_y: false, ..Default::default() });
+    assert!(raw.contains("viewBox=\"0 0 128 128\""));
+    assert!(raw.contains("<circle cx=\"32\" cy=\"96\""));
+
+    let north_up = map_data.export_svg(SvgOptions::default());
+    assert!(north_up.contains("viewBox=\"0 -128 128 128\""));
+    assert!(north_up.contains("<circle cx=\"32\" cy=\"-96\""));
+
+    let bounds = MapBounds { min_x: 0.0, max_x: 10.0, min_y: -5.0, max_y: 20.0 };
+    assert_eq!(bounds.oriented(false), bounds);
+    assert_eq!((bounds.oriented(true).min_y, bounds.oriented(true).max_y), (-20.0, 5.0));
+}
+
+#[test]
+fn test_texture_cache() {
+    let mut playpal = vec![0; Palette::SIZE];
+    playpal[21..24].copy_from_slice(&[10, 20, 30]);
+
+    // A 1x1 patch of color 7.
+    let mut patch = Vec::new();
+    for value in [1u16, 1, 0, 0] {
+        patch.extend(value.to_le_bytes());
+    }
+    patch.extend(12u32.to_le_bytes());
+    patch.extend([0, 1, 0, 7, 0, 0xFF]);
+
+    // STARTAN3 is 3x1 with the patch at x 1, and again off its right edge.
+    let mut pnames = 1i32.to_le_bytes().to_vec();
+    pnames.extend(test_lump_name("STIMA0"));
+
+    let mut texture1 = Vec::new();
+    for value in [1i32, 8] {
+        texture1.extend(value.to_le_bytes());
+    }
+    texture1.extend(test_lump_name("STARTAN3"));
+    texture1.extend(0i32.to_le_bytes());
+    for value in [3i16, 1, 0, 0, 2, 1, 0, 0, 1, 0, 3, 0, 0, 0, 1, 0] {
+        texture1.extend(value.to_le_bytes());
+    }
+
+    let lumps = vec![
+        ("PLAYPAL".to_string(), playpal),
+        ("PNAMES".to_string(), pnames),
+        ("TEXTURE1".to_string(), texture1),
+        ("STIMA0".to_string(), patch.clone()),
+        ("F_START".to_string(), Vec::new()),
+        ("FLOOR4_8".to_string(), vec![7; WAD::FLAT_SIZE]),
+        ("F_END".to_string(), Vec::new()),
+    ];
+
+    let path = write_test_wad("doom_rs_texture_cache.wad", &lumps);
+    let mut wad = WAD::new(&path).unwrap();
+    let mut cache = TextureCache::from_wad(&mut wad).unwrap();
+
+    for _ in 0..2 {
+        let stim = cache.get_or_decode(&mut wad, "STIMA0").unwrap();
+        assert_eq!((stim.picture.width, stim.picture.height), (1, 1));
+        assert_eq!(stim.rgba, vec![10, 20, 30, 255]);
+    }
+    assert_eq!(cache.decode_count(), 1);
+
+    let flat = cache.get_or_decode(&mut wad, "FLOOR4_8").unwrap();
+    assert_eq!(flat.rgba.len(), WAD::FLAT_SIZE * 4);
+    assert_eq!(cache.decode_count(), 2);
+
+    // The flat colors reuse the decoded flat.
+    let mut flat_colors = FlatColors::with_cache(&mut wad, &mut cache).unwrap();
+    assert_eq!(flat_colors.get("FLOOR4_8"), Some(Color::rgb(10, 20, 30)));
+    assert_eq!(cache.decode_count(), 2);
+
+    for _ in 0..2 {
+        let startan = cache.get_or_decode(&mut wad, "STARTAN3").unwrap();
+        assert_eq!(startan.picture.pixels, vec![None, Some(7), None]);
+        assert_eq!(startan.rgba[4..8], [10, 20, 30, 255]);
+    }
+    assert_eq!(cache.decode_count(), 3);
+
+    assert!(matches!(cache.get_or_decode(&mut wad, "MISSING"), Err(WadError::LumpNotFound(_))));
+    assert_eq!(cache.decode_count(), 3);
+}
+
+#[test]
+fn test_map_lumps_after_marker() {
+    // A script lump between each marker and its THINGS.
+    let with_scripts = |name: &str| {
+        let mut lumps = test_map_lumps(name);
+        lumps.insert(1, ("SCRIPTS".to_string(), b"script 1 open { }".to_vec()));
+
+        lumps
+    };
+
+    let mut lumps = with_scripts("MAP01");
+    lumps.retain(|(name, _)| name != "BLOCKMAP");
+    lumps.extend(with_scripts("MAP02"));
+
+    let path = write_test_wad("doom_rs_map_lumps_after_marker.wad", &lumps);
+    let mut map_data = WAD::new(&path).unwrap();
+
+    assert_eq!(map_data.map_names(), vec!["MAP01", "MAP02"]);
+
+    // With fixed offsets SCRIPTS would be read as THINGS and so on.
+    map_data.change_map("MAP01").unwrap();
+    assert_eq!(map_data.things.len(), 1);
+    assert_eq!(map_data.line_defs.len(), 4);
+    assert_eq!(map_data.sectors.len(), 1);
+    assert_eq!(map_data.sectors[0].ceiling_height, 128);
+
+    // MAP02's blockmap isn't taken for MAP01's.
+    assert!(matches!(map_data.map_lump(MapLumpIndex::BlockMap), Err(WadError::LumpNotFound(_))));
+
+    map_data.change_map("MAP02").unwrap();
+    assert_eq!(map_data.things.len(), 1);
+    assert!(map_data.map_lump(MapLumpIndex::BlockMap).is_ok());
 }